@@ -7,7 +7,22 @@ use rustesge::actor::Actor;
 use rustesge::base::BaseGame;
 use rustesge::base;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use rustesge::genesis;
+use rustesge::pathfinding;
+use rustesge::map;
+use rustesge::dialogue;
+use rustesge::behaviour;
+use rustesge::attribute;
+use rustesge::combat;
+use rustesge::inventory;
+use rustesge::quest;
+use rustesge::container;
+use rustesge::thing;
+use rustesge::locale;
+use rustesge::markup;
+use rustesge::prefab;
+use rustesge::response::MessageKind;
 
 pub fn main() {
 	let mut room1 = Room {
@@ -16,7 +31,9 @@ pub fn main() {
 		description: "This is room 1".to_string(),
 		items: Vec::new(),
 		actors: Vec::new(),
-		exits: HashMap::new()
+		exits: HashMap::new(),
+		exit_states: HashMap::new(),
+		exit_keys: HashMap::new()
 	};
 	let mut room2 = Room {
 		id: "room2".to_string(),
@@ -24,15 +41,15 @@ pub fn main() {
 		description: "This is room 2".to_string(),
 		items: Vec::new(),
 		actors: Vec::new(),
-		exits: HashMap::new()
+		exits: HashMap::new(),
+		exit_states: HashMap::new(),
+		exit_keys: HashMap::new()
 	};
 	room1.exits.insert("out".to_string(), "room2".to_string());
 	room2.exits.insert("in".to_string(), "room1".to_string());
-	let lalala = Actor {
-		id: "lalala".to_string(),
-		name: "Lalala".to_string(),
-		description: "Lalala the choco elfin.".to_string()
-	};
+	let lalala = Actor::new("lalala")
+		.with_name("Lalala")
+		.with_description("Lalala the choco elfin.");
 	room1.actors.push("lalala".to_string());
 	let state = BaseGame {
 		player: "lalala".to_string()
@@ -44,24 +61,21 @@ pub fn main() {
 	storage.insert(Box::new(state));
 	storage = genesis::initial_genesis("God");
 	let base_package = base::gen_esge_package();
-	let packages = vec![base_package];
+	let packages = vec![base_package, behaviour::gen_behaviour_package(),
+			attribute::gen_attribute_package(),
+			combat::gen_combat_package(Some("genesis-room".to_string())),
+			quest::gen_quest_package()];
 
 
 	let quit_cmd = Command {
 		keyword: "quit".to_string(),
 		action_fn: Box::new(| _, _ | {
 			Ok(Box::new(| mut ingame, _ | {
-				ingame.append_response("done", "true");
+				ingame.add_text(MessageKind::Done, "");
 				Ok(())
 			}))
 		})
 	};	
-	let look_cmd = Command {
-		keyword: "look".to_string(),
-		action_fn: Box::new(| _, _ | {
-			Ok(base::gen_display_current_room_action())
-		})
-	};
 	let store_cmd = Command {
 		keyword: "store".to_string(),
 		action_fn: Box::new(| _, _ | {
@@ -73,9 +87,9 @@ pub fn main() {
 	};
 	let go_cmd = Command {
 		keyword: "go".to_string(),
-		action_fn: Box::new(| _, keywords | {
+		action_fn: Box::new(| ingame, keywords | {
 			if keywords.len() <= 1 {
-				Err(gerr("Which direction?\n".to_string()))?
+				Err(gerr(ingame.msg("cmd.which_direction", &[])))?
 			} else {
 				Ok(base::gen_move_player_action(keywords[1].trim().to_string()))
 			}
@@ -85,26 +99,67 @@ pub fn main() {
 		keyword: "err".to_string(),
 		action_fn: Box::new(| _, _ | {
 			Ok(Box::new(| mut ingame, _ | {
-				ingame.append_response("err", "Test\n");
+				ingame.add_text(MessageKind::Error, "Test");
 				Ok(())
 			}))
 		})
 	};
 
 	match Ingame::init_packages(storage, packages) {
-		Ok(ingame) => {
-			let mut terminal = Terminal::new(ingame);
+		Ok(mut ingame) => {
+			match locale::load_catalogs("locales") {
+				Ok(catalogs) => for catalog in catalogs {
+					ingame.add_catalog(catalog);
+				},
+				Err(err) => print!("Could not load the languages: {}\n", err)
+			}
+			let mut terminal = Terminal::new(ingame)
+				.with_renderer(Box::new(markup::AnsiRenderer))
+				.with_width(markup::terminal_width());
 			terminal.add_command(quit_cmd);
-			terminal.add_command(look_cmd);
+			terminal.add_verb(container::gen_look_verb("look"));
 			terminal.add_command(go_cmd);
+			terminal.add_command(pathfinding::gen_goto_cmd("goto"));
+			terminal.add_command(pathfinding::gen_path_cmd("path"));
+			terminal.add_command(map::gen_builder_map_cmd("map"));
+			terminal.add_command(dialogue::gen_talk_cmd("talk"));
+			terminal.add_command(dialogue::gen_say_cmd("say"));
+			terminal.add_command(attribute::gen_status_cmd("status"));
+			terminal.add_command(combat::gen_attack_cmd("attack"));
+			terminal.add_verb(inventory::gen_take_verb("take"));
+			terminal.add_verb(inventory::gen_take_verb("get"));
+			terminal.add_verb(inventory::gen_drop_verb("drop"));
+			terminal.add_verb(inventory::gen_use_verb("use"));
+			terminal.add_command(inventory::gen_inventory_cmd("inventory"));
+			terminal.add_command(quest::gen_quests_cmd("quests"));
+			terminal.add_verb(container::gen_put_verb("put"));
+			terminal.add_verb(container::gen_open_verb("open"));
+			terminal.add_verb(container::gen_close_verb("close"));
+			terminal.add_verb(thing::gen_examine_verb("examine"));
+			terminal.add_verb(thing::gen_examine_verb("x"));
 			terminal.add_command(error_cmd);
 			terminal.add_command(store_cmd);
 			terminal.add_command(genesis::gen_exit_cmd("add_exit"));
+			terminal.add_command(genesis::gen_dig_cmd("dig"));
+			terminal.add_command(genesis::gen_lock_exit_cmd("lock_exit"));
+			terminal.add_command(genesis::gen_unlock_exit_cmd("unlock_exit"));
+			terminal.add_command(genesis::gen_close_exit_cmd("close_exit"));
+			terminal.add_command(genesis::gen_hide_exit_cmd("hide_exit"));
+			terminal.add_command(genesis::gen_exit_key_cmd("exit_key"));
+			terminal.add_command(genesis::gen_set_script_cmd("script"));
+			terminal.add_command(genesis::gen_add_rule_cmd("rule"));
+			terminal.add_command(genesis::gen_language_cmd("language"));
 			terminal.add_command(genesis::gen_rename_room_cmd("rename_room"));
 			terminal.add_command(genesis::gen_redescribe_room_cmd("redescribe_room"));
-			terminal.add_command(genesis::save_world_cmd("save".to_string()));
-			terminal.add_command(genesis::load_world_cmd("load".to_string()));
+			terminal.add_command(genesis::save_world_cmd("save".to_string(),
+					PathBuf::from("worlds")));
+			terminal.add_command(genesis::load_world_cmd("load".to_string(),
+					PathBuf::from("worlds")));
 			terminal.add_command(genesis::gen_empty_world_cmd("create_world".to_string()));
+			terminal.add_command(prefab::gen_export_prefab_cmd("save_prefab",
+					PathBuf::from("prefabs")));
+			terminal.add_command(prefab::gen_import_prefab_cmd("build_prefab",
+					PathBuf::from("prefabs")));
 			print!("Running terminal\n");
 			terminal.run();
 		},
@@ -2,6 +2,7 @@ extern crate rustesge;
 use rustesge::core::Ingame;
 use rustesge::terminal::{Terminal};
 use rustesge::terminal::Command;
+use rustesge::response::MessageKind;
 
 pub fn main() {
 	let ingame = Ingame::new("storage".to_string());
@@ -10,7 +11,7 @@ pub fn main() {
 		keyword: "quit".to_string(),
 		action_fn: Box::new(| _, _ | {
 			Ok(Box::new(| mut ingame, _ | {
-				ingame.append_response("done", "true");
+				ingame.add_text(MessageKind::Done, "");
 				Ok(())
 			}))
 		})
@@ -20,7 +21,7 @@ pub fn main() {
 		action_fn: Box::new(| _, keywords | {
 			let keywords = keywords.join(" ").clone();
 			Ok(Box::new(move | mut ingame, _ | { 
-				ingame.append_response("out", &keywords);
+				ingame.add_text(MessageKind::Output, &keywords);
 				Ok(())
 			}))
 		})
@@ -4,8 +4,11 @@
 
 use core;
 use core::{Item, Meta};
+use behaviour::Behaviour;
+use attribute::{Attribute, StatusEffect, attributes_from_item, effects_from_item,
+				merge_attributes_into_metas};
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 
 /// An item which  represents a living creature in the world. 
@@ -16,10 +19,61 @@ pub struct Actor {
 	/// Name of the actor.
 	pub name: String,
 	/// More detailed description of the actor. 
-	pub description: String
+	pub description: String,
+	/// Item identifiers carried by the actor.
+	pub items: Vec<String>,
+	/// Dialogue node the actor starts a conversation with.
+	pub dialogue: Option<String>,
+	/// What the actor does on its own.
+	pub behaviour: Option<Behaviour>,
+	/// Numeric attributes like health or strength.
+	pub attributes: BTreeMap<String, Attribute>,
+	/// Active status effects which modify the attributes.
+	pub effects: Vec<StatusEffect>
+}
+
+impl Actor {
+	/// Add a new actor with the given id.
+	pub fn new<S>(id: S) -> Self
+				where String: From<S> {
+		Actor {
+			id: String::from(id),
+			name: "".to_string(),
+			description: "".to_string(),
+			items: Vec::new(),
+			dialogue: None,
+			behaviour: None,
+			attributes: BTreeMap::new(),
+			effects: Vec::new()
+		}
+	}
+
+	/// Consumes the actor and returns new one with the given name.
+	///
+	/// Use for initialization.
+	pub fn with_name<S: Into<String>>(mut self, name: S) -> Self {
+		self.name = name.into();
+		self
+	}
+
+	/// Consumes the actor and returns new one with the given description.
+	///
+	/// Use for initialization.
+	pub fn with_description<S: Into<String>>(mut self, description: S) -> Self {
+		self.description = description.into();
+		self
+	}
+
+	/// Check if the actor carries the item with the given id.
+	pub fn carries(&self, item_id: &str) -> bool {
+		self.items.iter().any(|x| x == item_id)
+	}
 }
 
 impl core::Itemizeable for Actor {
+	fn item_type() -> Option<&'static str> {
+		Some("actor")
+	}
 	fn from_item(item: &Item) -> Option<Box<Self>> {
 
 		if item.item_type != "actor" {
@@ -27,10 +81,22 @@ impl core::Itemizeable for Actor {
 		} else {
 			let name = item.meta_text_or_default("name", "").to_string();
 			let desc = item.meta_text_or_default("desc", "").to_string();	
+			let items: Vec<String> = 
+				item.meta_textvec_or_default("items", &[])
+						.iter().map(|x| x.to_string()).collect();
+			let dialogue = match item.meta_text_or_default("dialogue", "") {
+				"" => None,
+				dialogue => Some(dialogue.to_string())
+			};
 			Some(Box::new(Actor {
 				id: item.item_id.clone(),
 				name: name,
-				description: desc
+				description: desc,
+				items: items,
+				dialogue: dialogue,
+				behaviour: Behaviour::from_metas(item),
+				attributes: attributes_from_item(item),
+				effects: effects_from_item(item)
 			}))
 		}
 	}
@@ -38,7 +104,7 @@ impl core::Itemizeable for Actor {
 		let mut item = Item {
 			item_id: self.id.clone(),
 			item_type: "actor".to_string(),
-			item_meta: HashMap::new()
+			item_meta: BTreeMap::new()
 		};
 		self.merge_into_item(&mut item);
 		item
@@ -47,6 +113,11 @@ impl core::Itemizeable for Actor {
 		let metas = &mut item.item_meta;
 		metas.insert("name".to_string(), Meta::Text(self.name.clone()));
 		metas.insert("desc".to_string(), Meta::Text(self.description.clone()));
+		metas.insert("items".to_string(), Meta::TextVec(self.items.clone()));
+		metas.insert("dialogue".to_string(), Meta::Text(
+				self.dialogue.clone().unwrap_or("".to_string())));
+		Behaviour::merge_into_metas(self.behaviour.as_ref(), metas);
+		merge_attributes_into_metas(&self.attributes, &self.effects, metas);
 	}
 	fn get_id(&self) -> &str {
 		&self.id
@@ -0,0 +1,301 @@
+#![warn(missing_docs)]
+
+//! Numeric attributes of actors like health or strength and status effects
+//! which modify them for a limited number of steps.
+
+use core::{MutIngame, Item, Meta, Action, GameResult,
+				serialize_hashmap, deserialize_hashmap, serialize_vec,
+				deserialize_vec};
+use response::MessageKind;
+use actor::Actor;
+use base::EsgePackage;
+use terminal::Command;
+
+use std::collections::{BTreeMap, HashMap};
+use std::cmp::{min, max};
+
+/// A number with lower and upper bound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attribute {
+	/// The base value without status effects.
+	pub value: i32,
+	/// The lowest possible value.
+	pub min: i32,
+	/// The highest possible value.
+	pub max: i32
+}
+
+/// Modifies an attribute for a limited number of steps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusEffect {
+	/// Name of the effect like "poisoned".
+	pub name: String,
+	/// Name of the modified attribute.
+	pub attribute: String,
+	/// Added to the attribute while the effect is active.
+	pub modifier: i32,
+	/// Number of steps until the effect expires.
+	pub remaining: u32
+}
+
+impl Attribute {
+	/// Create an attribute with the given value and bounds.
+	///
+	/// The value is clamped to the bounds.
+	pub fn new(value: i32, min_value: i32, max_value: i32) -> Self {
+		Attribute {
+			value: max(min_value, min(max_value, value)),
+			min: min_value,
+			max: max_value
+		}
+	}
+
+	/// Clamp a value to the bounds of the attribute.
+	pub fn clamp(&self, value: i32) -> i32 {
+		max(self.min, min(self.max, value))
+	}
+}
+
+impl StatusEffect {
+	/// Create an effect which modifies the attribute for the given steps.
+	pub fn new<S: Into<String>>(name: S, attribute: S, modifier: i32,
+								remaining: u32) -> Self {
+		StatusEffect {
+			name: name.into(),
+			attribute: attribute.into(),
+			modifier: modifier,
+			remaining: remaining
+		}
+	}
+}
+
+impl Actor {
+	/// Get the value of an attribute including the status effects.
+	///
+	/// Returns None if the actor doesn't have the attribute.
+	pub fn attribute(&self, name: &str) -> Option<i32> {
+		self.attributes.get(name).map(|attribute| {
+			let modifier: i32 = self.effects.iter()
+				.filter(|effect| effect.attribute == name)
+				.map(|effect| effect.modifier)
+				.sum();
+			attribute.clamp(attribute.value + modifier)
+		})
+	}
+
+	/// Add or replace an attribute.
+	pub fn add_attribute<S: Into<String>>(&mut self, name: S, value: i32,
+										  min_value: i32, max_value: i32) {
+		self.attributes.insert(name.into(),
+				Attribute::new(value, min_value, max_value));
+	}
+
+	/// Consumes the actor and returns new one with the given attribute.
+	///
+	/// Use for initialization.
+	pub fn with_attribute<S: Into<String>>(mut self, name: S, value: i32,
+										   min_value: i32, max_value: i32) -> Self {
+		self.add_attribute(name, value, min_value, max_value);
+		self
+	}
+
+	/// Set the base value of an attribute, clamped to its bounds.
+	///
+	/// Returns false if the actor doesn't have the attribute.
+	pub fn set_attribute(&mut self, name: &str, value: i32) -> bool {
+		match self.attributes.get_mut(name) {
+			Some(attribute) => {
+				attribute.value = attribute.clamp(value);
+				true
+			},
+			None => false
+		}
+	}
+
+	/// Add the difference to the base value of an attribute.
+	///
+	/// Returns false if the actor doesn't have the attribute.
+	pub fn change_attribute(&mut self, name: &str, difference: i32) -> bool {
+		match self.attributes.get(name).map(|attribute| attribute.value) {
+			Some(value) => self.set_attribute(name, value + difference),
+			None => false
+		}
+	}
+
+	/// Add a status effect.
+	pub fn add_effect(&mut self, effect: StatusEffect) {
+		self.effects.push(effect);
+	}
+
+	/// Count down the status effects by one step.
+	///
+	/// Returns the effects which expired.
+	pub fn expire_effects(&mut self) -> Vec<StatusEffect> {
+		for effect in self.effects.iter_mut() {
+			effect.remaining = effect.remaining.saturating_sub(1);
+		}
+		let (expired, active) = self.effects.drain(..)
+			.partition(|effect| effect.remaining == 0);
+		self.effects = active;
+		expired
+	}
+}
+
+/// Read the attributes from the metas of an actor item.
+pub fn attributes_from_item(item: &Item) -> BTreeMap<String, Attribute> {
+	deserialize_hashmap(item.meta_text_or_default("attributes", "{}"))
+		.into_iter()
+		.filter_map(|(name, value)| {
+			let values: Vec<i32> = deserialize_vec(&value).iter()
+				.filter_map(|x| x.parse::<i32>().ok())
+				.collect();
+			if values.len() == 3 {
+				Some((name, Attribute::new(values[0], values[1], values[2])))
+			} else {
+				None
+			}
+		})
+		.collect()
+}
+
+/// Read the status effects from the metas of an actor item.
+pub fn effects_from_item(item: &Item) -> Vec<StatusEffect> {
+	item.meta_textvec_or_default("effects", &[]).iter()
+		.filter_map(|effect| {
+			let parts = deserialize_vec(effect);
+			if parts.len() != 4 {
+				return None
+			}
+			let modifier = parts[2].parse::<i32>().ok();
+			let remaining = parts[3].parse::<u32>().ok();
+			match (modifier, remaining) {
+				(Some(modifier), Some(remaining)) => Some(StatusEffect::new(
+						parts[0].clone(), parts[1].clone(), modifier, remaining)),
+				_ => None
+			}
+		})
+		.collect()
+}
+
+/// Write the attributes and status effects to the metas of an actor item.
+pub fn merge_attributes_into_metas(attributes: &BTreeMap<String, Attribute>,
+								   effects: &[StatusEffect],
+								   metas: &mut BTreeMap<String, Meta>) {
+	let attributes: HashMap<String, String> = attributes.iter()
+		.map(|(name, attribute)| (name.clone(), serialize_vec(&vec![
+			attribute.value.to_string(),
+			attribute.min.to_string(),
+			attribute.max.to_string()])))
+		.collect();
+	let effects: Vec<String> = effects.iter()
+		.map(|effect| serialize_vec(&vec![
+			effect.name.clone(),
+			effect.attribute.clone(),
+			effect.modifier.to_string(),
+			effect.remaining.to_string()]))
+		.collect();
+	metas.insert("attributes".to_string(), Meta::Text(serialize_hashmap(&attributes)));
+	metas.insert("effects".to_string(), Meta::TextVec(effects));
+}
+
+impl<'a> MutIngame<'a> {
+	/// Count down the status effects of all actors.
+	///
+	/// Tells the player about own expired effects.
+	pub fn expire_effects(&mut self) -> GameResult<()> {
+		let player_id = self.ingame.get_player().map(|x| x.id).unwrap_or(String::new());
+		let mut actors: Vec<Box<Actor>> = self.ingame.all_of_type::<Actor>()
+			.filter(|actor| !actor.effects.is_empty())
+			.collect();
+		actors.sort_by(|a, b| a.id.cmp(&b.id));
+		for mut actor in actors {
+			let expired = actor.expire_effects();
+			if actor.id == player_id {
+				for effect in &expired {
+					let msg = self.ingame.msg("attribute.effect_ended",
+							&[("effect", &effect.name)]);
+					self.add_message(MessageKind::Output, &actor.id, &msg);
+				}
+			}
+			self.insert_item(actor);
+		}
+		Ok(())
+	}
+
+	/// Print the attributes and effects of the player to out.
+	pub fn display_player_status(&mut self) -> GameResult<()> {
+		let player = self.ingame.get_player()?;
+		let msg = self.ingame.msg("attribute.status", &[("actor", &player.name)]);
+		self.add_message(MessageKind::Output, &player.id, &msg);
+		for (name, attribute) in &player.attributes {
+			let value = player.attribute(name).unwrap_or(attribute.value);
+			let msg = self.ingame.msg("attribute.value", &[("attribute", name),
+					("value", &value.to_string()), ("min", &attribute.min.to_string()),
+					("max", &attribute.max.to_string())]);
+			self.add_message(MessageKind::Output, &player.id, &msg);
+		}
+		for effect in &player.effects {
+			let msg = self.ingame.msg("attribute.effect", &[("effect", &effect.name),
+					("attribute", &effect.attribute),
+					("modifier", &format!("{:+}", effect.modifier)),
+					("steps", &effect.remaining.to_string())]);
+			self.add_message(MessageKind::Output, &player.id, &msg);
+		}
+		Ok(())
+	}
+}
+
+/// Create the attribute package.
+///
+/// Its init action stays registered and lets status effects expire.
+pub fn gen_attribute_package() -> EsgePackage {
+	EsgePackage::new(Box::new(|ingame, _| ingame.expire_effects()),
+			Box::new(|_| None))
+}
+
+/// Creates an Action which prints the status of the player.
+pub fn gen_status_action() -> Action {
+	Box::new(|ingame, _| ingame.display_player_status())
+}
+
+/// Command which prints the status of the player.
+pub fn gen_status_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|_, _ | Ok(gen_status_action()))
+	}
+}
+
+#[test]
+fn attribute_test() {
+	use core::{Ingame, Storage, Itemizeable};
+	use room::Room;
+	use base::BaseGame;
+	let mut player = Actor::new("player").with_name("Player")
+		.with_attribute("health", 15, 0, 20)
+		.with_attribute("strength", 5, 0, 10);
+	assert!(player.change_attribute("health", 10));
+	assert_eq!(Some(20), player.attribute("health"));
+	player.add_effect(StatusEffect::new("weak", "strength", -3, 2));
+	assert_eq!(Some(2), player.attribute("strength"));
+	let item = player.to_item();
+	let stored = Actor::from_item(&item).unwrap();
+	assert_eq!(player.attributes, stored.attributes);
+	assert_eq!(player.effects, stored.effects);
+
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	let storage = Storage::new("storage")
+		.with_item(player)
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room);
+	let mut ingame = Ingame::init_packages(storage,
+			vec![gen_attribute_package()]).unwrap();
+	ingame.add_one_time_action(gen_status_action());
+	ingame.step();
+	assert_eq!("Status of Player\nhealth: 20 (0..20)\nstrength: 2 (0..10)\n\
+			weak: strength -3 for 1 steps", ingame.response().text(&MessageKind::Output));
+	ingame.step();
+	assert_eq!("You are no longer weak.", ingame.response().text(&MessageKind::Output));
+	assert_eq!(Some(5), ingame.get_player().unwrap().attribute("strength"));
+}
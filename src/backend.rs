@@ -0,0 +1,451 @@
+#![warn(missing_docs)]
+
+//! Places where the items of a world are kept.
+//!
+//! A `Backend` loads and stores single items.  The `Storage` keeps them in
+//! memory and the `DirBackend` of the worlddir module keeps one file per
+//! item on disk.
+//!
+//! `CachedStorage` sits on top of a backend.  It loads items when they are
+//! asked for and keeps changes in memory until they are flushed, so worlds
+//! which don't fit into memory can be generated and edited piece by piece.
+//! Only a limited number of items stay in memory, the least recently used
+//! items which are not changed are dropped first.  The `Ingame` keeps its
+//! items in a `CachedStorage`, see `Ingame::with_backend`.
+//!
+//! Backends index the items by type and the actors by the rooms which hold
+//! them, so looking them up doesn't read the whole world.  A `Storage` is
+//! already in memory, the `CachedStorage` passes its changes through
+//! instead of keeping copies of the items.
+
+use core::{Storage, Item, Itemizeable, GameResult};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Loads and stores the items of a world.
+pub trait Backend {
+	/// Get the ID of the storage.
+	fn id(&self) -> &str;
+
+	/// Get the ids of all items in order.
+	fn ids(&self) -> GameResult<Vec<String>>;
+
+	/// Load an item, None if there is no item with the id.
+	fn load(&self, item_id: &str) -> GameResult<Option<Item>>;
+
+	/// Store an item and replace the item with the same id.
+	fn store(&mut self, item: &Item) -> GameResult<()>;
+
+	/// Remove an item if there is one.
+	fn remove(&mut self, item_id: &str) -> GameResult<()>;
+
+	/// Get the ids of all items of the type in order.
+	fn ids_of_type(&self, item_type: &str) -> GameResult<Vec<String>>;
+
+	/// Get the id of the room which holds the actor, None if there is none.
+	fn room_of_actor(&self, actor_id: &str) -> GameResult<Option<String>>;
+
+	/// Check if the items are in memory already, then they are not cached.
+	fn is_in_memory(&self) -> bool {
+		false
+	}
+}
+
+/// Check if the item is a room which holds the actor.
+fn holds_actor(item: &Item, actor_id: &str) -> bool {
+	item.item_type == "room"
+		&& item.meta_textvec_or_default("actors", &[]).iter().any(|x| x == actor_id)
+}
+
+impl Backend for Storage {
+	fn id(&self) -> &str {
+		Storage::id(self)
+	}
+	fn ids(&self) -> GameResult<Vec<String>> {
+		Ok(Storage::ids(self).map(|x| x.to_string()).collect())
+	}
+	fn load(&self, item_id: &str) -> GameResult<Option<Item>> {
+		Ok(self.get_item::<Item>(item_id).map(|item| *item))
+	}
+	fn store(&mut self, item: &Item) -> GameResult<()> {
+		self.remove_item(&item.item_id);
+		self.insert(Box::new(item.clone()));
+		Ok(())
+	}
+	fn remove(&mut self, item_id: &str) -> GameResult<()> {
+		self.remove_item(item_id);
+		Ok(())
+	}
+	fn ids_of_type(&self, item_type: &str) -> GameResult<Vec<String>> {
+		Ok(self.items_of_type(item_type).map(|x| x.item_id.clone()).collect())
+	}
+	fn room_of_actor(&self, actor_id: &str) -> GameResult<Option<String>> {
+		Ok(self.items_of_type("room")
+			.find(|room| holds_actor(room, actor_id))
+			.map(|room| room.item_id.clone()))
+	}
+	fn is_in_memory(&self) -> bool {
+		true
+	}
+}
+
+/// Number of items a `CachedStorage` keeps in memory by default.
+pub const DEFAULT_CAPACITY: usize = 10000;
+
+/// Items in memory with the order in which they were used.
+struct Cache {
+	items: BTreeMap<String, (u64, Item)>,
+	order: BTreeMap<u64, String>,
+	clock: u64
+}
+
+impl Cache {
+	fn new() -> Self {
+		Cache {
+			items: BTreeMap::new(),
+			order: BTreeMap::new(),
+			clock: 0
+		}
+	}
+
+	/// Get a copy of an item and mark it as used.
+	fn get(&mut self, item_id: &str) -> Option<Item> {
+		let clock = self.clock + 1;
+		match self.items.get_mut(item_id) {
+			Some(&mut (ref mut used, ref item)) => {
+				self.order.remove(used);
+				self.order.insert(clock, item_id.to_string());
+				*used = clock;
+				self.clock = clock;
+				Some(item.clone())
+			},
+			None => None
+		}
+	}
+
+	fn insert(&mut self, item_id: &str, item: Item) {
+		self.remove(item_id);
+		self.clock += 1;
+		self.order.insert(self.clock, item_id.to_string());
+		self.items.insert(item_id.to_string(), (self.clock, item));
+	}
+
+	fn remove(&mut self, item_id: &str) {
+		if let Some((used, _)) = self.items.remove(item_id) {
+			self.order.remove(&used);
+		}
+	}
+
+	/// Drop the least recently used items until at most capacity are left.
+	///
+	/// Items which are dirty are kept.
+	fn evict(&mut self, capacity: usize, dirty: &BTreeSet<String>) {
+		if self.items.len() <= capacity {
+			return
+		}
+		let evicted: Vec<String> = self.order.values()
+			.filter(|item_id| !dirty.contains(*item_id))
+			.take(self.items.len() - capacity)
+			.cloned()
+			.collect();
+		for item_id in evicted {
+			self.remove(&item_id);
+		}
+	}
+
+	fn clear(&mut self) {
+		self.items.clear();
+		self.order.clear();
+	}
+}
+
+/// Loads the items of a backend on demand and writes back changed items.
+pub struct CachedStorage {
+	backend: Box<Backend>,
+	cache: RefCell<Cache>,
+	capacity: usize,
+	dirty: BTreeSet<String>,
+	removed: BTreeSet<String>
+}
+
+impl CachedStorage {
+	/// Create a cache without loaded items which keeps up to
+	/// `DEFAULT_CAPACITY` items in memory.
+	pub fn new(backend: Box<Backend>) -> Self {
+		CachedStorage {
+			backend: backend,
+			cache: RefCell::new(Cache::new()),
+			capacity: DEFAULT_CAPACITY,
+			dirty: BTreeSet::new(),
+			removed: BTreeSet::new()
+		}
+	}
+
+	/// Set the number of items which are kept in memory.
+	///
+	/// Changed items stay in memory until they are flushed, even if there
+	/// are more of them.
+	pub fn with_capacity(mut self, capacity: usize) -> Self {
+		self.capacity = capacity;
+		self.cache.borrow_mut().evict(capacity, &self.dirty);
+		self
+	}
+
+	/// Get the ID of the storage.
+	pub fn id(&self) -> &str {
+		self.backend.id()
+	}
+
+	fn load(&self, item_id: &str) -> GameResult<Option<Item>> {
+		if self.backend.is_in_memory() {
+			return self.backend.load(item_id)
+		}
+		if self.removed.contains(item_id) {
+			return Ok(None)
+		}
+		if let Some(item) = self.cache.borrow_mut().get(item_id) {
+			return Ok(Some(item))
+		}
+		let item = self.backend.load(item_id)?;
+		if let Some(ref item) = item {
+			let mut cache = self.cache.borrow_mut();
+			cache.insert(item_id, item.clone());
+			cache.evict(self.capacity, &self.dirty);
+		}
+		Ok(item)
+	}
+
+	/// Get an item, it is loaded from the backend if it's not cached.
+	///
+	/// Returns None if the item was not found or if it cannot be converted
+	/// to T.
+	pub fn get_item<T: Itemizeable>(&self, item_id: &str) -> GameResult<Option<Box<T>>> {
+		Ok(self.load(item_id)?.and_then(|item| T::from_item(&item)))
+	}
+
+	/// Insert an item like `Storage::insert`, it is written on flush.
+	pub fn insert<T: Itemizeable>(&mut self, item: Box<T>) -> GameResult<()> {
+		let item_id = item.get_id().to_string();
+		let item = match self.load(&item_id)? {
+			Some(mut stored_item) => {
+				item.merge_into_item(&mut stored_item);
+				stored_item
+			},
+			None => item.to_item()
+		};
+		if self.backend.is_in_memory() {
+			return self.backend.store(&item)
+		}
+		self.cache.borrow_mut().insert(&item_id, item);
+		self.removed.remove(&item_id);
+		self.dirty.insert(item_id);
+		self.cache.borrow_mut().evict(self.capacity, &self.dirty);
+		Ok(())
+	}
+
+	/// Remove an item, it is removed from the backend on flush.
+	pub fn remove_item(&mut self, item_id: &str) {
+		if self.backend.is_in_memory() && self.backend.remove(item_id).is_ok() {
+			return
+		}
+		self.cache.borrow_mut().remove(item_id);
+		self.dirty.remove(item_id);
+		self.removed.insert(item_id.to_string());
+	}
+
+	/// Get the ids of all items including the changes which are not flushed.
+	pub fn ids(&self) -> GameResult<Vec<String>> {
+		let mut ids: BTreeSet<String> = self.backend.ids()?.into_iter()
+			.filter(|id| !self.removed.contains(id))
+			.collect();
+		ids.extend(self.dirty.iter().cloned());
+		Ok(ids.into_iter().collect())
+	}
+
+	/// Get the ids of all items of the type like `ids`.
+	pub fn ids_of_type(&self, item_type: &str) -> GameResult<Vec<String>> {
+		let cache = self.cache.borrow();
+		let mut ids: BTreeSet<String> = self.backend.ids_of_type(item_type)?.into_iter()
+			.filter(|id| !self.removed.contains(id) && !self.dirty.contains(id))
+			.collect();
+		ids.extend(self.dirty.iter()
+			.filter(|id| cache.items.get(*id)
+				.map(|&(_, ref item)| item.item_type == item_type)
+				.unwrap_or(false))
+			.cloned());
+		Ok(ids.into_iter().collect())
+	}
+
+	/// Get the id of the room which holds the actor.
+	///
+	/// Changed rooms are looked at first, the others are found by the
+	/// index of the backend.
+	pub fn room_of_actor(&self, actor_id: &str) -> GameResult<Option<String>> {
+		{
+			let cache = self.cache.borrow();
+			let changed = self.dirty.iter()
+				.find(|id| cache.items.get(*id)
+					.map(|&(_, ref item)| holds_actor(item, actor_id))
+					.unwrap_or(false));
+			if let Some(room_id) = changed {
+				return Ok(Some(room_id.clone()))
+			}
+		}
+		Ok(self.backend.room_of_actor(actor_id)?
+			.filter(|id| !self.removed.contains(id) && !self.dirty.contains(id)))
+	}
+
+	/// Get the number of items in memory.
+	pub fn cached(&self) -> usize {
+		self.cache.borrow().items.len()
+	}
+
+	/// Check if there are changes which are not flushed.
+	pub fn is_dirty(&self) -> bool {
+		!self.dirty.is_empty() || !self.removed.is_empty()
+	}
+
+	/// Write the changed items to the backend.
+	pub fn flush(&mut self) -> GameResult<()> {
+		for item_id in self.removed.iter() {
+			self.backend.remove(item_id)?;
+		}
+		self.removed.clear();
+		for item_id in self.dirty.iter() {
+			if let Some(&(_, ref item)) = self.cache.borrow().items.get(item_id) {
+				self.backend.store(item)?;
+			}
+		}
+		self.dirty.clear();
+		self.cache.borrow_mut().evict(self.capacity, &self.dirty);
+		Ok(())
+	}
+
+	/// Flush and free the memory of all cached items.
+	pub fn unload(&mut self) -> GameResult<()> {
+		self.flush()?;
+		self.cache.borrow_mut().clear();
+		Ok(())
+	}
+
+	/// Replace all items by the ones of the storage.
+	///
+	/// A backend in memory is replaced by the storage including its id.
+	/// Other backends keep their id, the items are written to them before
+	/// more than the capacity are in memory.
+	pub fn replace_all(&mut self, mut storage: Storage) -> GameResult<()> {
+		if self.backend.is_in_memory() {
+			self.backend = Box::new(storage);
+			self.dirty.clear();
+			self.removed.clear();
+			self.cache.borrow_mut().clear();
+			return Ok(())
+		}
+		for item_id in self.ids()? {
+			self.remove_item(&item_id);
+		}
+		let item_ids: Vec<String> = storage.ids().map(|x| x.to_string()).collect();
+		for item_id in item_ids {
+			if let Some(item) = storage.remove_item(&item_id) {
+				self.insert(Box::new(item))?;
+			}
+			if self.dirty.len() >= self.capacity {
+				self.flush()?;
+			}
+		}
+		self.flush()
+	}
+
+	/// Load all items into a `Storage`, for example to start an `Ingame`.
+	pub fn to_storage(&self) -> GameResult<Storage> {
+		let mut storage = Storage::new(self.id());
+		for item_id in self.ids()? {
+			if let Some(item) = self.load(&item_id)? {
+				storage.insert(Box::new(item));
+			}
+		}
+		Ok(storage)
+	}
+}
+
+#[test]
+fn backend_test() {
+	use std::env;
+	use std::fs;
+	use core::{Ingame, MutIngame};
+	use room::Room;
+	use worlddir::{DirBackend, save_world_dir};
+	let mut memory = CachedStorage::new(Box::new(Storage::new("memory")));
+	memory.insert(Box::new(Room::new("hall").with_name("Hall"))).unwrap();
+	assert_eq!(vec!["hall".to_string()], memory.ids().unwrap());
+	assert!(!memory.is_dirty());
+	assert_eq!(0, memory.cached());
+	assert_eq!("memory", memory.to_storage().unwrap().id());
+
+	let dir = env::temp_dir().join("rustesge-backend-test");
+	let _ = fs::remove_dir_all(&dir);
+	let mut hall = Room::new("hall").with_name("Hall");
+	hall.actors.push("guard".to_string());
+	let storage = Storage::new("castle")
+		.with_item(hall)
+		.with_item(Room::new("odd id"));
+	save_world_dir(&storage, &dir).unwrap();
+	let mut cached = CachedStorage::new(Box::new(DirBackend::open(&dir).unwrap()));
+	assert_eq!("castle", cached.id());
+	assert_eq!(vec!["hall".to_string(), "odd id".to_string()], cached.ids().unwrap());
+	assert_eq!(0, cached.cached());
+	assert_eq!("Hall", cached.get_item::<Room>("hall").unwrap().unwrap().name);
+	assert_eq!(1, cached.cached());
+	assert!(cached.get_item::<Room>("nothing").unwrap().is_none());
+	assert_eq!(Some("hall".to_string()), cached.room_of_actor("guard").unwrap());
+	assert_eq!(2, cached.ids_of_type("room").unwrap().len());
+	assert!(cached.ids_of_type("actor").unwrap().is_empty());
+
+	cached.insert(Box::new(Room::new("hall").with_name("Great Hall"))).unwrap();
+	let mut cellar = Room::new("cellar");
+	cellar.actors.push("guard".to_string());
+	cached.insert(Box::new(cellar)).unwrap();
+	cached.remove_item("odd id");
+	assert_eq!(Some("cellar".to_string()), cached.room_of_actor("guard").unwrap());
+	assert_eq!(vec!["cellar".to_string(), "hall".to_string()],
+			cached.ids_of_type("room").unwrap());
+	assert!(!dir.join("room").join("cellar.json").exists());
+	assert!(dir.join("room").join("odd%20id.json").exists());
+	cached.unload().unwrap();
+	assert_eq!(0, cached.cached());
+	assert!(dir.join("room").join("cellar.json").exists());
+	assert!(!dir.join("room").join("odd%20id.json").exists());
+
+	let reopened = CachedStorage::new(Box::new(DirBackend::open(&dir).unwrap()))
+		.with_capacity(1);
+	assert_eq!("Great Hall", reopened.get_item::<Room>("hall").unwrap().unwrap().name);
+	let loaded = reopened.to_storage().unwrap();
+	assert_eq!(vec!["cellar", "hall"], loaded.ids().collect::<Vec<&str>>());
+	assert_eq!(1, reopened.cached());
+
+	let mut ingame = Ingame::with_cache(CachedStorage::new(
+			Box::new(DirBackend::open(&dir).unwrap())).with_capacity(1));
+	assert_eq!(2, ingame.all_of_type::<Room>().count());
+	{
+		let mut mut_ingame = MutIngame { ingame: &mut ingame };
+		mut_ingame.insert_item(Box::new(Room::new("attic")));
+		mut_ingame.insert_item(Box::new(Room::new("cellar").with_name("Cellar")));
+	}
+	assert!(!dir.join("room").join("attic.json").exists());
+	ingame.flush().unwrap();
+	assert!(dir.join("room").join("attic.json").exists());
+	assert_eq!(3, ingame.storage().unwrap().ids().count());
+
+	let json = Ingame::with_storage(Storage::new("save")
+		.with_item(Room::new("hall").with_name("Saved Hall"))).serialize().unwrap();
+	ingame.from_json(&json).unwrap();
+	assert_eq!("castle", ingame.storage().unwrap().id());
+	assert_eq!(vec!["hall"], ingame.storage().unwrap().ids().collect::<Vec<&str>>());
+	assert!(!dir.join("room").join("attic.json").exists());
+	assert_eq!("Saved Hall", DirBackend::open(&dir).unwrap().load("hall").unwrap().unwrap()
+		.meta_text_or_default("name", ""));
+
+	fs::copy(dir.join("room").join("hall.json"), dir.join("room").join("cellar.json")).unwrap();
+	let err = DirBackend::open(&dir).unwrap().load("cellar").unwrap_err();
+	assert!(err.to_string().ends_with("Expected item cellar but found hall"));
+}
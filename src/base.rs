@@ -7,8 +7,11 @@ use core;
 use core::{Ingame, MutIngame, GameResult, GameError, Itemizeable, Meta, 
 				gerr, berr};
 use core::Item;
+use response::MessageKind;
 use actor::Actor;
-use room::Room;
+use room::{Room, ExitState};
+use script;
+use markup::{Style, styled, escape};
 
 use std::result::Result;
 
@@ -18,23 +21,70 @@ pub type PlausabilityCheck = Box<Fn(&Ingame) -> Option<String>>;
 /// A package which can be used to initialize the game engine.
 pub struct EsgePackage {
 	init_action: core::Action,
+	final_action: Option<core::Action>,
 	plausability_check: PlausabilityCheck
 }
 
+impl EsgePackage {
+	/// Create a package from its init action and plausability check.
+	///
+	/// The init action is added as regular action and runs in every step.
+	pub fn new(init_action: core::Action, 
+			   plausability_check: PlausabilityCheck) -> Self {
+		EsgePackage {
+			init_action: init_action,
+			final_action: None,
+			plausability_check: plausability_check
+		}
+	}
+
+	/// Consumes the package and returns new one with an action which runs
+	/// at the end of every step.
+	///
+	/// Use for initialization.
+	pub fn with_final_action(mut self, final_action: core::Action) -> Self {
+		self.final_action = Some(final_action);
+		self
+	}
+}
+
 impl Ingame {
 	/// Get the room of the actor given actor.
 	///
 	/// # Failure
 	/// Returns an error if a room was not found.
 	pub fn room_of_actor(&self, actor: &Actor) -> GameResult<Box<Room>> {
-		let id = &actor.id;
-		let rooms = self.all_of_type::<Room>();
-		for room in rooms {
-			if room.actors.contains(id) {
-				return Ok(room)
+		self.room_id_of_actor(&actor.id)
+			.and_then(|room_id| self.get_item::<Room>(&room_id))
+			.ok_or(berr(self.msg("actor.no_room", &[("actor", &actor.name)])))
+	}
+
+	/// Check if the actor can pass the exit of the room.
+	///
+	/// Returns the id of the room behind the exit.
+	///
+	/// # Failure
+	/// Error if the exit is not in the room, if it's hidden or closed or if
+	/// it's locked and the actor does not carry the key.
+	pub fn check_exit(&self, actor: &Actor, room: &Room, 
+					  exit_name: &str) -> GameResult<String> {
+		let dest_room_name = room.exits.get(exit_name)
+				.ok_or(gerr(self.msg("exit.not_found", &[])))?;
+		match room.exit_state(exit_name) {
+			ExitState::Open => (),
+			ExitState::Hidden => Err(gerr(self.msg("exit.not_found", &[])))?,
+			ExitState::Closed => 
+				Err(gerr(self.msg("exit.closed", &[("exit", exit_name)])))?,
+			ExitState::Locked => {
+				let has_key = room.exit_key(exit_name)
+					.map(|key| actor.carries(key))
+					.unwrap_or(false);
+				if !has_key {
+					Err(gerr(self.msg("exit.locked", &[("exit", exit_name)])))?
+				}
 			}
 		}
-		Err(berr("Room not found"))
+		Ok(dest_room_name.clone())
 	}
 
 	/// Get a tuple of exit names and the rooms behind them. 
@@ -81,6 +131,9 @@ impl Ingame {
 				return Err(result.unwrap())
 			}
 			ingame.add_action(package.init_action);
+			if let Some(final_action) = package.final_action {
+				ingame.add_final_action(final_action);
+			}
 		}
 		Ok(ingame)
 	}
@@ -89,9 +142,9 @@ impl Ingame {
 	/// Return the player of the ingme.
 	pub fn get_player(&self) -> GameResult<Box<Actor>> {
 		let base_game = self.get_item::<BaseGame>("base_game")
-				.ok_or(GameError::new("Could not find base_game"))?;
+				.ok_or(GameError::new(self.msg("game.no_player", &[])))?;
 		self.get_item::<Actor>(&base_game.player)
-				.ok_or(berr(format!("Player not found: {}", base_game.player)))
+				.ok_or(berr(self.msg("actor.not_found", &[("actor", &base_game.player)])))
 	}
 
 	/// Get the room which holds the player.
@@ -132,46 +185,109 @@ impl<'a> MutIngame<'a> {
 
 	/// Move an actor through a exit to another room.
 	///
+	/// Runs the `on_exit:<exit>` script of the room, which can cancel the
+	/// move, and the `on_enter` script of the destination room.
+	///
 	/// # Failure
-	/// Error if the room behind the exit was not found and if the exit is not
-	/// in the room.
+	/// Error if the room behind the exit was not found, if the exit is not
+	/// in the room or if the actor is not allowed to pass the exit.
 	pub fn move_actor(&mut self, 
 					  actor: &Actor, 
 					  exit_name: &str) -> GameResult<()> {
 		let actor_room = self.ingame.room_of_actor(actor)?;
-		let dest_room_name = actor_room.exits.get(exit_name)
-				.ok_or(gerr("Could not find exit"))?;
-		let dest_room: Box<Room> = self.get_item(dest_room_name)
-				.ok_or(gerr("Could not get the destination room"))?;
+		let dest_room_name = self.ingame.check_exit(actor, &actor_room, exit_name)?;
+		let dest_room: Box<Room> = self.get_item(&dest_room_name)
+				.ok_or(gerr(self.ingame.msg("room.not_found", &[("room", &dest_room_name)])))?;
+		if !self.run_exit_triggers(actor, &actor_room, exit_name)? {
+			return Ok(())
+		}
 		self.warp_actor(actor, dest_room.clone());
 		self.display_room(dest_room);
+		self.run_enter_triggers(actor, &dest_room_name)?;
+		Ok(())
+	}
+
+	/// Run the script of the exit before the actor passes it.
+	///
+	/// Returns false if the script cancelled the passage.
+	fn run_exit_triggers(&mut self, actor: &Actor, room: &Room, 
+						 exit_name: &str) -> GameResult<bool> {
+		let vars = [("actor", actor.id.as_str()), ("room", room.id.as_str()),
+				("exit", exit_name)];
+		let trigger = script::on_exit_trigger(exit_name);
+		Ok(self.run_trigger(&room.id, &trigger, &vars)? != Some(false))
+	}
+
+	/// Run the script of the room after the actor entered it.
+	fn run_enter_triggers(&mut self, actor: &Actor, 
+						  room_id: &str) -> GameResult<()> {
+		let vars = [("actor", actor.id.as_str()), ("room", room_id)];
+		self.run_trigger(room_id, script::ON_ENTER, &vars)?;
+		Ok(())
+	}
+
+	/// Move a non player actor through an exit to another room.
+	///
+	/// Other than `move_actor` it doesn't display the room but tells the
+	/// player if the actor leaves or enters the room of the player.  The
+	/// scripts of the exit and the room are run like in `move_actor`.
+	///
+	/// # Failure
+	/// Same as `move_actor`.
+	pub fn move_npc(&mut self, actor: &Actor, exit_name: &str) -> GameResult<()> {
+		let actor_room = self.ingame.room_of_actor(actor)?;
+		let dest_room_name = self.ingame.check_exit(actor, &actor_room, exit_name)?;
+		let dest_room: Box<Room> = self.get_item(&dest_room_name)
+				.ok_or(gerr(self.ingame.msg("room.not_found", &[("room", &dest_room_name)])))?;
+		let player_room = self.ingame.room_of_player().ok().map(|x| x.id);
+		if !self.run_exit_triggers(actor, &actor_room, exit_name)? {
+			return Ok(())
+		}
+		self.warp_actor(actor, dest_room.clone());
+		if player_room.as_ref() == Some(&actor_room.id) {
+			let msg = self.ingame.msg("actor.leaves",
+					&[("actor", &actor.name), ("exit", exit_name)]);
+			self.add_message(MessageKind::Output, &actor.id, &msg);
+		}
+		if player_room.as_ref() == Some(&dest_room.id) {
+			let msg = self.ingame.msg("actor.arrives", &[("actor", &actor.name)]);
+			self.add_message(MessageKind::Output, &actor.id, &msg);
+		}
+		self.run_enter_triggers(actor, &dest_room.id)?;
 		Ok(())
 	}
 
 	/// Print a room to out.
+	///
+	/// The title is a heading and the room name and exits are marked up.
 	pub fn display_room(&mut self, room: Box<Room>) {
-		self.append_response("out", "Room: ");
-		self.append_response("out", &room.name);
-		self.append_response("out", "\n");
-		self.append_response("out", &room.description);
-		self.append_response("out", "\n");
+		let mut lines = Vec::new();
+		let name = styled(Style::Room, &escape(&room.name));
+		let title = self.ingame.msg_markup("room.title", &[("name", &name)]);
+		lines.push(styled(Style::Heading, &title));
+		lines.push(room.description.clone());
 		if !room.items.is_empty() {
-			self.append_response("out", "Items: ");
-			for item in &room.items {
-				self.append_response("out", &item);
-				self.append_response("out", " ");
-			}
-			self.append_response("out", "\n");
+			let names: Vec<String> = room.items.iter()
+				.map(|id| self.ingame.item_name(id))
+				.collect();
+			lines.push(self.ingame.msg("room.items", &[("items", &names.join(", "))]));
 		}
-		if !room.exits.is_empty() {
-			self.append_response("out", "Directions: ");
-			let keys = room.exits.keys();
-			for key in keys {
-				self.append_response("out", key);
-				self.append_response("out", " ");
-			}
-			self.append_response("out", "\n");
+		let player_id = self.ingame.get_player().map(|x| x.id).unwrap_or(String::new());
+		let actors: Vec<String> = room.actors.iter()
+			.filter(|id| **id != player_id)
+			.map(|id| self.ingame.item_name(id))
+			.collect();
+		if !actors.is_empty() {
+			lines.push(self.ingame.msg("room.actors", &[("actors", &actors.join(", "))]));
 		}
+		if room.visible_exits().next().is_some() {
+			let directions: Vec<String> = room.visible_exits()
+				.map(|x| styled(Style::Exit, &escape(x)))
+				.collect();
+			lines.push(self.ingame.msg_markup("room.directions",
+					&[("directions", &directions.join(" "))]));
+		}
+		self.add_message(MessageKind::Output, &room.id, &lines.join("\n"));
 	}
 
 	/// Display the room which holds the player.
@@ -250,12 +366,13 @@ pub fn gen_display_current_room_action() -> core::Action {
 }
 
 /// Creates an Action which moves an actor through an exit into another room.
+///
+/// The actor is moved like a non player actor, see `MutIngame::move_npc`.
 pub fn gen_move_actor_action(actor_ref: &Actor, 
 							 direction: String) -> core::Action {
 	let actor = actor_ref.clone();
 	Box::new(move |mut ingame, _| {
-		ingame.move_actor(&actor, &direction).is_ok();
-		Ok(())
+		ingame.move_npc(&actor, &direction)
 	})
 }
 
@@ -269,9 +386,39 @@ pub fn gen_move_player_action(direction: String) -> core::Action {
 
 /// Create the base package.
 pub fn gen_esge_package() -> EsgePackage {
-	EsgePackage {
-		init_action: Box::new(| _, _ | Ok(())),
-		plausability_check: Box::new(| _ | None)
-	}
+	EsgePackage::new(Box::new(| _, _ | Ok(())), Box::new(| _ | None))
 }
 
+
+
+#[test]
+fn move_actor_exit_state_test() {
+	use core::Storage;
+	let actor = Actor::new("actor").with_name("Actor");
+	let mut room1 = Room::new("room1");
+	room1.actors.push("actor".to_string());
+	room1.exits.insert("north".to_string(), "room2".to_string());
+	room1.exit_states.insert("north".to_string(), ExitState::Locked);
+	room1.exit_keys.insert("north".to_string(), "key".to_string());
+	let mut room2 = Room::new("room2");
+	room2.exits.insert("south".to_string(), "room1".to_string());
+	room2.exit_keys.insert("south".to_string(), "key".to_string());
+	let storage = Storage::new("storage")
+		.with_item(actor.clone())
+		.with_item(room1)
+		.with_item(room2);
+	let mut ingame = Ingame::with_storage(storage);
+	{
+		let mut mut_ingame = MutIngame { ingame: &mut ingame };
+		assert!(mut_ingame.move_actor(&actor, "north").is_err());
+		let mut actor_with_key = actor.clone();
+		actor_with_key.items.push("key".to_string());
+		assert!(mut_ingame.move_actor(&actor_with_key, "north").is_ok());
+	}
+	assert_eq!("room2", ingame.room_of_actor(&actor).unwrap().id);
+	{
+		let mut mut_ingame = MutIngame { ingame: &mut ingame };
+		assert!(mut_ingame.move_actor(&actor, "south").is_ok());
+	}
+	assert_eq!("room1", ingame.room_of_actor(&actor).unwrap().id);
+}
@@ -0,0 +1,350 @@
+#![warn(missing_docs)]
+
+//! Behaviours let non player actors act on their own.
+//!
+//! A behaviour is stored with the actor and executed by the recurring
+//! action of the behaviour package in every `Ingame::step`.  Actors move at
+//! most one room per step.
+
+use core::{Ingame, MutIngame, Item, Meta, Itemizeable, GameResult};
+use actor::Actor;
+use base::EsgePackage;
+use room::{Room, ExitState};
+
+use std::collections::BTreeMap;
+
+/// What an actor does on its own.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BehaviourKind {
+	/// Walk through a random exit.
+	Wander,
+	/// Walk towards the actor with the given id.
+	Follow(String),
+	/// Walk to the given rooms one after another and start again.
+	Patrol(Vec<String>),
+	/// Be in a room at a given time.
+	///
+	/// Contains the length of a day in steps and the start times of the
+	/// rooms within the day.
+	Schedule(u32, Vec<(u32, String)>)
+}
+
+/// Behaviour of an actor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Behaviour {
+	/// What the actor does.
+	pub kind: BehaviourKind,
+	/// The actor acts only every interval steps.
+	pub interval: u32,
+	/// Internal state like the patrol position.
+	pub state: u32
+}
+
+/// Counts the game steps.
+pub struct Clock {
+	/// Steps since the start of the game.
+	pub time: u32
+}
+
+impl Behaviour {
+	/// Create a behaviour which acts in every step.
+	pub fn new(kind: BehaviourKind) -> Self {
+		Behaviour {
+			kind: kind,
+			interval: 1,
+			state: 0
+		}
+	}
+
+	/// Consumes the behaviour and returns new one with the given interval.
+	///
+	/// Use for initialization.
+	pub fn with_interval(mut self, interval: u32) -> Self {
+		self.interval = interval;
+		self
+	}
+
+	/// Read the behaviour from the metas of an actor item.
+	///
+	/// Returns None if the actor has no behaviour.
+	pub fn from_metas(item: &Item) -> Option<Self> {
+		let args = item.meta_textvec_or_default("behaviour", &[]);
+		let kind = match args.first().map(|x| x.as_str()) {
+			Some("wander") => BehaviourKind::Wander,
+			Some("follow") if args.len() == 2 =>
+				BehaviourKind::Follow(args[1].clone()),
+			Some("patrol") => BehaviourKind::Patrol(args[1..].to_vec()),
+			Some("schedule") if args.len() >= 2 => {
+				let period = match args[1].parse::<u32>() {
+					Ok(period) => period,
+					Err(_) => return None
+				};
+				let mut entries = Vec::new();
+				for entry in &args[2..] {
+					let mut parts = entry.splitn(2, ':');
+					let time = parts.next().and_then(|x| x.parse::<u32>().ok());
+					let room = parts.next();
+					match (time, room) {
+						(Some(time), Some(room)) =>
+							entries.push((time, room.to_string())),
+						_ => return None
+					}
+				}
+				BehaviourKind::Schedule(period, entries)
+			},
+			_ => return None
+		};
+		let interval = item.item_meta.get("behaviour_interval")
+			.and_then(|x| x.int())
+			.unwrap_or(1)
+			.max(0);
+		let state = item.item_meta.get("behaviour_state")
+			.and_then(|x| x.int())
+			.unwrap_or(0)
+			.max(0);
+		Some(Behaviour {
+			kind: kind,
+			interval: interval as u32,
+			state: state as u32
+		})
+	}
+
+	/// Write the behaviour to the metas of an actor item.
+	pub fn merge_into_metas(behaviour: Option<&Behaviour>,
+							metas: &mut BTreeMap<String, Meta>) {
+		let (args, interval, state) = match behaviour {
+			Some(behaviour) => {
+				let args = match behaviour.kind {
+					BehaviourKind::Wander => vec!["wander".to_string()],
+					BehaviourKind::Follow(ref target) =>
+						vec!["follow".to_string(), target.clone()],
+					BehaviourKind::Patrol(ref rooms) => {
+						let mut args = vec!["patrol".to_string()];
+						args.extend(rooms.iter().cloned());
+						args
+					},
+					BehaviourKind::Schedule(period, ref entries) => {
+						let mut args = vec!["schedule".to_string(), period.to_string()];
+						args.extend(entries.iter()
+							.map(|&(time, ref room)| format!("{}:{}", time, room)));
+						args
+					}
+				};
+				(args, behaviour.interval, behaviour.state)
+			},
+			None => (Vec::new(), 1, 0)
+		};
+		metas.insert("behaviour".to_string(), Meta::TextVec(args));
+		metas.insert("behaviour_interval".to_string(), Meta::Int(interval as i32));
+		metas.insert("behaviour_state".to_string(), Meta::Int(state as i32));
+	}
+}
+
+impl Itemizeable for Clock {
+	fn item_type() -> Option<&'static str> {
+		Some("clock")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "clock" {
+			None
+		} else {
+			let time = item.item_meta.get("time").and_then(|x| x.int()).unwrap_or(0).max(0);
+			Some(Box::new(Clock { time: time as u32 }))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("clock".to_string(), "clock".to_string());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		item.item_meta.insert("time".to_string(), Meta::Int(self.time as i32));
+	}
+	fn get_id(&self) -> &str {
+		"clock"
+	}
+}
+
+impl Ingame {
+	/// Get the number of steps since the start of the game.
+	pub fn time(&self) -> u32 {
+		self.get_item::<Clock>("clock").map(|clock| clock.time).unwrap_or(0)
+	}
+
+	/// Get the first exit of the way from the actor to the room.
+	///
+	/// Returns None if the actor is already there or if there is no way.
+	pub fn next_exit_to_room(&self, actor: &Actor, room_id: &str) -> Option<String> {
+		let room = match self.room_of_actor(actor) {
+			Ok(room) => room,
+			Err(_) => return None
+		};
+		self.find_path(actor, &room.id, room_id).ok()
+			.and_then(|path| path.into_iter().next())
+	}
+}
+
+/// Decide which exit the actor takes in this step.
+///
+/// Updates the state of the behaviour.  Random decisions use the random
+/// number generator of the ingame.
+fn next_exit(ingame: &mut Ingame, actor: &Actor,
+			 behaviour: &mut Behaviour, time: u32) -> Option<String> {
+	match behaviour.kind.clone() {
+		BehaviourKind::Wander => {
+			let room = match ingame.room_of_actor(actor) {
+				Ok(room) => room,
+				Err(_) => return None
+			};
+			let mut exits: Vec<String> = room.exits.iter()
+				.filter(|&(exit, dest)| room.exit_state(exit) == ExitState::Open &&
+						ingame.get_item::<Room>(dest).is_some())
+				.map(|(exit, _)| exit.clone())
+				.collect();
+			if exits.is_empty() {
+				return None
+			}
+			exits.sort();
+			let i = ingame.rng().index(exits.len());
+			Some(exits.swap_remove(i))
+		},
+		BehaviourKind::Follow(ref target) => {
+			let target = match ingame.get_item::<Actor>(target) {
+				Some(target) => target,
+				None => return None
+			};
+			let target_room = match ingame.room_of_actor(&target) {
+				Ok(room) => room,
+				Err(_) => return None
+			};
+			ingame.next_exit_to_room(actor, &target_room.id)
+		},
+		BehaviourKind::Patrol(ref rooms) => {
+			if rooms.is_empty() {
+				return None
+			}
+			let room = match ingame.room_of_actor(actor) {
+				Ok(room) => room,
+				Err(_) => return None
+			};
+			let mut index = behaviour.state as usize % rooms.len();
+			if rooms[index] == room.id {
+				index = (index + 1) % rooms.len();
+				behaviour.state = index as u32;
+			}
+			ingame.next_exit_to_room(actor, &rooms[index])
+		},
+		BehaviourKind::Schedule(period, ref entries) => {
+			let now = if period == 0 { time } else { time % period };
+			let room = entries.iter()
+				.filter(|&&(start, _)| start <= now)
+				.max_by_key(|&&(start, _)| start)
+				.or(entries.iter().max_by_key(|&&(start, _)| start));
+			match room {
+				Some(&(_, ref room)) => ingame.next_exit_to_room(actor, room),
+				None => None
+			}
+		}
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Advance the clock by one step.
+	///
+	/// Returns the new time.
+	pub fn tick(&mut self) -> u32 {
+		let time = self.ingame.time() + 1;
+		self.insert_item(Box::new(Clock { time: time }));
+		time
+	}
+
+	/// Run the behaviours of all actors except the player.
+	///
+	/// If an actor cannot move, it stays where it is and the other actors
+	/// still act.  The player is not bothered with the error.
+	pub fn run_behaviours(&mut self) -> GameResult<()> {
+		let time = self.tick();
+		let player_id = self.ingame.get_player().map(|x| x.id).unwrap_or(String::new());
+		let mut actors: Vec<Box<Actor>> = self.ingame.all_of_type::<Actor>()
+			.filter(|actor| actor.behaviour.is_some() && actor.id != player_id)
+			.collect();
+		actors.sort_by(|a, b| a.id.cmp(&b.id));
+		for mut actor in actors {
+			let mut behaviour = actor.behaviour.clone().unwrap();
+			let interval = if behaviour.interval == 0 { 1 } else { behaviour.interval };
+			if time % interval != 0 {
+				continue
+			}
+			let exit = next_exit(self.ingame, &actor, &mut behaviour, time);
+			if actor.behaviour.as_ref() != Some(&behaviour) {
+				actor.behaviour = Some(behaviour);
+				self.insert_item(actor.clone());
+			}
+			if let Some(exit) = exit {
+				let _ = self.move_npc(&actor, &exit);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Create the behaviour package.
+///
+/// Its init action stays registered and runs the behaviours in every step.
+pub fn gen_behaviour_package() -> EsgePackage {
+	EsgePackage::new(Box::new(|ingame, _| ingame.run_behaviours()),
+			Box::new(|_| None))
+}
+
+#[test]
+fn behaviour_test() {
+	use core::Storage;
+	use response::MessageKind;
+	use base::BaseGame;
+	let mut guard = Actor::new("guard").with_name("Guard");
+	guard.behaviour = Some(Behaviour::new(BehaviourKind::Patrol(
+			vec!["room1".to_string(), "room2".to_string()])));
+	let mut dog = Actor::new("dog").with_name("Dog");
+	dog.behaviour = Some(Behaviour::new(BehaviourKind::Follow("player".to_string())));
+	let mut room1 = Room::new("room1");
+	room1.exits.insert("east".to_string(), "room2".to_string());
+	room1.actors.push("guard".to_string());
+	room1.actors.push("player".to_string());
+	let mut room2 = Room::new("room2");
+	room2.exits.insert("west".to_string(), "room1".to_string());
+	room2.actors.push("dog".to_string());
+	let mut bat = Actor::new("bat").with_name("Bat");
+	bat.behaviour = Some(Behaviour::new(BehaviourKind::Wander));
+	let mut room3 = Room::new("room3");
+	room3.exits.insert("down".to_string(), "void".to_string());
+	room3.actors.push("bat".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(bat.clone())
+		.with_item(room3)
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(guard.clone())
+		.with_item(dog.clone())
+		.with_item(room1)
+		.with_item(room2);
+	let ingame = Ingame::init_packages(storage,
+			vec![gen_behaviour_package()]).unwrap();
+	let mut ingame = ingame;
+	ingame.step();
+	assert_eq!("Dog arrives.\nGuard leaves east.", ingame.response().text(&MessageKind::Output));
+	assert!(!ingame.response().contains(&MessageKind::Error));
+	assert_eq!("room3", ingame.room_of_actor(&bat).unwrap().id);
+	assert_eq!("room1", ingame.room_of_actor(&dog).unwrap().id);
+	assert_eq!("room2", ingame.room_of_actor(&guard).unwrap().id);
+	ingame.step();
+	assert_eq!("Guard arrives.", ingame.response().text(&MessageKind::Output));
+	assert_eq!(2, ingame.time());
+	let stored = ingame.get_item::<Actor>("guard").unwrap();
+	assert_eq!(guard.behaviour.unwrap().kind, stored.behaviour.unwrap().kind);
+
+	let mut item = ingame.get_item::<Actor>("guard").unwrap().to_item();
+	item.item_meta.insert("behaviour_interval".to_string(), Meta::Int(-3));
+	item.item_meta.insert("behaviour_state".to_string(), Meta::Int(-1));
+	let behaviour = Actor::from_item(&item).unwrap().behaviour.unwrap();
+	assert_eq!((0, 0), (behaviour.interval, behaviour.state));
+}
@@ -0,0 +1,335 @@
+#![warn(missing_docs)]
+
+//! Turn based combat between the player and other actors.
+//!
+//! Combat is opt-in and enabled by the combat package.  It uses the
+//! attributes of the actors:
+//!
+//! * `health`: the actor dies if it drops to zero.  Actors without health
+//!   cannot be attacked.
+//! * `strength`: improves hits and damage.
+//! * `defense`: makes the actor harder to hit and reduces the damage.
+//! * `agility`: improves the initiative.
+//!
+//! Missing attributes count as zero.  An attack hits if a twenty sided die
+//! plus the strength of the attacker beats ten plus the defense of the
+//! defender.  The damage is a six sided die plus half of the strength minus
+//! half of the defense but at least one.
+
+use core::{Ingame, MutIngame, Item, Meta, Action, Itemizeable,
+				GameError, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use room::Room;
+use base::EsgePackage;
+use random::Rng;
+use terminal::Command;
+
+use std::cmp::max;
+
+/// Attribute which decides if an actor is alive.
+pub const HEALTH: &'static str = "health";
+/// Attribute which improves hits and damage.
+pub const STRENGTH: &'static str = "strength";
+/// Attribute which makes an actor harder to hit.
+pub const DEFENSE: &'static str = "defense";
+/// Attribute which improves the initiative.
+pub const AGILITY: &'static str = "agility";
+
+/// Settings of the combat package.
+#[derive(Clone, Debug)]
+pub struct CombatConfig {
+	/// The room where a dead player wakes up, the game ends if there is none.
+	pub respawn_room: Option<String>
+}
+
+impl Itemizeable for CombatConfig {
+	fn item_type() -> Option<&'static str> {
+		Some("combat")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "combat" {
+			None
+		} else {
+			let respawn_room = match item.meta_text_or_default("respawn_room", "") {
+				"" => None,
+				room => Some(room.to_string())
+			};
+			Some(Box::new(CombatConfig {
+				respawn_room: respawn_room
+			}))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("combat".to_string(), "combat".to_string());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		item.item_meta.insert("respawn_room".to_string(), Meta::Text(
+				self.respawn_room.clone().unwrap_or("".to_string())));
+	}
+	fn get_id(&self) -> &str {
+		"combat"
+	}
+}
+
+fn attribute_or_zero(actor: &Actor, name: &str) -> i32 {
+	actor.attribute(name).unwrap_or(0)
+}
+
+/// Check if the actor has health left.
+pub fn is_alive(actor: &Actor) -> bool {
+	actor.attribute(HEALTH).map(|health| health > 0).unwrap_or(false)
+}
+
+/// Roll the initiative of an actor.
+pub fn roll_initiative(rng: &mut Rng, actor: &Actor) -> i32 {
+	rng.range(1, 7) + attribute_or_zero(actor, AGILITY)
+}
+
+/// Roll an attack.
+///
+/// Returns the damage or None if the attack misses.
+pub fn roll_attack(rng: &mut Rng, attacker: &Actor, defender: &Actor) -> Option<i32> {
+	let strength = attribute_or_zero(attacker, STRENGTH);
+	let defense = attribute_or_zero(defender, DEFENSE);
+	if rng.range(1, 21) + strength > 10 + defense {
+		Some(max(1, rng.range(1, 7) + strength / 2 - defense / 2))
+	} else {
+		None
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Let the attacker attack the defender once.
+	///
+	/// Returns false if the defender died.
+	fn combat_turn(&mut self, attacker_id: &str, defender_id: &str,
+				   player_id: &str) -> GameResult<bool> {
+		let attacker = self.get_item::<Actor>(attacker_id)
+				.ok_or(berr(self.ingame.msg("actor.not_found", &[("actor", attacker_id)])))?;
+		let mut defender = self.get_item::<Actor>(defender_id)
+				.ok_or(berr(self.ingame.msg("actor.not_found", &[("actor", defender_id)])))?;
+		let (key, damage) = match roll_attack(self.rng(), &attacker, &defender) {
+			Some(damage) => {
+				if !defender.change_attribute(HEALTH, -damage) {
+					Err(berr(self.ingame.msg("combat.cannot_fight",
+							&[("actor", &defender.name)])))?
+				}
+				self.insert_item(defender.clone());
+				if attacker.id == player_id {
+					("combat.player_hits", damage)
+				} else if defender.id == player_id {
+					("combat.hits_player", damage)
+				} else {
+					("combat.hits", damage)
+				}
+			},
+			None => {
+				if attacker.id == player_id {
+					("combat.player_misses", 0)
+				} else if defender.id == player_id {
+					("combat.misses_player", 0)
+				} else {
+					("combat.misses", 0)
+				}
+			}
+		};
+		let msg = self.ingame.msg(key, &[("attacker", &attacker.name),
+				("target", &defender.name), ("damage", &damage.to_string())]);
+		self.add_message(MessageKind::Output, &defender.id, &msg);
+		if is_alive(&defender) {
+			Ok(true)
+		} else {
+			self.kill_actor(&defender, player_id)?;
+			Ok(false)
+		}
+	}
+
+	/// Handle the death of an actor.
+	///
+	/// Other actors are removed from their room.  The player wakes up in the
+	/// respawn room with full health or the game ends.
+	pub fn kill_actor(&mut self, actor: &Actor, player_id: &str) -> GameResult<()> {
+		let room = self.ingame.room_of_actor(actor)?;
+		if actor.id != player_id {
+			let msg = self.ingame.msg("combat.dies", &[("actor", &actor.name)]);
+			self.add_message(MessageKind::Output, &actor.id, &msg);
+			self.remove_actor_from_room(actor, room);
+			return Ok(())
+		}
+		let config = self.get_item::<CombatConfig>("combat")
+				.ok_or(berr(self.ingame.msg("combat.disabled", &[])))?;
+		match config.respawn_room {
+			Some(ref respawn_room) => {
+				let respawn_room = self.get_item::<Room>(respawn_room)
+						.ok_or(berr(self.ingame.msg("room.not_found",
+								&[("room", respawn_room)])))?;
+				let mut player = actor.clone();
+				let max_health = player.attributes.get(HEALTH)
+					.map(|health| health.max).unwrap_or(1);
+				player.set_attribute(HEALTH, max_health);
+				player.effects.clear();
+				self.insert_item(Box::new(player.clone()));
+				let msg = self.ingame.msg("combat.respawn", &[]);
+				self.add_message(MessageKind::Output, &player.id, &msg);
+				self.warp_actor(&player, respawn_room.clone());
+				self.display_room(respawn_room);
+			},
+			None => {
+				let msg = self.ingame.msg("combat.game_over", &[]);
+				self.add_message(MessageKind::Output, &actor.id, &msg);
+				self.add_message(MessageKind::Done, &actor.id, "");
+			}
+		}
+		Ok(())
+	}
+
+	/// Fight one round between the player and the target.
+	///
+	/// Both attack each other in the order of their initiative.  The dice
+	/// are rolled with the random number generator of the ingame.
+	///
+	/// # Failure
+	/// Error if combat is not enabled, if the target cannot be attacked or
+	/// if one of them has no health.
+	pub fn combat_round(&mut self, target: &Actor) -> GameResult<()> {
+		if !self.ingame.combat_enabled() {
+			return Err(berr(self.ingame.msg("combat.disabled", &[])))
+		}
+		let player = self.ingame.get_player()?;
+		if player.id == target.id {
+			return Err(berr(self.ingame.msg("combat.attack_self", &[])))
+		}
+		if !is_alive(target) {
+			return Err(berr(self.ingame.msg("combat.cannot_attack",
+					&[("target", &target.name)])))
+		}
+		for actor in &[&*player, target] {
+			if actor.attribute(HEALTH).is_none() {
+				return Err(berr(self.ingame.msg("combat.cannot_fight",
+						&[("actor", &actor.name)])))
+			}
+		}
+		let mut order = vec![
+			(roll_initiative(self.rng(), &player), player.id.clone(),
+				target.id.clone()),
+			(roll_initiative(self.rng(), target), target.id.clone(),
+				player.id.clone())
+		];
+		order.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+		for (_, attacker, defender) in order {
+			if !self.combat_turn(&attacker, &defender, &player.id)? {
+				break
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Ingame {
+	/// Check if the combat package is enabled.
+	pub fn combat_enabled(&self) -> bool {
+		self.get_item::<CombatConfig>("combat").is_some()
+	}
+}
+
+/// Create the combat package.
+///
+/// The player wakes up in the respawn room after death, without respawn
+/// room the game ends.
+pub fn gen_combat_package(respawn_room: Option<String>) -> EsgePackage {
+	let check_room = respawn_room.clone();
+	EsgePackage::new(Box::new(move |ingame, _| {
+		if !ingame.ingame.combat_enabled() {
+			ingame.insert_item(Box::new(CombatConfig {
+				respawn_room: respawn_room.clone()
+			}));
+		}
+		Ok(())
+	}), Box::new(move |ingame| {
+		match check_room {
+			Some(ref room) if ingame.get_item::<Room>(room).is_none() =>
+				Some(ingame.msg("room.not_found", &[("room", room)])),
+			_ => None
+		}
+	}))
+}
+
+/// Creates an Action which attacks an actor in the room of the player.
+pub fn gen_attack_action(actor_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let target = ingame.ingame.find_actor_in_player_room(&actor_name)?;
+		ingame.combat_round(&target)
+	})
+}
+
+/// Command which attacks an actor, `attack <actor>`.
+pub fn gen_attack_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			Ok(gen_attack_action(keywords[1..].join(" ").trim().to_string()))
+		})
+	}
+}
+
+#[test]
+fn combat_test() {
+	use core::Storage;
+	use base::BaseGame;
+	let run = || {
+		let player = Actor::new("player").with_name("Player")
+			.with_attribute(HEALTH, 20, 0, 20)
+			.with_attribute(STRENGTH, 8, 0, 20);
+		let rat = Actor::new("rat").with_name("Rat")
+			.with_attribute(HEALTH, 3, 0, 3)
+			.with_attribute(STRENGTH, 1, 0, 20);
+		let mut room = Room::new("room");
+		room.actors.push("player".to_string());
+		room.actors.push("rat".to_string());
+		let storage = Storage::new("storage")
+			.with_item(player)
+			.with_item(rat)
+			.with_item(BaseGame { player: "player".to_string() })
+			.with_item(room)
+			.with_item(Room::new("temple"));
+		let mut ingame = Ingame::init_packages(storage,
+				vec![gen_combat_package(Some("temple".to_string()))]).unwrap();
+		ingame.set_seed(7);
+		ingame.step();
+		let mut out = String::new();
+		while ingame.room_of_player().unwrap().actors.len() > 1 {
+			ingame.add_one_time_action(gen_attack_action("rat".to_string()));
+			ingame.step();
+			assert_eq!("", ingame.response().text(&MessageKind::Error));
+			out.push_str(&ingame.response().text(&MessageKind::Output));
+		}
+		out
+	};
+	let out = run();
+	assert_eq!(out, run());
+	assert!(out.ends_with("Rat dies."));
+
+	let rat = Actor::new("rat").with_name("Rat")
+		.with_attribute(HEALTH, 3, 0, 3);
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	room.actors.push("rat".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(rat)
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room);
+	let mut ingame = Ingame::init_packages(storage,
+			vec![gen_combat_package(None)]).unwrap();
+	ingame.step();
+	ingame.add_one_time_action(gen_attack_action("rat".to_string()));
+	ingame.step();
+	assert_eq!("Player cannot fight", ingame.response().text(&MessageKind::Error));
+	assert_eq!(Some(3), ingame.get_item::<Actor>("rat").unwrap().attribute(HEALTH));
+}
@@ -0,0 +1,373 @@
+#![warn(missing_docs)]
+
+//! Containers like chests and bags which hold other items.
+//!
+//! Any item becomes a container when it has the `contents` meta, so a
+//! container keeps its other metas like the name.  Items inside open
+//! containers can be reached as if they were lying next to the container,
+//! also if the containers are nested.
+
+use core::{Ingame, MutIngame, Item, Meta, Action, Itemizeable,
+				GameError, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use base;
+use parser::Verb;
+
+/// The container view of an item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+	/// Id of the item.
+	pub id: String,
+	/// Ids of the contained items.
+	pub items: Vec<String>,
+	/// Only the items of open containers can be reached.
+	pub open: bool
+}
+
+impl Container {
+	/// Create an empty and open container.
+	pub fn new<S: Into<String>>(id: S) -> Self {
+		Container {
+			id: id.into(),
+			items: Vec::new(),
+			open: true
+		}
+	}
+
+	/// Consumes the container and returns new one with the added item id.
+	///
+	/// Use for initialization.
+	pub fn with_item<S: Into<String>>(mut self, item_id: S) -> Self {
+		self.items.push(item_id.into());
+		self
+	}
+
+	/// Consumes the container and returns new one which is closed.
+	///
+	/// Use for initialization.
+	pub fn closed(mut self) -> Self {
+		self.open = false;
+		self
+	}
+}
+
+impl Itemizeable for Container {
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		let items = match item.item_meta.get("contents").and_then(|x| x.textvec_ref()) {
+			Some(items) => items.clone(),
+			None => return None
+		};
+		let open = item.item_meta.get("open").and_then(|x| x.int()).unwrap_or(1);
+		Some(Box::new(Container {
+			id: item.item_id.clone(),
+			items: items,
+			open: open != 0
+		}))
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("container".to_string(), self.id.clone());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		item.item_meta.insert("contents".to_string(), Meta::TextVec(self.items.clone()));
+		item.item_meta.insert("open".to_string(), Meta::Int(self.open as i32));
+	}
+	fn get_id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl Ingame {
+	/// Get the given items and all items in their open containers.
+	///
+	/// Nested containers are searched too, the given items come first.
+	pub fn reachable_items(&self, item_ids: &[String]) -> Vec<String> {
+		let mut items: Vec<String> = Vec::new();
+		let mut queue: Vec<String> = item_ids.to_vec();
+		let mut i = 0;
+		while i < queue.len() {
+			let id = queue[i].clone();
+			i += 1;
+			if items.contains(&id) {
+				continue
+			}
+			if let Some(container) = self.get_item::<Container>(&id) {
+				if container.open {
+					queue.extend(container.items.iter().cloned());
+				}
+			}
+			items.push(id);
+		}
+		items
+	}
+
+	/// Get the id of the open container which holds the item.
+	///
+	/// Only containers reachable from the given items are searched.
+	pub fn container_of(&self, item_ids: &[String], item_id: &str) -> Option<String> {
+		self.reachable_items(item_ids).into_iter()
+			.find(|id| self.get_item::<Container>(id)
+				.map(|container| container.open
+					&& container.items.iter().any(|x| x == item_id))
+				.unwrap_or(false))
+	}
+
+	/// Get the items which the actor can reach in its room and inventory.
+	pub fn items_around(&self, actor: &Actor) -> GameResult<Vec<String>> {
+		let room = self.room_of_actor(actor)?;
+		let mut items = room.items.clone();
+		items.extend(actor.items.iter().cloned());
+		Ok(self.reachable_items(&items))
+	}
+
+	/// Find a container which the actor can reach by its id or name.
+	///
+	/// # Failure
+	/// Error if there is no such container.
+	pub fn find_container(&self, actor: &Actor, name: &str) -> GameResult<Box<Container>> {
+		let items = self.items_around(actor)?;
+		self.find_item_in(&items, name)
+			.and_then(|id| self.get_item::<Container>(&id))
+			.ok_or(berr(self.msg("container.not_here", &[("container", name)])))
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Open or close a container.
+	pub fn set_container_open(&mut self, container: &Container,
+							  open: bool) -> GameResult<()> {
+		let name = self.ingame.item_name(&container.id);
+		if container.open == open {
+			let key = if open { "container.already_open" } else { "container.already_closed" };
+			return Err(berr(self.ingame.msg(key, &[("container", &name)])))
+		}
+		let mut changed = container.clone();
+		changed.open = open;
+		self.insert_item(Box::new(changed));
+		let key = if open { "container.opened" } else { "container.closed_now" };
+		let msg = self.ingame.msg(key, &[("container", &name)]);
+		self.add_message(MessageKind::Output, &container.id, &msg);
+		Ok(())
+	}
+
+	/// Move an item from the inventory of the actor into a container.
+	///
+	/// # Failure
+	/// Error if the actor doesn't carry the item, if the container is closed
+	/// or if the container is inside the item.
+	pub fn put_item(&mut self, actor: &Actor, item_id: &str,
+					container: &Container) -> GameResult<()> {
+		let mut actor = self.get_item::<Actor>(&actor.id)
+				.ok_or(berr(self.ingame.msg("actor.not_found", &[("actor", &actor.id)])))?;
+		let item_name = self.ingame.item_name(item_id);
+		let container_name = self.ingame.item_name(&container.id);
+		let args = [("item", item_name.as_str()), ("container", container_name.as_str())];
+		if !actor.carries(item_id) {
+			return Err(berr(self.ingame.msg("item.not_carried", &args)))
+		}
+		if !container.open {
+			return Err(berr(self.ingame.msg("container.closed", &args)))
+		}
+		if self.ingame.reachable_items(&[item_id.to_string()]).contains(&container.id) {
+			return Err(berr(self.ingame.msg("container.cannot_put", &args)))
+		}
+		let mut container = container.clone();
+		actor.items.retain(|x| x != item_id);
+		container.items.push(item_id.to_string());
+		self.insert_item(actor);
+		self.insert_item(Box::new(container));
+		let msg = self.ingame.msg("container.put", &args);
+		self.add_message(MessageKind::Output, item_id, &msg);
+		Ok(())
+	}
+
+	/// Print the contents of a container to out.
+	pub fn display_container(&mut self, container: &Container) -> GameResult<()> {
+		let name = self.ingame.item_name(&container.id);
+		if !container.open {
+			return Err(berr(self.ingame.msg("container.closed", &[("container", &name)])))
+		}
+		if container.items.is_empty() {
+			let msg = self.ingame.msg("container.empty", &[("container", &name)]);
+			self.add_message(MessageKind::Output, &container.id, &msg);
+			return Ok(())
+		}
+		let names: Vec<String> = container.items.iter()
+			.map(|id| self.ingame.item_name(id))
+			.collect();
+		let msg = self.ingame.msg("container.contains",
+				&[("container", &name), ("items", &names.join(", "))]);
+		self.add_message(MessageKind::Output, &container.id, &msg);
+		Ok(())
+	}
+}
+
+/// Creates an Action which lets the player take an item out of a container.
+pub fn gen_take_from_action(item_name: String, container_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let container = ingame.ingame.find_container(&player, &container_name)?;
+		if !container.open {
+			return Err(berr(ingame.ingame.msg("container.closed",
+					&[("container", &ingame.ingame.item_name(&container.id))])))
+		}
+		let item_id = ingame.ingame.find_item_in(&container.items, &item_name)
+				.ok_or(berr(ingame.ingame.msg("container.not_in",
+						&[("item", &item_name), ("container", &container_name)])))?;
+		ingame.take_item(&player, &item_id)
+	})
+}
+
+/// Creates an Action which lets the player put an item into a container.
+pub fn gen_put_action(item_name: String, container_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let item_id = ingame.ingame.find_item_in(&player.items, &item_name)
+				.ok_or(berr(ingame.ingame.msg("item.not_carried", &[("item", &item_name)])))?;
+		let container = ingame.ingame.find_container(&player, &container_name)?;
+		ingame.put_item(&player, &item_id, &container)
+	})
+}
+
+/// Creates an Action which shows the contents of a container.
+pub fn gen_look_in_action(container_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let container = ingame.ingame.find_container(&player, &container_name)?;
+		ingame.display_container(&container)
+	})
+}
+
+/// Creates an Action which opens or closes a container.
+pub fn gen_open_action(container_name: String, open: bool) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let container = ingame.ingame.find_container(&player, &container_name)?;
+		ingame.set_container_open(&container, open)
+	})
+}
+
+/// Verb which puts items into a container, `put <items> in <container>`.
+pub fn gen_put_verb<S: Into<String>>(keyword: S) -> Verb {
+	Verb {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, parsed| {
+			let items = parsed.expect_direct(ingame)?;
+			let container_id = parsed.expect_indirect(ingame,
+					&["in", "into", "inside", "on", "onto"])?;
+			Ok(Box::new(move |ingame, _| {
+				let player = ingame.ingame.get_player()?;
+				for item_id in &items {
+					let container = ingame.get_item::<Container>(&container_id)
+						.ok_or(berr(ingame.ingame.msg("container.cannot_put_anything",
+								&[("container", &ingame.ingame.item_name(&container_id))])))?;
+					ingame.put_item(&player, item_id, &container)?;
+				}
+				Ok(())
+			}))
+		})
+	}
+}
+
+fn gen_open_verb_with(keyword: String, open: bool) -> Verb {
+	Verb {
+		keyword: keyword,
+		action_fn: Box::new(move |ingame, parsed| {
+			let items = parsed.expect_direct(ingame)?;
+			let verb = parsed.verb.clone();
+			Ok(Box::new(move |ingame, _| {
+				for item_id in &items {
+					let container = ingame.get_item::<Container>(item_id)
+						.ok_or(berr(ingame.ingame.msg("container.cannot",
+								&[("verb", &verb), ("item", &ingame.ingame.item_name(item_id))])))?;
+					ingame.set_container_open(&container, open)?;
+				}
+				Ok(())
+			}))
+		})
+	}
+}
+
+/// Verb which opens containers, `open <containers>`.
+pub fn gen_open_verb<S: Into<String>>(keyword: S) -> Verb {
+	gen_open_verb_with(keyword.into(), true)
+}
+
+/// Verb which closes containers, `close <containers>`.
+pub fn gen_close_verb<S: Into<String>>(keyword: S) -> Verb {
+	gen_open_verb_with(keyword.into(), false)
+}
+
+/// Verb which shows the room, `look`, a container, `look in <container>`,
+/// or describes something, `look at <thing>`.
+pub fn gen_look_verb<S: Into<String>>(keyword: S) -> Verb {
+	Verb {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, parsed| {
+			let preposition = parsed.preposition.clone().unwrap_or(String::new());
+			let target = parsed.direct.first().cloned().or(parsed.indirect.clone());
+			let target = match target {
+				Some(target) => target,
+				None if preposition.is_empty() =>
+					return Ok(base::gen_display_current_room_action()),
+				None => Err(GameError::new(ingame.msg("container.look_what",
+						&[("preposition", &preposition)])))?
+			};
+			Ok(Box::new(move |ingame, _| {
+				if preposition == "in" || preposition == "inside" {
+					let container = ingame.get_item::<Container>(&target)
+						.ok_or(berr(ingame.ingame.msg("container.cannot_look_into",
+								&[("item", &ingame.ingame.item_name(&target))])))?;
+					ingame.display_container(&container)
+				} else {
+					ingame.display_description(&target)
+				}
+			}))
+		})
+	}
+}
+
+#[test]
+fn container_test() {
+	use core::Storage;
+	use room::Room;
+	use base::BaseGame;
+	use inventory::gen_take_action;
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	room.items.push("chest".to_string());
+	let mut chest = Item::new("thing".to_string(), "chest".to_string());
+	chest.item_meta.insert("name".to_string(), Meta::Text("Chest".to_string()));
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room)
+		.with_item(chest)
+		.with_item(Container::new("chest").with_item("box").closed())
+		.with_item(Container::new("box").with_item("key"))
+		.with_item(Item::new("thing".to_string(), "key".to_string()));
+	let mut ingame = Ingame::with_storage(storage);
+	let run = |ingame: &mut Ingame, action: Action| {
+		ingame.add_one_time_action(action);
+		ingame.step();
+		let response = ingame.response();
+		format!("{}{}", response.text(&MessageKind::Output), response.text(&MessageKind::Error))
+	};
+	assert_eq!("There is no key here", run(&mut ingame, gen_take_action("key".to_string())));
+	assert_eq!("You open the Chest.",
+			run(&mut ingame, gen_open_action("chest".to_string(), true)));
+	assert_eq!("The Chest contains: box",
+			run(&mut ingame, gen_look_in_action("chest".to_string())));
+	assert_eq!("Taken: key", run(&mut ingame, gen_take_action("key".to_string())));
+	assert_eq!("Taken: box", run(&mut ingame,
+			gen_take_from_action("box".to_string(), "chest".to_string())));
+	assert_eq!("You cannot put box into box", run(&mut ingame,
+			gen_put_action("box".to_string(), "box".to_string())));
+	assert_eq!("You put key in box.", run(&mut ingame,
+			gen_put_action("key".to_string(), "box".to_string())));
+	let chest = ingame.get_item::<Item>("chest").unwrap();
+	assert_eq!("Chest", chest.meta_text_or_default("name", ""));
+	assert!(ingame.get_item::<Container>("chest").unwrap().items.is_empty());
+}
@@ -0,0 +1,500 @@
+#![warn(missing_docs)]
+
+//! Dialogue trees which let the player talk to actors.
+//!
+//! A dialogue consists of nodes which contain the text of the actor and
+//! choices which the player can select.  Choices may have a condition on
+//! the storage and an effect which changes the storage.  An actor starts a
+//! conversation with the node stored in `Actor::dialogue`.
+
+use core::{Ingame, MutIngame, Item, Meta, Action, Itemizeable,
+				GameError, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use terminal::Command;
+
+/// Something the actor says and the choices the player can reply with.
+#[derive(Clone, Debug)]
+pub struct DialogueNode {
+	/// Internal identifier.
+	pub id: String,
+	/// Text the actor says.
+	pub text: String,
+	/// Identifiers of the DialogueChoice items.
+	pub choices: Vec<String>
+}
+
+/// Condition on the storage which must be true to offer a choice.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DialogueCondition {
+	/// The player carries the item with the given id.
+	HasItem(String),
+	/// The text meta of an item has the given value.
+	///
+	/// Contains the item id, the meta key and the value.
+	MetaEquals(String, String, String)
+}
+
+/// A reply the player can choose in a dialogue.
+#[derive(Clone, Debug)]
+pub struct DialogueChoice {
+	/// Internal identifier.
+	pub id: String,
+	/// Text the player says.
+	pub text: String,
+	/// The next node, the conversation ends if there is none.
+	pub next: Option<String>,
+	/// Only offer the choice if the condition is true.
+	pub condition: Option<DialogueCondition>,
+	/// Set a text meta of an item when the choice is taken.
+	///
+	/// Contains the item id, the meta key and the value.
+	pub effect: Option<(String, String, String)>
+}
+
+/// The conversation which is currently running.
+#[derive(Clone, Debug)]
+pub struct DialogueState {
+	/// The actor the player is talking to.
+	pub actor: String,
+	/// The node which was displayed last.
+	pub node: String
+}
+
+impl DialogueNode {
+	/// Create a new node with the given id and text.
+	pub fn new<S: Into<String>>(id: S, text: S) -> Self {
+		DialogueNode {
+			id: id.into(),
+			text: text.into(),
+			choices: Vec::new()
+		}
+	}
+
+	/// Consumes the node and returns new one with the added choice id.
+	///
+	/// Use for initialization.
+	pub fn with_choice<S: Into<String>>(mut self, choice: S) -> Self {
+		self.choices.push(choice.into());
+		self
+	}
+}
+
+impl DialogueChoice {
+	/// Create a new choice which leads to the given node.
+	pub fn new<S: Into<String>>(id: S, text: S, next: Option<String>) -> Self {
+		DialogueChoice {
+			id: id.into(),
+			text: text.into(),
+			next: next,
+			condition: None,
+			effect: None
+		}
+	}
+
+	/// Consumes the choice and returns new one with the given condition.
+	///
+	/// Use for initialization.
+	pub fn with_condition(mut self, condition: DialogueCondition) -> Self {
+		self.condition = Some(condition);
+		self
+	}
+
+	/// Consumes the choice and returns new one which sets the text meta of
+	/// an item when it's taken.
+	///
+	/// Use for initialization.
+	pub fn with_effect<S: Into<String>>(mut self, item_id: S, key: S,
+										value: S) -> Self {
+		self.effect = Some((item_id.into(), key.into(), value.into()));
+		self
+	}
+}
+
+impl DialogueCondition {
+	/// Check the condition against the storage.
+	pub fn check(&self, ingame: &Ingame, player: &Actor) -> bool {
+		match *self {
+			DialogueCondition::HasItem(ref item_id) => player.carries(item_id),
+			DialogueCondition::MetaEquals(ref item_id, ref key, ref value) =>
+				ingame.get_item::<Item>(item_id)
+					.map(|item| item.meta_text_or_default(key, "") == value)
+					.unwrap_or(false)
+		}
+	}
+
+	fn to_vec(&self) -> Vec<String> {
+		match *self {
+			DialogueCondition::HasItem(ref item_id) =>
+				vec!["has_item".to_string(), item_id.clone()],
+			DialogueCondition::MetaEquals(ref item_id, ref key, ref value) =>
+				vec!["meta_equals".to_string(), item_id.clone(), key.clone(),
+					value.clone()]
+		}
+	}
+
+	fn from_vec(vec: &[String]) -> Option<Self> {
+		match vec.first().map(|x| x.as_str()) {
+			Some("has_item") if vec.len() == 2 =>
+				Some(DialogueCondition::HasItem(vec[1].clone())),
+			Some("meta_equals") if vec.len() == 4 =>
+				Some(DialogueCondition::MetaEquals(vec[1].clone(),
+						vec[2].clone(), vec[3].clone())),
+			_ => None
+		}
+	}
+}
+
+impl Itemizeable for DialogueNode {
+	fn item_type() -> Option<&'static str> {
+		Some("dialogue_node")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "dialogue_node" {
+			None
+		} else {
+			Some(Box::new(DialogueNode {
+				id: item.item_id.clone(),
+				text: item.meta_text_or_default("text", "").to_string(),
+				choices: item.meta_textvec_or_default("choices", &[]).to_vec()
+			}))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("dialogue_node".to_string(), self.id.clone());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		let metas = &mut item.item_meta;
+		metas.insert("text".to_string(), Meta::Text(self.text.clone()));
+		metas.insert("choices".to_string(), Meta::TextVec(self.choices.clone()));
+	}
+	fn get_id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl Itemizeable for DialogueChoice {
+	fn item_type() -> Option<&'static str> {
+		Some("dialogue_choice")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "dialogue_choice" {
+			None
+		} else {
+			let next = match item.meta_text_or_default("next", "") {
+				"" => None,
+				next => Some(next.to_string())
+			};
+			let condition = DialogueCondition::from_vec(
+					item.meta_textvec_or_default("condition", &[]));
+			let effect = item.meta_textvec_or_default("effect", &[]);
+			let effect = if effect.len() == 3 {
+				Some((effect[0].clone(), effect[1].clone(), effect[2].clone()))
+			} else {
+				None
+			};
+			Some(Box::new(DialogueChoice {
+				id: item.item_id.clone(),
+				text: item.meta_text_or_default("text", "").to_string(),
+				next: next,
+				condition: condition,
+				effect: effect
+			}))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("dialogue_choice".to_string(), self.id.clone());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		let metas = &mut item.item_meta;
+		metas.insert("text".to_string(), Meta::Text(self.text.clone()));
+		metas.insert("next".to_string(), Meta::Text(
+				self.next.clone().unwrap_or("".to_string())));
+		metas.insert("condition".to_string(), Meta::TextVec(
+				self.condition.as_ref().map(|x| x.to_vec()).unwrap_or(Vec::new())));
+		let effect = match self.effect {
+			Some((ref item_id, ref key, ref value)) =>
+				vec![item_id.clone(), key.clone(), value.clone()],
+			None => Vec::new()
+		};
+		metas.insert("effect".to_string(), Meta::TextVec(effect));
+	}
+	fn get_id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl Itemizeable for DialogueState {
+	fn item_type() -> Option<&'static str> {
+		Some("dialogue_state")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "dialogue_state" {
+			None
+		} else {
+			Some(Box::new(DialogueState {
+				actor: item.meta_text_or_default("actor", "").to_string(),
+				node: item.meta_text_or_default("node", "").to_string()
+			}))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("dialogue_state".to_string(),
+				"dialogue_state".to_string());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		item.item_meta.insert("actor".to_string(), Meta::Text(self.actor.clone()));
+		item.item_meta.insert("node".to_string(), Meta::Text(self.node.clone()));
+	}
+	fn get_id(&self) -> &str {
+		"dialogue_state"
+	}
+}
+
+impl Ingame {
+	/// Find an actor in the room of the player by name or id.
+	///
+	/// The name is compared case insensitive.
+	pub fn find_actor_in_player_room(&self, name: &str) -> GameResult<Box<Actor>> {
+		let room = self.room_of_player()?;
+		let name = name.trim().to_lowercase();
+		let actor = self.actors_in_room(&room)
+			.find(|actor| actor.id == name || actor.name.to_lowercase() == name);
+		actor.ok_or(berr(self.msg("item.not_here", &[("item", &name)])))
+	}
+
+	/// Get the choices of the node which are available for the player.
+	pub fn available_choices(&self, node: &DialogueNode)
+							-> GameResult<Vec<Box<DialogueChoice>>> {
+		let player = self.get_player()?;
+		Ok(node.choices.iter()
+			.filter_map(|id| self.get_item::<DialogueChoice>(id))
+			.filter(|choice| choice.condition.as_ref()
+				.map(|condition| condition.check(self, &player))
+				.unwrap_or(true))
+			.collect())
+	}
+
+	/// Get the running conversation.
+	pub fn dialogue_state(&self) -> Option<Box<DialogueState>> {
+		self.get_item::<DialogueState>("dialogue_state")
+			.and_then(|state| if state.actor.is_empty() { None } else { Some(state) })
+	}
+
+	/// Check if the player has ever started a conversation with the actor.
+	pub fn has_talked_to(&self, actor_id: &str) -> bool {
+		self.get_item::<Item>("dialogue_state")
+			.map(|state| state.meta_textvec_or_default("talked", &[])
+				.iter().any(|x| x == actor_id))
+			.unwrap_or(false)
+	}
+
+	/// Get the number of conversations the player has started.
+	pub fn conversation_count(&self) -> u32 {
+		self.get_item::<Item>("dialogue_state")
+			.and_then(|state| state.item_meta.get("conversations").and_then(|x| x.int()))
+			.unwrap_or(0)
+			.max(0) as u32
+	}
+
+	/// Get the number of the last conversation with the actor, starting
+	/// with one.  None if the player never talked to it.
+	pub fn last_conversation_with(&self, actor_id: &str) -> Option<u32> {
+		self.get_item::<Item>("dialogue_state")
+			.and_then(|state| state.item_meta.get(&format!("talked:{}", actor_id))
+				.and_then(|x| x.int()))
+			.map(|x| x.max(0) as u32)
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Print the dialogue node and its choices to out.
+	///
+	/// Ends the conversation if there are no choices left.
+	pub fn display_dialogue_node(&mut self, actor: &Actor,
+								 node: &DialogueNode) -> GameResult<()> {
+		let choices = self.ingame.available_choices(node)?;
+		let line = self.ingame.msg("dialogue.line",
+				&[("actor", &actor.name), ("text", &node.text)]);
+		self.add_message(MessageKind::Output, &actor.id, &line);
+		for (i, choice) in choices.iter().enumerate() {
+			self.add_message(MessageKind::Output, &choice.id,
+					&format!("  {}) {}", i + 1, choice.text));
+		}
+		if choices.is_empty() {
+			self.end_dialogue();
+		} else {
+			self.insert_item(Box::new(DialogueState {
+				actor: actor.id.clone(),
+				node: node.id.clone()
+			}));
+		}
+		Ok(())
+	}
+
+	/// Stop the running conversation.
+	pub fn end_dialogue(&mut self) {
+		self.insert_item(Box::new(DialogueState {
+			actor: "".to_string(),
+			node: "".to_string()
+		}));
+	}
+
+	/// Start a conversation with the actor.
+	///
+	/// # Failure
+	/// Error if the actor has nothing to say.
+	pub fn start_dialogue(&mut self, actor: &Actor) -> GameResult<()> {
+		let node_id = actor.dialogue.clone()
+				.ok_or(berr(self.ingame.msg("dialogue.nothing_to_say",
+						&[("actor", &actor.name)])))?;
+		let node = self.get_item::<DialogueNode>(&node_id)
+				.ok_or(berr(self.ingame.msg("dialogue.not_found", &[("dialogue", &node_id)])))?;
+		self.display_dialogue_node(actor, &node)?;
+		let mut state = self.get_item::<Item>("dialogue_state")
+			.unwrap_or(Box::new(DialogueState {
+				actor: "".to_string(),
+				node: "".to_string()
+			}.to_item()));
+		let mut talked = state.meta_textvec_or_default("talked", &[]).to_vec();
+		if !talked.contains(&actor.id) {
+			talked.push(actor.id.clone());
+			state.item_meta.insert("talked".to_string(), Meta::TextVec(talked));
+		}
+		let count = self.ingame.conversation_count() as i32 + 1;
+		state.item_meta.insert("conversations".to_string(), Meta::Int(count));
+		state.item_meta.insert(format!("talked:{}", actor.id), Meta::Int(count));
+		self.insert_item(state);
+		Ok(())
+	}
+
+	/// Take the choice with the given number, starting with one.
+	///
+	/// # Failure
+	/// Error if there is no conversation or if the number is not available.
+	pub fn choose_dialogue(&mut self, number: usize) -> GameResult<()> {
+		let state = self.ingame.dialogue_state()
+				.ok_or(berr(self.ingame.msg("dialogue.not_talking", &[])))?;
+		let actor = self.get_item::<Actor>(&state.actor)
+				.ok_or(berr(self.ingame.msg("actor.not_found", &[("actor", &state.actor)])))?;
+		let node = self.get_item::<DialogueNode>(&state.node)
+				.ok_or(berr(self.ingame.msg("dialogue.not_found",
+						&[("dialogue", &state.node)])))?;
+		let choices = self.ingame.available_choices(&node)?;
+		let choice = if number == 0 { None } else { choices.get(number - 1) }
+				.ok_or(berr(self.ingame.msg("dialogue.no_choice",
+						&[("number", &number.to_string())])))?;
+		self.add_message(MessageKind::Output, &choice.id, &format!("> {}", choice.text));
+		if let Some((ref item_id, ref key, ref value)) = choice.effect {
+			let mut item = self.get_item::<Item>(item_id)
+					.ok_or(berr(self.ingame.msg("item.not_found", &[("item", item_id)])))?;
+			item.item_meta.insert(key.clone(), Meta::Text(value.clone()));
+			self.insert_item(item);
+		}
+		match choice.next {
+			Some(ref next) => {
+				let next = self.get_item::<DialogueNode>(next)
+						.ok_or(berr(self.ingame.msg("dialogue.not_found",
+								&[("dialogue", next)])))?;
+				self.display_dialogue_node(&actor, &next)
+			},
+			None => {
+				self.end_dialogue();
+				Ok(())
+			}
+		}
+	}
+}
+
+/// Creates an Action which starts the conversation with an actor in the
+/// room of the player.
+pub fn gen_talk_action(actor_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let actor = ingame.ingame.find_actor_in_player_room(&actor_name)?;
+		ingame.start_dialogue(&actor)
+	})
+}
+
+/// Creates an Action which takes a choice in the running conversation.
+pub fn gen_say_action(number: usize) -> Action {
+	Box::new(move |ingame, _| {
+		ingame.choose_dialogue(number)
+	})
+}
+
+/// Command which starts a conversation, `talk <actor>`.
+pub fn gen_talk_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			Ok(gen_talk_action(keywords[1..].join(" ").trim().to_string()))
+		})
+	}
+}
+
+/// Command which takes a choice in the conversation, `say <number>`.
+pub fn gen_say_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			let number = keywords[1].trim().parse::<usize>()
+				.map_err(|_| GameError::new(ingame.msg("cmd.expected_number", &[])))?;
+			Ok(gen_say_action(number))
+		})
+	}
+}
+
+#[test]
+fn dialogue_test() {
+	use core::Storage;
+	use room::Room;
+	use base::BaseGame;
+	let mut lalala = Actor::new("lalala").with_name("Lalala");
+	lalala.dialogue = Some("hello".to_string());
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	room.actors.push("lalala".to_string());
+	let quest = Item::new("flag".to_string(), "quest".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(lalala)
+		.with_item(room)
+		.with_item(quest)
+		.with_item(DialogueNode::new("hello", "Hello!")
+			.with_choice("ask")
+			.with_choice("secret"))
+		.with_item(DialogueChoice::new("ask", "Any chocolate?",
+				Some("chocolate".to_string()))
+			.with_effect("quest", "chocolate", "asked"))
+		.with_item(DialogueChoice::new("secret", "The password", None)
+			.with_condition(DialogueCondition::HasItem("password".to_string())))
+		.with_item(DialogueNode::new("chocolate", "Sure, have some."));
+	let mut ingame = Ingame::with_storage(storage);
+	ingame.add_one_time_action(gen_talk_action("lalala".to_string()));
+	ingame.step();
+	assert_eq!("Lalala: Hello!\n  1) Any chocolate?",
+			ingame.response().text(&MessageKind::Output));
+	ingame.add_one_time_action(gen_say_action(2));
+	ingame.step();
+	assert_eq!("There is no choice 2", ingame.response().text(&MessageKind::Error));
+	ingame.add_one_time_action(gen_say_action(1));
+	ingame.step();
+	assert_eq!("> Any chocolate?\nLalala: Sure, have some.",
+			ingame.response().text(&MessageKind::Output));
+	assert!(ingame.dialogue_state().is_none());
+	assert_eq!("asked", ingame.get_item::<Item>("quest").unwrap()
+			.meta_text_or_default("chocolate", ""));
+}
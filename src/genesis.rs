@@ -1,22 +1,24 @@
 #![warn(missing_docs)]
 
 //! Create a world inside another world
-use core::{Storage, Action, Ingame, GameError};
-use room::Room;
+use core::{Storage, Action, Ingame, GameError, GameResult, Item, Meta, berr};
+use room::{Room, ExitState};
 use actor::Actor;
 use base::{BaseGame};
 use terminal::{Command, multiline_input, line_input};
-use std::io::{Write, Read};
+use script;
+use rules::{Rule, RulePhase};
+use util::write_atomic;
+use std::io::Read;
 use std::error::Error;
 use std::fs;
+use std::path::{Path, PathBuf, Component};
 
 /// Get ste minimal storage required
 pub fn initial_genesis(player_name: &str) -> Storage {
-	let player = Actor {
-		id: "player-actor".to_string(),
-		name: player_name.into(),
-		description: "You".to_string()
-	};
+	let player = Actor::new("player-actor")
+		.with_name(player_name)
+		.with_description("You");
 	let base_game = BaseGame {
 		player: "player-actor".to_string()
 	};
@@ -69,15 +71,191 @@ pub fn gen_exit_action<S: Into<String>>(exit_name: S, room_id: S) -> Action {
 pub fn gen_exit_cmd<S: Into<String>>(keyword: S) -> Command{
 	Command {
 		keyword: keyword.into(),
-		action_fn: Box::new(|_, keywords | {
+		action_fn: Box::new(|ingame, keywords | {
 			if keywords.len() < 3 {
-				Err(GameError::new("Expected two arguments".to_string()))?;
+				Err(GameError::new(ingame.msg("cmd.expected_two", &[])))?;
 			}
 			Ok(gen_exit_action(keywords[1].trim(), keywords[2].trim()))
 		})
 	}
 }
 
+/// Pairs of directions which lead back to each other.
+pub const OPPOSITE_DIRECTIONS: &'static [(&'static str, &'static str)] = &[
+	("north", "south"),
+	("east", "west"),
+	("northeast", "southwest"),
+	("northwest", "southeast"),
+	("up", "down"),
+	("in", "out")
+];
+
+/// Get the direction which leads back.
+///
+/// Returns None if the direction is not in `OPPOSITE_DIRECTIONS`.
+pub fn opposite_direction(direction: &str) -> Option<&'static str> {
+	for &(a, b) in OPPOSITE_DIRECTIONS {
+		if a == direction {
+			return Some(b)
+		}
+		if b == direction {
+			return Some(a)
+		}
+	}
+	None
+}
+
+/// Derive an unused room id from a room name.
+///
+/// The name is lowercased and everything except letters and digits is
+/// replaced by a dash.  A number is appended if the id is already taken.
+pub fn room_id_from_name(ingame: &Ingame, name: &str) -> String {
+	let mut base = String::new();
+	for c in name.trim().to_lowercase().chars() {
+		if c.is_alphanumeric() {
+			base.push(c);
+		} else if !base.is_empty() && !base.ends_with('-') {
+			base.push('-');
+		}
+	}
+	let base = base.trim_matches('-').to_string();
+	let base = if base.is_empty() { "room".to_string() } else { base };
+	let mut id = base.clone();
+	let mut i = 2;
+	while ingame.get_item::<Item>(&id).is_some() {
+		id = format!("{}-{}", base, i);
+		i += 1;
+	}
+	id
+}
+
+/// Action which creates a new room behind the given direction, links it in
+/// both directions and moves the player into it.
+pub fn gen_dig_action<S: Into<String>>(direction: S, name: S) -> Action {
+	let direction: String = direction.into();
+	let name: String = name.into();
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let mut player_room = ingame.ingame.room_of_player()?;
+		if player_room.exits.contains_key(&direction) {
+			return Err(berr(ingame.ingame.msg("genesis.exit_exists", &[("exit", &direction)])))
+		}
+		let back = opposite_direction(&direction)
+				.ok_or(berr(ingame.ingame.msg("genesis.unknown_direction",
+						&[("direction", &direction)])))?;
+		let room_id = room_id_from_name(ingame.ingame, &name);
+		let mut room = Room::new(room_id.clone()).with_name(name.clone());
+		room.exits.insert(back.to_string(), player_room.id.clone());
+		player_room.exits.insert(direction.clone(), room_id.clone());
+		ingame.insert_item(player_room);
+		ingame.insert_item(Box::new(room));
+		let room = ingame.get_item::<Room>(&room_id)
+				.ok_or(berr(ingame.ingame.msg("room.not_found", &[("room", &room_id)])))?;
+		ingame.warp_actor(&player, room.clone());
+		ingame.display_room(room);
+		Ok(())
+	})
+}
+
+/// Command which digs a new room, `dig <direction> <name>`.
+pub fn gen_dig_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 3 {
+				Err(GameError::new(ingame.msg("cmd.expected_two", &[])))?;
+			}
+			let name = keywords[2..].join(" ");
+			Ok(gen_dig_action(keywords[1].trim(), name.trim()))
+		})
+	}
+}
+
+/// Action which changes the state of an exit in the player's room.
+pub fn gen_exit_state_action<S: Into<String>>(exit_name: S, 
+											  state: ExitState) -> Action {
+	let exit_name: String = exit_name.into();
+	Box::new(move |ingame, _| {
+		let mut player_room = ingame.ingame.room_of_player()?;
+		if !player_room.exits.contains_key(&exit_name) {
+			return Err(berr(ingame.ingame.msg("genesis.exit_not_found",
+					&[("exit", &exit_name)])))
+		}
+		player_room.exit_states.insert(exit_name.clone(), state);
+		ingame.insert_item(player_room);
+		Ok(())
+	})
+}
+
+/// Action which sets or removes the key required to pass an exit in the
+/// player's room.
+pub fn gen_exit_key_action<S: Into<String>>(exit_name: S, 
+											key: Option<String>) -> Action {
+	let exit_name: String = exit_name.into();
+	Box::new(move |ingame, _| {
+		let mut player_room = ingame.ingame.room_of_player()?;
+		if !player_room.exits.contains_key(&exit_name) {
+			return Err(berr(ingame.ingame.msg("genesis.exit_not_found",
+					&[("exit", &exit_name)])))
+		}
+		match key {
+			Some(ref key) => 
+				player_room.exit_keys.insert(exit_name.clone(), key.clone()),
+			None => player_room.exit_keys.remove(&exit_name)
+		};
+		ingame.insert_item(player_room);
+		Ok(())
+	})
+}
+
+fn gen_exit_state_cmd(keyword: String, state: ExitState) -> Command {
+	Command {
+		keyword: keyword,
+		action_fn: Box::new(move |ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			Ok(gen_exit_state_action(keywords[1].trim(), state))
+		})
+	}
+}
+
+/// Command which locks an exit.
+pub fn gen_lock_exit_cmd<S: Into<String>>(keyword: S) -> Command {
+	gen_exit_state_cmd(keyword.into(), ExitState::Locked)
+}
+
+/// Command which unlocks and opens an exit.
+pub fn gen_unlock_exit_cmd<S: Into<String>>(keyword: S) -> Command {
+	gen_exit_state_cmd(keyword.into(), ExitState::Open)
+}
+
+/// Command which closes an exit.
+pub fn gen_close_exit_cmd<S: Into<String>>(keyword: S) -> Command {
+	gen_exit_state_cmd(keyword.into(), ExitState::Closed)
+}
+
+/// Command which hides an exit.
+pub fn gen_hide_exit_cmd<S: Into<String>>(keyword: S) -> Command {
+	gen_exit_state_cmd(keyword.into(), ExitState::Hidden)
+}
+
+/// Command which sets the key of an exit.
+///
+/// Without a key id the key requirement is removed.
+pub fn gen_exit_key_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one_or_two", &[])))?;
+			}
+			let key = keywords.get(2).map(|x| x.trim().to_string());
+			Ok(gen_exit_key_action(keywords[1].trim(), key))
+		})
+	}
+}
+
 /// Action to rename a room.
 pub fn gen_rename_room_action<S: Into<String>>(name: S) -> Action {
 	let name: String = name.into();
@@ -104,9 +282,9 @@ pub fn gen_redescribe_room_action<S: Into<String>>(name: S) -> Action {
 pub fn gen_rename_room_cmd<S: Into<String>>(keyword: S) -> Command{
 	Command {
 		keyword: keyword.into(),
-		action_fn: Box::new(|_, keywords | {
+		action_fn: Box::new(|ingame, keywords | {
 			if keywords.len() < 2 {
-				Err(GameError::new("Expected one argument".to_string()))?;
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
 			}
 			Ok(gen_rename_room_action(keywords[1].trim()))
 		})
@@ -117,21 +295,172 @@ pub fn gen_rename_room_cmd<S: Into<String>>(keyword: S) -> Command{
 pub fn gen_redescribe_room_cmd<S: Into<String>>(keyword: S) -> Command{
 	Command {
 		keyword: keyword.into(),
-		action_fn: Box::new(|_, _ | {
-			print!("Write a multiline text, terminate with END\n");
+		action_fn: Box::new(|ingame, _ | {
+			print!("{}\n", ingame.msg("genesis.write_text", &[("term", "END")]));
 			let description = multiline_input("END")?;
 			Ok(gen_redescribe_room_action(description))
 		})
 	}
 }
 
-/// Save the storage to a file at the given path.
+/// Resolve a world name to a path inside the given root directory.
+///
+/// Only plain relative names are accepted.  Names containing `..`, absolute
+/// paths and paths which leave the root through a symlink are rejected.
+///
+/// # Failure
+/// Returns an error if the name is not allowed or if the root or the parent
+/// directory of the world does not exist.
+pub fn world_path<P: AsRef<Path>>(ingame: &Ingame, root: P,
+								  name: &str) -> GameResult<PathBuf> {
+	let name = name.trim();
+	if name.is_empty() {
+		return Err(berr(ingame.msg("genesis.world_name_empty", &[])))
+	}
+	let invalid = || berr(ingame.msg("genesis.invalid_world_name", &[("name", name)]));
+	let relative = Path::new(name);
+	for component in relative.components() {
+		match component {
+			Component::Normal(_) => (),
+			_ => return Err(invalid())
+		}
+	}
+	let root = fs::canonicalize(root.as_ref())?;
+	let path = root.join(relative);
+	let resolved = match fs::canonicalize(&path) {
+		Ok(resolved) => resolved,
+		Err(_) => {
+			let parent = path.parent().ok_or_else(&invalid)?;
+			let file_name = path.file_name().ok_or_else(&invalid)?;
+			fs::canonicalize(parent)?.join(file_name)
+		}
+	};
+	if !resolved.starts_with(&root) {
+		return Err(berr(ingame.msg("genesis.world_outside", &[("name", name)])))
+	}
+	Ok(path)
+}
+
+/// Action which attaches a script to a trigger of an item.
+///
+/// The item id `here` stands for the room of the player.  An empty script
+/// removes the trigger.
+pub fn gen_set_script_action<S: Into<String>>(item_id: S, trigger: S,
+											  source: S) -> Action {
+	let item_id: String = item_id.into();
+	let trigger: String = trigger.into();
+	let source: String = source.into();
+	Box::new(move |ingame, _| {
+		let item_id = if item_id == "here" {
+			ingame.ingame.room_of_player()?.id
+		} else {
+			item_id.clone()
+		};
+		script::parse(&source)?;
+		let mut item = ingame.get_item::<Item>(&item_id)
+				.ok_or(berr(ingame.ingame.msg("item.not_found", &[("item", &item_id)])))?;
+		item.item_meta.insert(trigger.clone(), Meta::Text(source.clone()));
+		ingame.insert_item(item);
+		Ok(())
+	})
+}
+
+/// Command which attaches a script, `script <item> <trigger>`.
+///
+/// The script is read as multiline text.
+pub fn gen_set_script_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 3 {
+				Err(GameError::new(ingame.msg("cmd.expected_two", &[])))?;
+			}
+			print!("{}\n", ingame.msg("genesis.write_script", &[("term", "END")]));
+			let source = multiline_input("END")?;
+			Ok(gen_set_script_action(keywords[1].trim().to_string(),
+					keywords[2].trim().to_string(), source))
+		})
+	}
+}
+
+/// Action which adds a rule for the room of the player.
+///
+/// The id of the rule is generated from the room and the verb.
+pub fn gen_add_rule_action(phase: RulePhase, verb: String, object: Option<String>,
+						   condition: String, script: String) -> Action {
+	Box::new(move |ingame, _| {
+		script::parse(&script)?;
+		if !condition.trim().is_empty() {
+			script::parse_expression(&condition)?;
+		}
+		let room = ingame.ingame.room_of_player()?;
+		let base_id = format!("rule-{}-{}", room.id, verb);
+		let mut id = base_id.clone();
+		let mut i = 2;
+		while ingame.get_item::<Item>(&id).is_some() {
+			id = format!("{}-{}", base_id, i);
+			i += 1;
+		}
+		let mut rule = Rule::new(id, phase, verb.clone(), script.clone())
+			.with_room(room.id)
+			.with_condition(condition.clone());
+		rule.object = object.clone();
+		ingame.insert_item(Box::new(rule));
+		Ok(())
+	})
+}
+
+/// Command which adds a rule to the room, `rule <phase> <verb> [object]`.
+///
+/// The phase is `before`, `instead` or `after`.  Condition and script are
+/// read from the input.
+pub fn gen_add_rule_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 3 {
+				Err(GameError::new(ingame.msg("cmd.expected_at_least_two", &[])))?;
+			}
+			let phase = RulePhase::parse(keywords[1])
+				.ok_or(GameError::new(ingame.msg("genesis.unknown_phase",
+						&[("phase", keywords[1])])))?;
+			let object = keywords[3..].join(" ").trim().to_string();
+			let object = if object.is_empty() { None } else { Some(object) };
+			let condition = line_input(
+					format!("{}: ", ingame.msg("genesis.condition", &[])))?;
+			print!("{}\n", ingame.msg("genesis.write_script", &[("term", "END")]));
+			let script = multiline_input("END")?;
+			Ok(gen_add_rule_action(phase, keywords[2].trim().to_string(), object,
+					condition, script))
+		})
+	}
+}
+
+/// Action which chooses the language of the world.
+pub fn gen_language_action<S: Into<String>>(language: S) -> Action {
+	let language: String = language.into();
+	Box::new(move |ingame, _| {
+		ingame.set_language(&language)
+	})
+}
+
+/// Command which chooses the language of the world, `language <code>`.
+pub fn gen_language_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			Ok(gen_language_action(keywords[1].trim()))
+		})
+	}
+}
+
+/// Save the storage to a file at the given path, see `write_atomic`.
 pub fn save_world(ingame: &Ingame, path: String) -> Result<(), Box<Error>> {
 	let export_str: String = ingame.serialize()?;
-	let mut out_file = fs::File::create(&path)?;
-	write!(out_file, "{}", export_str)?;
-
-	Ok(())
+	write_atomic(path, &export_str)
 }
 
 /// Load the storage from the given path.
@@ -144,24 +473,31 @@ pub fn load_world(ingame: &mut Ingame, path: String) -> Result<(), Box<Error>> {
 }
 
 /// Command to save the storage.
-pub fn save_world_cmd(keyword: String) -> Command {
+///
+/// The world name is resolved inside the root directory, see `world_path`.
+pub fn save_world_cmd(keyword: String, root: PathBuf) -> Command {
 	Command {
 		keyword: keyword,
-		action_fn: Box::new(|ingame, _ | {
-			let name = line_input("World input: ")?;
-			save_world(ingame, name.trim().to_string())?;
+		action_fn: Box::new(move |ingame, _ | {
+			let name = line_input(format!("{}: ", ingame.msg("genesis.world_name", &[])))?;
+			fs::create_dir_all(&root)?;
+			let path = world_path(ingame, &root, &name)?;
+			save_world(ingame, path.to_string_lossy().into_owned())?;
 			Err(GameError::new("".to_string()))?
 		})
 	}
 }
 
 /// Command to load the storage.
-pub fn load_world_cmd(keyword: String) -> Command {
+///
+/// The world name is resolved inside the root directory, see `world_path`.
+pub fn load_world_cmd(keyword: String, root: PathBuf) -> Command {
 	Command {
 		keyword: keyword,
-		action_fn: Box::new(|mut ingame, _ | {
-			let name = line_input("World name: ")?;
-			load_world(ingame, name)?;
+		action_fn: Box::new(move |ingame, _| {
+			let name = line_input(format!("{}: ", ingame.msg("genesis.world_name", &[])))?;
+			let path = world_path(ingame, &root, &name)?;
+			load_world(ingame, path.to_string_lossy().into_owned())?;
 			Err(GameError::new("".to_string()))?
 		})
 	}
@@ -169,11 +505,9 @@ pub fn load_world_cmd(keyword: String) -> Command {
 
 /// Create a very simple worly
 pub fn empty_world(player_name: &str, world_name: &str) -> Box<Storage> {
-	let player = Actor {
-		id: "player-actor".to_string(),
-		name: player_name.into(),
-		description: "You".to_string()
-	};
+	let player = Actor::new("player-actor")
+		.with_name(player_name)
+		.with_description("You");
 	let base_game = BaseGame {
 		player: "player-actor".to_string()
 	};
@@ -191,13 +525,87 @@ pub fn empty_world(player_name: &str, world_name: &str) -> Box<Storage> {
 pub fn gen_empty_world_cmd(keyword: String) -> Command {
 		Command {
 		keyword: keyword,
-		action_fn: Box::new(move |_, _ | {
-			let player_name = line_input("Player name: ")?;
-			let world_name = line_input("World name: ")?;
+		action_fn: Box::new(move |ingame, _ | {
+			let player_name = line_input(
+					format!("{}: ", ingame.msg("genesis.player_name", &[])))?;
+			let world_name = line_input(
+					format!("{}: ", ingame.msg("genesis.world_name", &[])))?;
 			Ok(Box::new(move |mut ingame, _ | {
 				let world = empty_world(&player_name, &world_name);
 				ingame.insert_item_in_player_room(world)
 			}))
 		})
 	}
-}
\ No newline at end of file
+}
+
+#[test]
+fn world_path_test() {
+	use std::env;
+	let root = env::temp_dir().join("rustesge-world-path-test");
+	fs::create_dir_all(&root).unwrap();
+	let canonical_root = fs::canonicalize(&root).unwrap();
+	let ingame = Ingame::new("paths");
+	assert_eq!(canonical_root.join("world"), world_path(&ingame, &root, "world").unwrap());
+	assert!(world_path(&ingame, &root, "").is_err());
+	assert!(world_path(&ingame, &root, "../world").is_err());
+	assert!(world_path(&ingame, &root, "a/../../world").is_err());
+	assert!(world_path(&ingame, &root, "/etc/passwd").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn world_path_symlink_test() {
+	use std::env;
+	use std::os::unix::fs::symlink;
+	let base = env::temp_dir().join("rustesge-world-path-symlink-test");
+	let _ = fs::remove_dir_all(&base);
+	let root = base.join("root");
+	let outside = base.join("outside");
+	fs::create_dir_all(&root).unwrap();
+	fs::create_dir_all(&outside).unwrap();
+	fs::File::create(outside.join("world")).unwrap();
+	symlink(&outside, root.join("escape")).unwrap();
+	symlink(outside.join("world"), root.join("link")).unwrap();
+	fs::create_dir_all(root.join("inside")).unwrap();
+	symlink(root.join("inside"), root.join("alias")).unwrap();
+	let ingame = Ingame::new("paths");
+	assert!(world_path(&ingame, &root, "escape").is_err());
+	assert!(world_path(&ingame, &root, "escape/world").is_err());
+	assert!(world_path(&ingame, &root, "escape/new").is_err());
+	assert!(world_path(&ingame, &root, "link").is_err());
+	assert!(world_path(&ingame, &root, "alias/world").is_ok());
+}
+
+#[test]
+fn save_world_test() {
+	use std::env;
+	let root = env::temp_dir().join("rustesge-save-world-test");
+	fs::create_dir_all(&root).unwrap();
+	let ingame = Ingame::with_storage(initial_genesis("God"));
+	let path = world_path(&ingame, &root, "world").unwrap();
+	save_world(&ingame, path.to_string_lossy().into_owned()).unwrap();
+	assert!(path.exists());
+	assert!(fs::read_dir(&root).unwrap()
+			.all(|entry| !entry.unwrap().path().to_string_lossy().ends_with(".tmp")));
+	let mut loaded = Ingame::new("empty");
+	load_world(&mut loaded, path.to_string_lossy().into_owned()).unwrap();
+	assert!(loaded.get_player().is_ok());
+}
+
+#[test]
+fn dig_test() {
+	use response::MessageKind;
+	let mut ingame = Ingame::with_storage(initial_genesis("God"));
+	ingame.add_one_time_action(gen_dig_action("north", "Dark Cave"));
+	ingame.step();
+	assert_eq!("", ingame.response().text(&MessageKind::Error));
+	let room = ingame.room_of_player().unwrap();
+	assert_eq!("dark-cave", room.id);
+	assert_eq!("Dark Cave", room.name);
+	assert_eq!(Some(&"genesis-room".to_string()), room.exits.get("south"));
+	let genesis_room = ingame.get_item::<Room>("genesis-room").unwrap();
+	assert_eq!(Some(&"dark-cave".to_string()), genesis_room.exits.get("north"));
+	ingame.add_one_time_action(gen_dig_action("up", "Dark Cave"));
+	ingame.step();
+	assert_eq!("dark-cave-2", ingame.room_of_player().unwrap().id);
+}
@@ -0,0 +1,316 @@
+#![warn(missing_docs)]
+
+//! Items carried by actors: take, drop and use.
+
+use core::{Ingame, MutIngame, Item, Action, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use room::Room;
+use container::Container;
+use script::{ON_TAKE, ON_USE};
+use terminal::Command;
+use parser::Verb;
+
+impl Ingame {
+	/// Get the name of an item, the id if it has no name.
+	pub fn item_name(&self, item_id: &str) -> String {
+		self.get_item::<Item>(item_id)
+			.map(|item| item.meta_text_or_default("name", item_id).to_string())
+			.unwrap_or(item_id.to_string())
+	}
+
+	/// Find the id of an item in a list of ids by its id, name or nouns.
+	///
+	/// The name is compared case insensitive.
+	pub fn find_item_in(&self, item_ids: &[String], name: &str) -> Option<String> {
+		let name = name.trim().to_lowercase();
+		item_ids.iter()
+			.find(|id| id.to_lowercase() == name ||
+					self.item_name(id).to_lowercase() == name ||
+					self.get_item::<Item>(id)
+						.map(|item| item.meta_textvec_or_default("nouns", &[]).iter()
+							.any(|noun| noun.to_lowercase() == name))
+						.unwrap_or(false))
+			.cloned()
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Move an item from the room of the actor into its inventory.
+	///
+	/// The item may also be inside an open container in the room or in the
+	/// inventory.  Runs the `on_take` script of the item which can cancel it.
+	///
+	/// # Failure
+	/// Error if the item is not in the room.
+	pub fn take_item(&mut self, actor: &Actor, item_id: &str) -> GameResult<()> {
+		let room = self.ingame.room_of_actor(actor)?;
+		let container = if room.items.iter().any(|x| x == item_id) {
+			None
+		} else {
+			let mut around = room.items.clone();
+			around.extend(actor.items.iter().cloned());
+			let container = self.ingame.container_of(&around, item_id)
+					.ok_or(berr(self.ingame.msg("item.not_here",
+							&[("item", &self.ingame.item_name(item_id))])))?;
+			Some(container)
+		};
+		let vars = [("actor", actor.id.as_str()), ("item", item_id),
+				("room", room.id.as_str())];
+		if self.run_trigger(item_id, ON_TAKE, &vars)? == Some(false) {
+			return Ok(())
+		}
+		let mut room = self.ingame.room_of_actor(actor)?;
+		let mut actor = self.get_item::<Actor>(&actor.id)
+				.ok_or(berr(self.ingame.msg("actor.not_found", &[("actor", &actor.id)])))?;
+		match container {
+			Some(container_id) => {
+				let mut container = self.get_item::<Container>(&container_id)
+						.ok_or(berr(self.ingame.msg("container.not_found",
+								&[("container", &container_id)])))?;
+				container.items.retain(|x| x != item_id);
+				self.insert_item(container);
+			},
+			None => {
+				room.items.retain(|x| x != item_id);
+				self.insert_item(room);
+			}
+		}
+		actor.items.push(item_id.to_string());
+		self.insert_item(actor);
+		let msg = self.ingame.msg("item.taken", &[("item", &self.ingame.item_name(item_id))]);
+		self.add_message(MessageKind::Output, item_id, &msg);
+		Ok(())
+	}
+
+	/// Remove an item from all rooms, containers and inventories.
+	pub fn detach_item(&mut self, item_id: &str) {
+		let rooms: Vec<Box<Room>> = self.ingame.all_of_type::<Room>()
+			.filter(|room| room.items.iter().any(|x| x == item_id))
+			.collect();
+		for mut room in rooms {
+			room.items.retain(|x| x != item_id);
+			self.insert_item(room);
+		}
+		let containers: Vec<Box<Container>> = self.ingame.all_of_type::<Container>()
+			.filter(|container| container.items.iter().any(|x| x == item_id))
+			.collect();
+		for mut container in containers {
+			container.items.retain(|x| x != item_id);
+			self.insert_item(container);
+		}
+		let actors: Vec<Box<Actor>> = self.ingame.all_of_type::<Actor>()
+			.filter(|actor| actor.carries(item_id))
+			.collect();
+		for mut actor in actors {
+			actor.items.retain(|x| x != item_id);
+			self.insert_item(actor);
+		}
+	}
+
+	/// Move an item from the inventory of the actor into its room.
+	///
+	/// # Failure
+	/// Error if the actor doesn't carry the item.
+	pub fn drop_item(&mut self, actor: &Actor, item_id: &str) -> GameResult<()> {
+		let mut actor = self.get_item::<Actor>(&actor.id)
+				.ok_or(berr(self.ingame.msg("actor.not_found", &[("actor", &actor.id)])))?;
+		if !actor.carries(item_id) {
+			return Err(berr(self.ingame.msg("item.not_carried",
+					&[("item", &self.ingame.item_name(item_id))])))
+		}
+		let mut room = self.ingame.room_of_actor(&actor)?;
+		actor.items.retain(|x| x != item_id);
+		room.items.push(item_id.to_string());
+		self.insert_item(room);
+		self.insert_item(actor);
+		let msg = self.ingame.msg("item.dropped", &[("item", &self.ingame.item_name(item_id))]);
+		self.add_message(MessageKind::Output, item_id, &msg);
+		Ok(())
+	}
+
+	/// Use an item which the actor carries or which is in its room.
+	///
+	/// Runs the `on_use` script of the item.
+	///
+	/// # Failure
+	/// Error if the item is not available or if it has no use.
+	pub fn use_item(&mut self, actor: &Actor, item_id: &str) -> GameResult<()> {
+		let room = self.ingame.room_of_actor(actor)?;
+		if !actor.carries(item_id) && !room.items.iter().any(|x| x == item_id) {
+			return Err(berr(self.ingame.msg("item.not_here",
+					&[("item", &self.ingame.item_name(item_id))])))
+		}
+		let vars = [("actor", actor.id.as_str()), ("item", item_id),
+				("room", room.id.as_str())];
+		match self.run_trigger(item_id, ON_USE, &vars)? {
+			Some(_) => Ok(()),
+			None => Err(berr(self.ingame.msg("item.nothing_happens", &[])))
+		}
+	}
+
+	/// Print the inventory of the player to out.
+	pub fn display_inventory(&mut self) -> GameResult<()> {
+		let player = self.ingame.get_player()?;
+		if player.items.is_empty() {
+			let msg = self.ingame.msg("inventory.empty", &[]);
+			self.add_message(MessageKind::Output, &player.id, &msg);
+			return Ok(())
+		}
+		let names: Vec<String> = player.items.iter()
+			.map(|id| self.ingame.item_name(id))
+			.collect();
+		let msg = self.ingame.msg("inventory.list", &[("items", &names.join(", "))]);
+		self.add_message(MessageKind::Output, &player.id, &msg);
+		Ok(())
+	}
+}
+
+/// Creates an Action which lets the player take an item from the room.
+///
+/// Items in open containers in the room are found too.
+pub fn gen_take_action(name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let room = ingame.ingame.room_of_player()?;
+		let items = ingame.ingame.reachable_items(&room.items);
+		let item_id = ingame.ingame.find_item_in(&items, &name)
+				.ok_or(berr(ingame.ingame.msg("item.not_here", &[("item", &name)])))?;
+		ingame.take_item(&player, &item_id)
+	})
+}
+
+/// Creates an Action which lets the player drop an item.
+pub fn gen_drop_action(name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let item_id = ingame.ingame.find_item_in(&player.items, &name)
+				.ok_or(berr(ingame.ingame.msg("item.not_carried", &[("item", &name)])))?;
+		ingame.drop_item(&player, &item_id)
+	})
+}
+
+/// Creates an Action which lets the player use an item.
+pub fn gen_use_action(name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let room = ingame.ingame.room_of_player()?;
+		let item_id = ingame.ingame.find_item_in(&player.items, &name)
+			.or(ingame.ingame.find_item_in(&room.items, &name))
+			.ok_or(berr(ingame.ingame.msg("item.not_here", &[("item", &name)])))?;
+		ingame.use_item(&player, &item_id)
+	})
+}
+
+/// Verb which takes items, `take <items> [from <container>]`.
+pub fn gen_take_verb<S: Into<String>>(keyword: S) -> Verb {
+	Verb {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, parsed| {
+			let items = parsed.expect_direct(ingame)?;
+			let container = match parsed.preposition {
+				Some(_) => Some(parsed.expect_indirect(ingame, &["from"])?),
+				None => None
+			};
+			Ok(Box::new(move |ingame, _| {
+				let player = ingame.ingame.get_player()?;
+				for item_id in &items {
+					if let Some(ref container) = container {
+						let inside = ingame.get_item::<Container>(container)
+							.map(|x| x.items.contains(item_id))
+							.unwrap_or(false);
+						if !inside {
+							return Err(berr(ingame.ingame.msg("container.not_in",
+									&[("item", &ingame.ingame.item_name(item_id)),
+									("container", &ingame.ingame.item_name(container))])))
+						}
+					}
+					ingame.take_item(&player, item_id)?;
+				}
+				Ok(())
+			}))
+		})
+	}
+}
+
+/// Verb which drops items, `drop <items>`.
+pub fn gen_drop_verb<S: Into<String>>(keyword: S) -> Verb {
+	Verb {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, parsed| {
+			let items = parsed.expect_direct(ingame)?;
+			Ok(Box::new(move |ingame, _| {
+				let player = ingame.ingame.get_player()?;
+				for item_id in &items {
+					ingame.drop_item(&player, item_id)?;
+				}
+				Ok(())
+			}))
+		})
+	}
+}
+
+/// Verb which uses items, `use <items>`.
+pub fn gen_use_verb<S: Into<String>>(keyword: S) -> Verb {
+	Verb {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, parsed| {
+			let items = parsed.expect_direct(ingame)?;
+			Ok(Box::new(move |ingame, _| {
+				let player = ingame.ingame.get_player()?;
+				for item_id in &items {
+					ingame.use_item(&player, item_id)?;
+				}
+				Ok(())
+			}))
+		})
+	}
+}
+
+/// Command which lists the items of the player.
+pub fn gen_inventory_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|_, _ | {
+			Ok(Box::new(|ingame, _| ingame.display_inventory()))
+		})
+	}
+}
+
+#[test]
+fn inventory_test() {
+	use core::{Storage, Meta};
+	use room::Room;
+	use base::BaseGame;
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	room.items.push("lamp".to_string());
+	room.items.push("statue".to_string());
+	let mut lamp = Item::new("thing".to_string(), "lamp".to_string());
+	lamp.item_meta.insert("name".to_string(), Meta::Text("Lamp".to_string()));
+	lamp.item_meta.insert("on_use".to_string(),
+			Meta::Text("print(\"The lamp shines.\")".to_string()));
+	let mut statue = Item::new("thing".to_string(), "statue".to_string());
+	statue.item_meta.insert("on_take".to_string(),
+			Meta::Text("print(\"Too heavy.\"); cancel()".to_string()));
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room)
+		.with_item(lamp)
+		.with_item(statue);
+	let mut ingame = Ingame::with_storage(storage);
+	ingame.add_one_time_action(gen_take_action("statue".to_string()));
+	ingame.step();
+	assert_eq!("Too heavy.", ingame.response().text(&MessageKind::Output));
+	ingame.add_one_time_action(gen_take_action("lamp".to_string()));
+	ingame.step();
+	assert_eq!("Taken: Lamp", ingame.response().text(&MessageKind::Output));
+	assert!(ingame.get_player().unwrap().carries("lamp"));
+	ingame.add_one_time_action(gen_use_action("lamp".to_string()));
+	ingame.step();
+	assert_eq!("The lamp shines.", ingame.response().text(&MessageKind::Output));
+	ingame.add_one_time_action(gen_use_action("statue".to_string()));
+	ingame.step();
+	assert_eq!("Nothing happens", ingame.response().text(&MessageKind::Error));
+}
@@ -6,6 +6,28 @@ pub mod base;
 pub mod terminal;
 pub mod genesis;
 pub mod terminal_genesis;
+pub mod pathfinding;
+pub mod map;
+pub mod dialogue;
+pub mod behaviour;
+pub mod attribute;
+pub mod random;
+pub mod combat;
+pub mod script;
+pub mod inventory;
+pub mod rules;
+pub mod quest;
+pub mod container;
+pub mod thing;
+pub mod parser;
+pub mod locale;
+pub mod markup;
+pub mod response;
+pub mod util;
+pub mod worldfile;
+pub mod worlddir;
+pub mod backend;
+pub mod prefab;
 
 #[cfg(test)]
 mod tests {
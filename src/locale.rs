@@ -0,0 +1,403 @@
+#![warn(missing_docs)]
+
+//! Message catalogs to translate the texts of the engine.
+//!
+//! Every message shown to the player is looked up by a key like
+//! `room.title` and may contain placeholders like `{name}` which are
+//! replaced by the arguments.  The English messages are built in, other
+//! languages are loaded from language files at startup and the world
+//! chooses its language by the `language` item.  Missing messages fall back
+//! to English.
+//!
+//! A language file is named after its language, like `de.lang`, and
+//! contains one message per line:
+//!
+//! ```text
+//! # Comments start with a hash
+//! room.title = Raum: {name}
+//! ```
+//!
+//! `\n` in a message stands for a line break.
+
+use core::{Ingame, MutIngame, Item, Meta, Itemizeable, GameResult, berr};
+use markup::escape;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// The language used if the world doesn't choose one.
+pub const DEFAULT_LANGUAGE: &'static str = "en";
+
+/// File extension of the language files.
+pub const LANGUAGE_FILE_EXTENSION: &'static str = "lang";
+
+/// The built in English messages by their keys.
+pub const ENGLISH: &'static [(&'static str, &'static str)] = &[
+	// Terminal and commands
+	("cmd.empty", "Keywords are empty"),
+	("cmd.not_found", "Could not find command '{command}'"),
+	("cmd.nothing_to_repeat", "There is nothing to repeat"),
+	("cmd.error", "Error: {error}"),
+	("cmd.commands", "Commands: {count}"),
+	("cmd.expected_one", "Expected one argument"),
+	("cmd.expected_two", "Expected two arguments"),
+	("cmd.expected_one_or_two", "Expected one or two arguments"),
+	("cmd.expected_at_least_two", "Expected at least two arguments"),
+	("cmd.expected_number", "Expected a number"),
+	("cmd.which_direction", "Which direction?"),
+	("game.no_player", "The world has no player"),
+	// Rooms and exits
+	("room.title", "Room: {name}"),
+	("room.items", "Items: {items}"),
+	("room.actors", "Actors: {actors}"),
+	("room.directions", "Directions: {directions}"),
+	("room.not_found", "Could not find the room {room}"),
+	("exit.not_found", "Could not find exit"),
+	("exit.closed", "The exit {exit} is closed"),
+	("exit.locked", "The exit {exit} is locked"),
+	("actor.leaves", "{actor} leaves {exit}."),
+	("actor.arrives", "{actor} arrives."),
+	("actor.not_found", "Could not find the actor {actor}"),
+	("actor.no_room", "{actor} is in no room"),
+	// Genesis
+	("genesis.exit_exists", "Exit {exit} already exists"),
+	("genesis.exit_not_found", "Could not find exit {exit}"),
+	("genesis.unknown_direction", "Unknown direction: {direction}"),
+	("genesis.unknown_phase", "Unknown phase: {phase}"),
+	("genesis.unknown_language", "Unknown language: {language}"),
+	("genesis.write_text", "Write a multiline text, terminate with {term}"),
+	("genesis.write_script", "Write the script, terminate with {term}"),
+	("genesis.condition", "Condition (empty for always)"),
+	("genesis.world_name", "World name"),
+	("genesis.player_name", "Player name"),
+	("genesis.world_name_empty", "World name is empty"),
+	("genesis.invalid_world_name", "Invalid world name: {name}"),
+	("genesis.world_outside", "World is outside of the world directory: {name}"),
+	// Items and inventory
+	("item.not_here", "There is no {item} here"),
+	("item.not_carried", "You don't have {item}"),
+	("item.taken", "Taken: {item}"),
+	("item.dropped", "Dropped: {item}"),
+	("item.nothing_happens", "Nothing happens"),
+	("item.nothing_special", "You see nothing special about {item}."),
+	("item.not_found", "Could not find the item {item}"),
+	("inventory.empty", "You carry nothing."),
+	("inventory.list", "You carry: {items}"),
+	// Containers
+	("container.not_here", "There is no container {container} here"),
+	("container.not_found", "Could not find the container {container}"),
+	("container.not_in", "There is no {item} in {container}"),
+	("container.closed", "The {container} is closed"),
+	("container.already_open", "The {container} is already open"),
+	("container.already_closed", "The {container} is already closed"),
+	("container.opened", "You open the {container}."),
+	("container.closed_now", "You close the {container}."),
+	("container.put", "You put {item} in {container}."),
+	("container.cannot_put", "You cannot put {item} into {container}"),
+	("container.cannot_put_anything", "You cannot put anything into {container}"),
+	("container.cannot", "You cannot {verb} {item}"),
+	("container.cannot_look_into", "You cannot look into {item}"),
+	("container.look_what", "Look {preposition} what?"),
+	("container.empty", "The {container} is empty."),
+	("container.contains", "The {container} contains: {items}"),
+	("container.expected_put", "Expected: put <item> in <container>"),
+	("container.expected_look_in", "Expected: look in <container>"),
+	// Parser
+	("parser.what", "What do you want to {verb}?"),
+	("parser.where", "Where do you want to {verb} it, {prepositions}?"),
+	("parser.which", "Which {phrase} do you mean: {names}?"),
+	("parser.or", "or"),
+	("parser.unknown_pronoun", "I don't know what \"{pronoun}\" refers to"),
+	("parser.gone", "You can't see {item} any more"),
+	// Pathfinding
+	("path.no_way", "No way found to {room}"),
+	("path.already_there", "You are already there."),
+	("path.path", "Path: {path}"),
+	// Maps
+	("map.builder_only", "Only builders can see the hidden exits"),
+	// Dialogues
+	("dialogue.line", "{actor}: {text}"),
+	("dialogue.nothing_to_say", "{actor} has nothing to say"),
+	("dialogue.not_talking", "You are not talking to anyone"),
+	("dialogue.no_choice", "There is no choice {number}"),
+	("dialogue.not_found", "Could not find the dialogue {dialogue}"),
+	// Attributes
+	("attribute.effect_ended", "You are no longer {effect}."),
+	("attribute.status", "Status of {actor}"),
+	("attribute.value", "{attribute}: {value} ({min}..{max})"),
+	("attribute.effect", "{effect}: {attribute} {modifier} for {steps} steps"),
+	// Combat
+	("combat.disabled", "Combat is not enabled"),
+	("combat.attack_self", "You cannot attack yourself"),
+	("combat.cannot_attack", "You cannot attack {target}"),
+	("combat.cannot_fight", "{actor} cannot fight"),
+	("combat.player_hits", "You hit {target} for {damage} damage."),
+	("combat.hits_player", "{attacker} hits you for {damage} damage."),
+	("combat.hits", "{attacker} hits {target} for {damage} damage."),
+	("combat.player_misses", "You miss {target}."),
+	("combat.misses_player", "{attacker} misses you."),
+	("combat.misses", "{attacker} misses {target}."),
+	("combat.dies", "{actor} dies."),
+	("combat.respawn", "You die... and wake up again."),
+	("combat.game_over", "You die. Game over."),
+	// Quests
+	("quest.visit", "Visit {room}"),
+	("quest.obtain", "Obtain {item}"),
+	("quest.talk", "Talk to {actor}"),
+	("quest.new", "New quest: {quest}"),
+	("quest.updated", "Quest updated: {quest}"),
+	("quest.completed", "Quest completed: {quest}"),
+	("quest.reward", "You receive: {item}"),
+	("quest.none", "You have no quests."),
+	("quest.done", "{quest} (completed)"),
+	// Prefabs
+	("prefab.invalid", "{prefab} is not a prefab")
+];
+
+/// Replace the placeholders in the template by the arguments.
+///
+/// Placeholders without argument are kept.  The template is read once from
+/// left to right, so placeholders in the arguments are not replaced.
+pub fn fill(template: &str, args: &[(&str, &str)]) -> String {
+	let mut res = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		res.push_str(&rest[..start]);
+		let after = &rest[start + 1..];
+		match after.find(|c| c == '{' || c == '}') {
+			Some(end) if after[end..].starts_with('}') => {
+				let name = &after[..end];
+				match args.iter().find(|&&(arg, _)| arg == name) {
+					Some(&(_, value)) => res.push_str(value),
+					None => res.push_str(&rest[start..start + end + 2])
+				}
+				rest = &after[end + 1..];
+			},
+			_ => {
+				res.push('{');
+				rest = after;
+			}
+		}
+	}
+	res.push_str(rest);
+	res
+}
+
+/// Get the built in English message of the key.
+pub fn english(key: &str) -> Option<&'static str> {
+	ENGLISH.iter()
+		.find(|&&(name, _)| name == key)
+		.map(|&(_, text)| text)
+}
+
+/// The messages of one language.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Catalog {
+	/// Code of the language, like "en" or "de".
+	pub language: String,
+	/// The messages by their keys.
+	pub messages: HashMap<String, String>
+}
+
+impl Catalog {
+	/// Create an empty catalog.
+	pub fn new<S: Into<String>>(language: S) -> Self {
+		Catalog {
+			language: language.into(),
+			messages: HashMap::new()
+		}
+	}
+
+	/// Consumes the catalog and returns new one with the added message.
+	///
+	/// Use for initialization.
+	pub fn with_message<S: Into<String>>(mut self, key: S, text: S) -> Self {
+		self.messages.insert(key.into(), text.into());
+		self
+	}
+
+	/// Read the messages of a language file.
+	///
+	/// # Failure
+	/// Error with the line number if a line is not a comment, empty or
+	/// `key = text`.
+	pub fn parse<S: Into<String>>(language: S, source: &str) -> GameResult<Self> {
+		let mut catalog = Catalog::new(language);
+		for (i, line) in source.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue
+			}
+			let separator = line.find('=')
+				.ok_or(berr(format!("Line {}: expected key = text", i + 1)))?;
+			let key = line[..separator].trim();
+			if key.is_empty() {
+				return Err(berr(format!("Line {}: the key is empty", i + 1)))
+			}
+			let text = line[separator + 1..].trim().replace("\\n", "\n");
+			catalog.messages.insert(key.to_string(), text);
+		}
+		Ok(catalog)
+	}
+
+	/// Load a language file, the language is the name of the file.
+	///
+	/// # Failure
+	/// Error if the file could not be read or parsed.
+	pub fn load<P: AsRef<Path>>(path: P) -> GameResult<Self> {
+		let path = path.as_ref();
+		let language = path.file_stem()
+			.ok_or(berr(format!("Invalid language file: {}", path.display())))?
+			.to_string_lossy()
+			.into_owned();
+		let mut source = String::new();
+		fs::File::open(path)?.read_to_string(&mut source)?;
+		match Catalog::parse(language, &source) {
+			Ok(catalog) => Ok(catalog),
+			Err(err) => Err(berr(format!("{}: {}", path.display(), err)))
+		}
+	}
+
+	/// Get the message of the key.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.messages.get(key).map(|x| x.as_str())
+	}
+}
+
+/// Load all language files of a directory.
+///
+/// # Failure
+/// Error if the directory or a language file could not be read.
+pub fn load_catalogs<P: AsRef<Path>>(dir: P) -> GameResult<Vec<Catalog>> {
+	let mut paths = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.extension().map(|x| x == LANGUAGE_FILE_EXTENSION).unwrap_or(false) {
+			paths.push(path);
+		}
+	}
+	paths.sort();
+	paths.iter().map(Catalog::load).collect()
+}
+
+/// The language chosen by the world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Language {
+	/// Code of the language.
+	pub language: String
+}
+
+impl Itemizeable for Language {
+	fn item_type() -> Option<&'static str> {
+		Some("language")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "language" {
+			None
+		} else {
+			Some(Box::new(Language {
+				language: item.meta_text_or_default("language", DEFAULT_LANGUAGE).to_string()
+			}))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("language".to_string(), "language".to_string());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		item.item_meta.insert("language".to_string(), Meta::Text(self.language.clone()));
+	}
+	fn get_id(&self) -> &str {
+		"language"
+	}
+}
+
+impl Ingame {
+	/// Get the language chosen by the world.
+	pub fn language(&self) -> String {
+		self.get_item::<Language>("language")
+			.map(|x| x.language)
+			.unwrap_or(DEFAULT_LANGUAGE.to_string())
+	}
+
+	/// Check if messages of the language are available.
+	pub fn has_language(&self, language: &str) -> bool {
+		language == DEFAULT_LANGUAGE || self.catalog(language).is_some()
+	}
+
+	/// Get the message of the key in the language of the world.
+	///
+	/// Falls back to English and at last to the key itself.  The arguments
+	/// are escaped, so names can't contain markup.
+	pub fn msg(&self, key: &str, args: &[(&str, &str)]) -> String {
+		let escaped: Vec<(&str, String)> = args.iter()
+			.map(|&(name, value)| (name, escape(value)))
+			.collect();
+		let args: Vec<(&str, &str)> = escaped.iter()
+			.map(|&(name, ref value)| (name, value.as_str()))
+			.collect();
+		self.msg_markup(key, &args)
+	}
+
+	/// Get the message of the key like `msg`, but the arguments are markup.
+	pub fn msg_markup(&self, key: &str, args: &[(&str, &str)]) -> String {
+		let text = self.catalog(&self.language())
+			.and_then(|catalog| catalog.get(key))
+			.or(self.catalog(DEFAULT_LANGUAGE).and_then(|catalog| catalog.get(key)))
+			.or(english(key))
+			.unwrap_or(key);
+		fill(text, args)
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Choose the language of the world.
+	///
+	/// # Failure
+	/// Error if there are no messages for the language.
+	pub fn set_language(&mut self, language: &str) -> GameResult<()> {
+		if !self.ingame.has_language(language) {
+			return Err(berr(self.ingame.msg("genesis.unknown_language",
+					&[("language", language)])))
+		}
+		self.insert_item(Box::new(Language { language: language.to_string() }));
+		Ok(())
+	}
+}
+
+#[test]
+fn locale_test() {
+	use core::Storage;
+	let german = Catalog::parse("de", "# German\nroom.title = Raum: {name}\n\n\
+			actor.arrives = {actor} kommt an.\\n").unwrap();
+	assert_eq!(Some("Raum: {name}"), german.get("room.title"));
+	assert_eq!(Some("{actor} kommt an.\n"), german.get("actor.arrives"));
+	assert_eq!("Line 2: expected key = text",
+			Catalog::parse("de", "a = b\nbroken").unwrap_err().to_string());
+
+	let mut ingame = Ingame::with_storage(Storage::new("storage"));
+	ingame.add_catalog(german);
+	assert_eq!("Room: Hall", ingame.msg("room.title", &[("name", "Hall")]));
+	assert_eq!("unknown.key", ingame.msg("unknown.key", &[]));
+	assert_eq!("Room: [[em]Hall", ingame.msg("room.title", &[("name", "[em]Hall")]));
+	assert_eq!("Room: [em]Hall", ingame.msg_markup("room.title", &[("name", "[em]Hall")]));
+	assert_eq!("{b} and x, {c} {x",
+			fill("{a} and {b}, {c} {{b}", &[("a", "{b}"), ("b", "x")]));
+	{
+		let mut mut_ingame = MutIngame { ingame: &mut ingame };
+		assert!(mut_ingame.set_language("fr").is_err());
+		mut_ingame.set_language("de").unwrap();
+	}
+	assert_eq!("de", ingame.language());
+	assert_eq!("Raum: Hall", ingame.msg("room.title", &[("name", "Hall")]));
+	assert_eq!("Taken: Lamp", ingame.msg("item.taken", &[("item", "Lamp")]));
+
+	let shipped = load_catalogs(concat!(env!("CARGO_MANIFEST_DIR"), "/locales")).unwrap();
+	for catalog in shipped {
+		for &(key, _) in ENGLISH {
+			assert!(catalog.get(key).is_some(), "{} misses {}", catalog.language, key);
+		}
+	}
+}
@@ -0,0 +1,359 @@
+#![warn(missing_docs)]
+
+//! Export the structure of a world as Graphviz DOT graph or ASCII map.
+
+use core::{Storage, Item, Action, GameError};
+use response::MessageKind;
+use markup::escape;
+use actor::Actor;
+use room::{Room, ExitState};
+use terminal::Command;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Compass directions and their offset on the ASCII map.
+pub const COMPASS_DIRECTIONS: &'static [(&'static str, i32, i32)] = &[
+	("north", 0, -1),
+	("south", 0, 1),
+	("east", 1, 0),
+	("west", -1, 0),
+	("northeast", 1, -1),
+	("northwest", -1, -1),
+	("southeast", 1, 1),
+	("southwest", -1, 1)
+];
+
+const CELL_WIDTH: usize = 7;
+
+fn compass_offset(direction: &str) -> Option<(i32, i32)> {
+	COMPASS_DIRECTIONS.iter()
+		.find(|&&(name, _, _)| name == direction)
+		.map(|&(_, x, y)| (x, y))
+}
+
+fn escape_dot(text: &str) -> String {
+	text.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+fn sorted_rooms(storage: &Storage) -> Vec<Box<Room>> {
+	let mut rooms: Vec<Box<Room>> = storage.all_of_type::<Room>().collect();
+	rooms.sort_by(|a, b| a.id.cmp(&b.id));
+	rooms
+}
+
+fn sorted_exits(room: &Room) -> Vec<(&String, &String)> {
+	let mut exits: Vec<(&String, &String)> = room.exits.iter().collect();
+	exits.sort();
+	exits
+}
+
+fn room_title(room: &Room) -> &str {
+	if room.name.is_empty() { &room.id } else { &room.name }
+}
+
+fn actor_names(storage: &Storage, room: &Room) -> Vec<String> {
+	room.actors.iter()
+		.map(|id| storage.get_item::<Actor>(id)
+			.map(|actor| actor.name.clone())
+			.unwrap_or(id.clone()))
+		.collect()
+}
+
+fn item_names(storage: &Storage, room: &Room) -> Vec<String> {
+	room.items.iter()
+		.map(|id| storage.get_item::<Item>(id)
+			.map(|item| item.meta_text_or_default("name", id).to_string())
+			.unwrap_or(id.clone()))
+		.collect()
+}
+
+/// Collect the rooms which can be reached from the start room without
+/// hidden exits.
+fn reachable_rooms(storage: &Storage, start_room: &str) -> HashSet<String> {
+	let mut reached: HashSet<String> = HashSet::new();
+	let mut queue: VecDeque<String> = VecDeque::new();
+	reached.insert(start_room.to_string());
+	queue.push_back(start_room.to_string());
+	while let Some(room_id) = queue.pop_front() {
+		let room = match storage.get_item::<Room>(&room_id) {
+			Some(room) => room,
+			None => continue
+		};
+		for (exit, dest) in sorted_exits(&room) {
+			if room.exit_state(exit) != ExitState::Hidden && reached.insert(dest.clone()) {
+				queue.push_back(dest.clone());
+			}
+		}
+	}
+	reached
+}
+
+/// Export the rooms and their exits as Graphviz DOT graph.
+///
+/// Actors and items are added to the label of the room.  Exits which are
+/// not open are dashed and labelled with their state.  The builder view
+/// shows all rooms and the hidden exits, otherwise only the rooms which can
+/// be reached from the start room without hidden exits are shown.
+pub fn dot_map(storage: &Storage, start_room: &str, builder: bool) -> String {
+	let mut res = String::new();
+	res.push_str("digraph world {\n");
+	let mut rooms = sorted_rooms(storage);
+	if !builder {
+		let reachable = reachable_rooms(storage, start_room);
+		rooms.retain(|room| reachable.contains(&room.id));
+	}
+	for room in &rooms {
+		let mut label = escape_dot(room_title(room));
+		let actors = actor_names(storage, room);
+		if !actors.is_empty() {
+			label.push_str("\\nActors: ");
+			label.push_str(&escape_dot(&actors.join(", ")));
+		}
+		let items = item_names(storage, room);
+		if !items.is_empty() {
+			label.push_str("\\nItems: ");
+			label.push_str(&escape_dot(&items.join(", ")));
+		}
+		res.push_str(&format!("\t\"{}\" [label=\"{}\"];\n",
+				escape_dot(&room.id), label));
+	}
+	for room in &rooms {
+		for (exit, dest) in sorted_exits(room) {
+			let state = room.exit_state(exit);
+			if state == ExitState::Hidden && !builder {
+				continue
+			}
+			if state == ExitState::Open {
+				res.push_str(&format!("\t\"{}\" -> \"{}\" [label=\"{}\"];\n",
+						escape_dot(&room.id), escape_dot(dest), escape_dot(exit)));
+			} else {
+				res.push_str(&format!(
+						"\t\"{}\" -> \"{}\" [label=\"{} ({})\", style=dashed];\n",
+						escape_dot(&room.id), escape_dot(dest), escape_dot(exit),
+						state.as_str()));
+			}
+		}
+	}
+	res.push_str("}\n");
+	res
+}
+
+/// Place the rooms reachable by compass directions on a grid.
+///
+/// Hidden exits are not followed.
+fn layout(storage: &Storage, start_room: &str) -> HashMap<String, (i32, i32)> {
+	let mut positions: HashMap<String, (i32, i32)> = HashMap::new();
+	let mut occupied: HashMap<(i32, i32), String> = HashMap::new();
+	let mut queue: VecDeque<String> = VecDeque::new();
+	positions.insert(start_room.to_string(), (0, 0));
+	occupied.insert((0, 0), start_room.to_string());
+	queue.push_back(start_room.to_string());
+	while let Some(room_id) = queue.pop_front() {
+		let room = match storage.get_item::<Room>(&room_id) {
+			Some(room) => room,
+			None => continue
+		};
+		let (x, y) = positions[&room_id];
+		for (exit, dest) in sorted_exits(&room) {
+			if room.exit_state(exit) == ExitState::Hidden {
+				continue
+			}
+			let (dx, dy) = match compass_offset(exit) {
+				Some(offset) => offset,
+				None => continue
+			};
+			let pos = (x + dx, y + dy);
+			if positions.contains_key(dest) || occupied.contains_key(&pos) {
+				continue
+			}
+			if storage.get_item::<Room>(dest).is_none() {
+				continue
+			}
+			positions.insert(dest.clone(), pos);
+			occupied.insert(pos, dest.clone());
+			queue.push_back(dest.clone());
+		}
+	}
+	positions
+}
+
+fn put_char(lines: &mut Vec<Vec<char>>, line: usize, column: usize, c: char) {
+	let current = lines[line][column];
+	lines[line][column] = match (current, c) {
+		('/', '\\') | ('\\', '/') => 'X',
+		_ => c
+	};
+}
+
+/// Render the rooms around the start room as ASCII map.
+///
+/// Only rooms which are connected by compass directions are drawn.  The
+/// cells contain the beginning of the room name, a legend below the map
+/// lists the full names with their actors, items and the other exits.  The
+/// marked room, usually the room of the player, is drawn with angle
+/// brackets.
+pub fn ascii_map(storage: &Storage, start_room: &str,
+				 marked_room: Option<&str>) -> String {
+	let positions = layout(storage, start_room);
+	let min_x = positions.values().map(|&(x, _)| x).min().unwrap_or(0);
+	let max_x = positions.values().map(|&(x, _)| x).max().unwrap_or(0);
+	let min_y = positions.values().map(|&(_, y)| y).min().unwrap_or(0);
+	let max_y = positions.values().map(|&(_, y)| y).max().unwrap_or(0);
+	let width = ((max_x - min_x + 1) as usize) * (CELL_WIDTH + 1);
+	let height = ((max_y - min_y + 1) as usize) * 2;
+	let mut lines: Vec<Vec<char>> = vec![vec![' '; width]; height];
+
+	let mut room_ids: Vec<&String> = positions.keys().collect();
+	room_ids.sort_by_key(|id| (positions[*id].1, positions[*id].0));
+	let mut legend = String::new();
+	for room_id in &room_ids {
+		let room = match storage.get_item::<Room>(room_id) {
+			Some(room) => room,
+			None => continue
+		};
+		let (x, y) = positions[*room_id];
+		let column = ((x - min_x) as usize) * (CELL_WIDTH + 1);
+		let line = ((y - min_y) as usize) * 2;
+		let marked = marked_room == Some(room_id.as_str());
+		let title: Vec<char> = room_title(&room).chars().collect();
+		lines[line][column] = if marked { '<' } else { '[' };
+		for i in 0..(CELL_WIDTH - 2) {
+			lines[line][column + 1 + i] = *title.get(i).unwrap_or(&' ');
+		}
+		lines[line][column + CELL_WIDTH - 1] = if marked { '>' } else { ']' };
+
+		let mut other_exits: Vec<String> = Vec::new();
+		for (exit, dest) in sorted_exits(&room) {
+			if room.exit_state(exit) == ExitState::Hidden {
+				continue
+			}
+			let connected = compass_offset(exit)
+				.map(|(dx, dy)| positions.get(dest) == Some(&(x + dx, y + dy)))
+				.unwrap_or(false);
+			if !connected {
+				other_exits.push(format!("{}: {}", exit, dest));
+				continue
+			}
+			match exit.as_str() {
+				"east" => put_char(&mut lines, line, column + CELL_WIDTH, '-'),
+				"west" => put_char(&mut lines, line, column - 1, '-'),
+				"south" => put_char(&mut lines, line + 1, column + CELL_WIDTH / 2, '|'),
+				"north" => put_char(&mut lines, line - 1, column + CELL_WIDTH / 2, '|'),
+				"southeast" => put_char(&mut lines, line + 1, column + CELL_WIDTH, '\\'),
+				"northwest" => put_char(&mut lines, line - 1, column - 1, '\\'),
+				"northeast" => put_char(&mut lines, line - 1, column + CELL_WIDTH, '/'),
+				"southwest" => put_char(&mut lines, line + 1, column - 1, '/'),
+				_ => ()
+			}
+		}
+
+		legend.push_str(&format!("{} ({})", room_title(&room), room.id));
+		if marked {
+			legend.push_str(" *");
+		}
+		legend.push_str("\n");
+		let actors = actor_names(storage, &room);
+		if !actors.is_empty() {
+			legend.push_str(&format!("  Actors: {}\n", actors.join(", ")));
+		}
+		let items = item_names(storage, &room);
+		if !items.is_empty() {
+			legend.push_str(&format!("  Items: {}\n", items.join(", ")));
+		}
+		if !other_exits.is_empty() {
+			legend.push_str(&format!("  Exits: {}\n", other_exits.join(", ")));
+		}
+	}
+
+	let mut res = String::new();
+	for line in lines {
+		let line: String = line.into_iter().collect();
+		let line = line.trim_right();
+		if !line.is_empty() {
+			res.push_str(line);
+			res.push_str("\n");
+		}
+	}
+	res.push_str("\n");
+	res.push_str(&legend);
+	res
+}
+
+/// Creates an Action which prints the map around the player.
+///
+/// Prints a Graphviz DOT graph of the rooms the player can reach if dot is
+/// true, of the whole world with the hidden exits if builder is true.
+pub fn gen_map_action(dot: bool, builder: bool) -> Action {
+	Box::new(move |ingame, _| {
+		let player_room = ingame.ingame.room_of_player()?;
+		let map = if dot {
+			dot_map(&ingame.ingame.storage()?, &player_room.id, builder)
+		} else {
+			ascii_map(&ingame.ingame.storage()?, &player_room.id,
+					Some(&player_room.id))
+		};
+		ingame.add_text(MessageKind::Output, &escape(map.trim_right_matches('\n')));
+		Ok(())
+	})
+}
+
+fn gen_map_cmd_with(keyword: String, builder: bool) -> Command {
+	Command {
+		keyword: keyword,
+		action_fn: Box::new(move |ingame, keywords | {
+			let dot = keywords.get(1).map(|x| x.trim() == "dot").unwrap_or(false);
+			let hidden = keywords.get(2).map(|x| x.trim() == "hidden").unwrap_or(false);
+			if hidden && !builder {
+				Err(GameError::new(ingame.msg("map.builder_only", &[])))?
+			}
+			Ok(gen_map_action(dot, hidden))
+		})
+	}
+}
+
+/// Command which prints the map, `map` or `map dot`.
+pub fn gen_map_cmd<S: Into<String>>(keyword: S) -> Command {
+	gen_map_cmd_with(keyword.into(), false)
+}
+
+/// Command for builders which also accepts `map dot hidden` to print the
+/// whole world with the hidden exits.
+pub fn gen_builder_map_cmd<S: Into<String>>(keyword: S) -> Command {
+	gen_map_cmd_with(keyword.into(), true)
+}
+
+#[test]
+fn map_test() {
+	let mut room1 = Room::new("room1").with_name("Hall");
+	room1.exits.insert("east".to_string(), "room2".to_string());
+	room1.exits.insert("up".to_string(), "room3".to_string());
+	room1.exits.insert("north".to_string(), "secret".to_string());
+	room1.exit_states.insert("north".to_string(), ExitState::Hidden);
+	room1.actors.push("lalala".to_string());
+	let mut room2 = Room::new("room2").with_name("Kitchen");
+	room2.exits.insert("west".to_string(), "room1".to_string());
+	room2.exit_states.insert("west".to_string(), ExitState::Locked);
+	let lalala = Actor::new("lalala").with_name("Lalala");
+	let storage = Storage::new("storage")
+		.with_item(room1)
+		.with_item(room2)
+		.with_item(Room::new("room3"))
+		.with_item(Room::new("secret").with_name("Secret"))
+		.with_item(lalala);
+	assert_eq!("digraph world {\n\
+			\t\"room1\" [label=\"Hall\\nActors: Lalala\"];\n\
+			\t\"room2\" [label=\"Kitchen\"];\n\
+			\t\"room3\" [label=\"room3\"];\n\
+			\t\"room1\" -> \"room2\" [label=\"east\"];\n\
+			\t\"room1\" -> \"room3\" [label=\"up\"];\n\
+			\t\"room2\" -> \"room1\" [label=\"west (locked)\", style=dashed];\n\
+			}\n", dot_map(&storage, "room1", false));
+	let builder_map = dot_map(&storage, "room1", true);
+	assert!(builder_map.contains("\t\"secret\" [label=\"Secret\"];\n"));
+	assert!(builder_map.contains(
+			"\t\"room1\" -> \"secret\" [label=\"north (hidden)\", style=dashed];\n"));
+	assert_eq!("<Hall >-[Kitch]\n\
+			\n\
+			Hall (room1) *\n  Actors: Lalala\n  Exits: up: room3\n\
+			Kitchen (room2)\n", ascii_map(&storage, "room1", Some("room1")));
+}
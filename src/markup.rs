@@ -0,0 +1,345 @@
+#![warn(missing_docs)]
+
+//! A lightweight markup for styled response texts.
+//!
+//! Styled text is enclosed in tags like `[em]important[/em]`.  The known
+//! tags are `em` for emphasis, `h` for headings, `room` for room names,
+//! `exit` for exits and `error` for errors.  Brackets which don't form a
+//! known tag are kept as they are and `[[` is a single `[`.  Text like
+//! names which must not contain markup is escaped with `escape`.
+//!
+//! A `Renderer` turns the markup into the output of a frontend: ANSI
+//! colours for terminals, plain text for tests and logs or HTML for web
+//! frontends.  Long lines can be wrapped at spaces on the way.
+
+use std::env;
+
+/// Line width used if the width of the terminal is unknown.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// The styles of the markup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+	/// Emphasized text.
+	Emphasis,
+	/// A heading.
+	Heading,
+	/// The name of a room.
+	Room,
+	/// The name of an exit.
+	Exit,
+	/// An error message.
+	Error
+}
+
+/// All styles of the markup.
+pub const STYLES: &'static [Style] = &[Style::Emphasis, Style::Heading, Style::Room,
+		Style::Exit, Style::Error];
+
+impl Style {
+	/// Get the name of the tag.
+	pub fn tag(&self) -> &'static str {
+		match *self {
+			Style::Emphasis => "em",
+			Style::Heading => "h",
+			Style::Room => "room",
+			Style::Exit => "exit",
+			Style::Error => "error"
+		}
+	}
+
+	/// Get the style of a tag name.
+	pub fn from_tag(tag: &str) -> Option<Style> {
+		STYLES.iter().find(|style| style.tag() == tag).cloned()
+	}
+}
+
+/// Escape the text so it is rendered as it is.
+pub fn escape(text: &str) -> String {
+	text.replace('[', "[[")
+}
+
+/// Enclose the text in the tags of the style.
+pub fn styled(style: Style, text: &str) -> String {
+	format!("[{}]{}[/{}]", style.tag(), text, style.tag())
+}
+
+/// A piece of parsed markup.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+	/// Text without line breaks.
+	Text(String),
+	/// Start of a style.
+	Start(Style),
+	/// End of a style.
+	End(Style),
+	/// A line break.
+	LineBreak
+}
+
+fn push_text(segments: &mut Vec<Segment>, text: &str) {
+	for (i, line) in text.split('\n').enumerate() {
+		if i > 0 {
+			segments.push(Segment::LineBreak);
+		}
+		if line.is_empty() {
+			continue
+		}
+		if let Some(&mut Segment::Text(ref mut last)) = segments.last_mut() {
+			last.push_str(line);
+			continue
+		}
+		segments.push(Segment::Text(line.to_string()));
+	}
+}
+
+/// Split the markup into text, tags and line breaks.
+pub fn parse_markup(text: &str) -> Vec<Segment> {
+	let mut segments = Vec::new();
+	let mut rest = text;
+	while let Some(open) = rest.find('[') {
+		if rest[open + 1..].starts_with('[') {
+			push_text(&mut segments, &rest[..open + 1]);
+			rest = &rest[open + 2..];
+			continue
+		}
+		let tag = rest[open + 1..].find(']').map(|close| &rest[open + 1..open + 1 + close]);
+		let segment = tag.and_then(|tag| if tag.starts_with('/') {
+			Style::from_tag(&tag[1..]).map(Segment::End)
+		} else {
+			Style::from_tag(tag).map(Segment::Start)
+		});
+		match (tag, segment) {
+			(Some(tag), Some(segment)) => {
+				push_text(&mut segments, &rest[..open]);
+				segments.push(segment);
+				rest = &rest[open + tag.len() + 2..];
+			},
+			_ => {
+				push_text(&mut segments, &rest[..open + 1]);
+				rest = &rest[open + 1..];
+			}
+		}
+	}
+	push_text(&mut segments, rest);
+	segments
+}
+
+/// Wrap the lines of the segments at spaces so they fit into the width.
+///
+/// Tags don't count to the width.  Words longer than the width are not
+/// split.
+pub fn wrap(segments: &[Segment], width: usize) -> Vec<Segment> {
+	let mut res = Vec::new();
+	let mut column = 0;
+	let mut spaces = String::new();
+	for segment in segments {
+		let text = match *segment {
+			Segment::Text(ref text) => text,
+			Segment::LineBreak => {
+				res.push(Segment::LineBreak);
+				column = 0;
+				spaces.clear();
+				continue
+			},
+			ref tag => {
+				res.push(tag.clone());
+				continue
+			}
+		};
+		let mut word = String::new();
+		for c in text.chars().chain(Some('\n')) {
+			if c != ' ' && c != '\n' {
+				word.push(c);
+				continue
+			}
+			if !word.is_empty() {
+				let length = word.chars().count();
+				if !spaces.is_empty() && column > 0
+						&& column + spaces.len() + length > width {
+					res.push(Segment::LineBreak);
+					column = 0;
+				} else {
+					column += spaces.len();
+					push_text(&mut res, &spaces);
+				}
+				spaces.clear();
+				column += length;
+				push_text(&mut res, &word);
+				word.clear();
+			}
+			if c == ' ' {
+				spaces.push(c);
+			}
+		}
+	}
+	push_text(&mut res, &spaces);
+	res
+}
+
+/// Turns markup into the output of a frontend.
+pub trait Renderer {
+	/// Render a text without line breaks.
+	fn text(&self, text: &str) -> String;
+
+	/// Render the start of a style.
+	fn start(&self, style: Style) -> String;
+
+	/// Render the end of a style, the styles which are still open are given.
+	fn end(&self, style: Style, open: &[Style]) -> String;
+
+	/// Render a line break.
+	fn line_break(&self) -> String {
+		"\n".to_string()
+	}
+
+	/// Render the markup, wrapped to the width if there is one.
+	///
+	/// Styles which are not closed end with the text.
+	fn render(&self, markup: &str, width: Option<usize>) -> String {
+		let segments = parse_markup(markup);
+		let segments = match width {
+			Some(width) => wrap(&segments, width),
+			None => segments
+		};
+		let mut res = String::new();
+		let mut open: Vec<Style> = Vec::new();
+		for segment in segments {
+			match segment {
+				Segment::Text(ref text) => res.push_str(&self.text(text)),
+				Segment::LineBreak => res.push_str(&self.line_break()),
+				Segment::Start(style) => {
+					open.push(style);
+					res.push_str(&self.start(style));
+				},
+				Segment::End(style) => {
+					if let Some(position) = open.iter().rposition(|x| *x == style) {
+						open.remove(position);
+						res.push_str(&self.end(style, &open));
+					}
+				}
+			}
+		}
+		while let Some(style) = open.pop() {
+			res.push_str(&self.end(style, &open));
+		}
+		res
+	}
+}
+
+/// Renders the text without styles.
+pub struct PlainRenderer;
+
+impl Renderer for PlainRenderer {
+	fn text(&self, text: &str) -> String {
+		text.to_string()
+	}
+	fn start(&self, _: Style) -> String {
+		String::new()
+	}
+	fn end(&self, _: Style, _: &[Style]) -> String {
+		String::new()
+	}
+}
+
+/// Renders the styles as ANSI escape codes for terminals.
+///
+/// Control characters in the text are removed so it can't send its own
+/// escape codes, tabs become spaces.
+pub struct AnsiRenderer;
+
+fn ansi_code(style: Style) -> &'static str {
+	match style {
+		Style::Emphasis => "\x1b[1m",
+		Style::Heading => "\x1b[1;4m",
+		Style::Room => "\x1b[36m",
+		Style::Exit => "\x1b[32m",
+		Style::Error => "\x1b[31m"
+	}
+}
+
+impl Renderer for AnsiRenderer {
+	fn text(&self, text: &str) -> String {
+		text.chars()
+			.map(|c| if c == '\t' { ' ' } else { c })
+			.filter(|c| !c.is_control())
+			.collect()
+	}
+	fn start(&self, style: Style) -> String {
+		ansi_code(style).to_string()
+	}
+	fn end(&self, _: Style, open: &[Style]) -> String {
+		let mut res = "\x1b[0m".to_string();
+		for style in open {
+			res.push_str(ansi_code(*style));
+		}
+		res
+	}
+}
+
+/// Renders the styles as HTML elements for web frontends.
+///
+/// Room names, exits and errors become spans with the tag as class.
+pub struct HtmlRenderer;
+
+impl Renderer for HtmlRenderer {
+	fn text(&self, text: &str) -> String {
+		text.replace('&', "&amp;")
+			.replace('<', "&lt;")
+			.replace('>', "&gt;")
+			.replace('"', "&quot;")
+	}
+	fn start(&self, style: Style) -> String {
+		match style {
+			Style::Emphasis => "<em>".to_string(),
+			Style::Heading => "<strong>".to_string(),
+			_ => format!("<span class=\"{}\">", style.tag())
+		}
+	}
+	fn end(&self, style: Style, _: &[Style]) -> String {
+		match style {
+			Style::Emphasis => "</em>",
+			Style::Heading => "</strong>",
+			_ => "</span>"
+		}.to_string()
+	}
+	fn line_break(&self) -> String {
+		"<br>\n".to_string()
+	}
+}
+
+/// Get the width of the terminal from the `COLUMNS` variable.
+///
+/// Returns `DEFAULT_WIDTH` if it's not set.
+pub fn terminal_width() -> usize {
+	env::var("COLUMNS").ok()
+		.and_then(|x| x.trim().parse::<usize>().ok())
+		.filter(|x| *x > 0)
+		.unwrap_or(DEFAULT_WIDTH)
+}
+
+#[test]
+fn markup_test() {
+	let text = format!("{}\nYou see [a] {}.", styled(Style::Heading, "Hall"),
+			styled(Style::Exit, "north"));
+	assert_eq!(vec![Segment::Start(Style::Heading), Segment::Text("Hall".to_string()),
+			Segment::End(Style::Heading), Segment::LineBreak,
+			Segment::Text("You see [a] ".to_string()), Segment::Start(Style::Exit),
+			Segment::Text("north".to_string()), Segment::End(Style::Exit),
+			Segment::Text(".".to_string())],
+			parse_markup(&text));
+	assert_eq!("Hall\nYou see [a] north.", PlainRenderer.render(&text, None));
+	assert_eq!("\x1b[1;4mHall\x1b[0m\nYou see [a] \x1b[32mnorth\x1b[0m.",
+			AnsiRenderer.render(&text, None));
+	assert_eq!("<strong>Hall</strong><br>\nYou see [a] <span class=\"exit\">north</span>.",
+			HtmlRenderer.render(&text, None));
+	assert_eq!("\x1b[1mA \x1b[31mB\x1b[0m\x1b[1m C\x1b[0m",
+			AnsiRenderer.render("[em]A [error]B[/error] C", None));
+	assert_eq!("Hall\nYou see\n[a] north.", PlainRenderer.render(&text, Some(10)));
+	assert_eq!("a\nverylongword\nb", PlainRenderer.render("a verylongword b", Some(5)));
+	assert_eq!("<em>1 &lt; 2</em>", HtmlRenderer.render("[em]1 < 2", None));
+	assert_eq!("[[em]a[[/em]", escape("[em]a[/em]"));
+	assert_eq!("\x1b[36m[em]a[/em] [[\x1b[0m",
+			AnsiRenderer.render(&styled(Style::Room, &escape("[em]a[/em] [[")), None));
+	assert_eq!("a[2Jb c", AnsiRenderer.render("a\x1b[2Jb\x07\tc", None));
+}
@@ -0,0 +1,426 @@
+#![warn(missing_docs)]
+
+//! Parser for natural language commands.
+//!
+//! The parser turns an input like "take the rusty key from the chest" into
+//! the verb `take`, the direct object, the preposition `from` and the
+//! indirect object.  The objects are resolved to the ids of the items and
+//! actors in scope of the player by their names, nouns and adjectives.
+//! Several direct objects are separated by "and" or commas.  If a name fits
+//! more than one item in scope the parser asks which one is meant.
+//!
+//! The terminal keeps a conversation for each player which remembers the
+//! objects of the last command for the pronouns "it", "him", "her" and
+//! "them" and the last input for "again" or "g".
+//!
+//! Verbs are registered at the terminal like commands but their action
+//! function gets the parsed command.
+
+use core::{Ingame, Item, Action, GameResult, berr};
+use actor::Actor;
+use container::Container;
+
+use std::error::Error;
+
+/// Words which are ignored.
+pub const ARTICLES: &'static [&'static str] = &["the", "a", "an", "some"];
+
+/// Words which separate the direct from the indirect object.
+pub const PREPOSITIONS: &'static [&'static str] = &["in", "into", "inside", "on",
+		"onto", "from", "with", "to", "at", "under", "about"];
+
+/// Words which refer to the objects of the last command.
+pub const PRONOUNS: &'static [&'static str] = &["it", "them", "him", "her"];
+
+/// Inputs which repeat the last command.
+pub const AGAIN: &'static [&'static str] = &["again", "g"];
+
+/// A command which uses the parser.
+pub struct Verb {
+	/// The verb which starts the input, in lower case.
+	pub keyword: String,
+	/// Creates the action from the parsed command.
+	pub action_fn: Box<Fn(&mut Ingame, &ParsedCommand) -> Result<Action, Box<Error>>>
+}
+
+/// A parsed command with resolved objects.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedCommand {
+	/// The first word of the input.
+	pub verb: String,
+	/// Ids of the direct objects.
+	pub direct: Vec<String>,
+	/// The preposition if there is one.
+	pub preposition: Option<String>,
+	/// Id of the object after the preposition.
+	pub indirect: Option<String>
+}
+
+impl ParsedCommand {
+	/// Create a command from keywords without resolving the objects.
+	///
+	/// The first keyword is the verb and the remaining ones form the only
+	/// direct object.
+	pub fn from_keywords(keywords: &[&str]) -> Self {
+		let object = keywords.iter().skip(1).cloned().collect::<Vec<&str>>()
+			.join(" ").trim().to_string();
+		ParsedCommand {
+			verb: keywords.first().map(|x| x.trim()).unwrap_or("").to_string(),
+			direct: if object.is_empty() { Vec::new() } else { vec![object] },
+			preposition: None,
+			indirect: None
+		}
+	}
+
+	/// Get the direct objects.
+	///
+	/// # Failure
+	/// Error if there is none.
+	pub fn expect_direct(&self, ingame: &Ingame) -> GameResult<Vec<String>> {
+		if self.direct.is_empty() {
+			Err(berr(ingame.msg("parser.what", &[("verb", &self.verb)])))
+		} else {
+			Ok(self.direct.clone())
+		}
+	}
+
+	/// Get the indirect object if the preposition is one of the given ones.
+	///
+	/// # Failure
+	/// Error if there is no indirect object or if the preposition differs.
+	pub fn expect_indirect(&self, ingame: &Ingame,
+						   prepositions: &[&str]) -> GameResult<String> {
+		match (self.preposition.as_ref(), self.indirect.as_ref()) {
+			(Some(preposition), Some(indirect))
+					if prepositions.contains(&preposition.as_str()) => Ok(indirect.clone()),
+			_ => {
+				let or = format!(" {} ", ingame.msg("parser.or", &[]));
+				Err(berr(ingame.msg("parser.where",
+						&[("verb", &self.verb), ("prepositions", &prepositions.join(&or))])))
+			}
+		}
+	}
+}
+
+/// A name which fits more than one item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ambiguity {
+	/// The tokens of the input.
+	pub tokens: Vec<String>,
+	/// Position of the ambiguous name in the tokens.
+	pub position: usize,
+	/// The ambiguous name.
+	pub phrase: Vec<String>,
+	/// Ids of the items which fit the name.
+	pub candidates: Vec<String>
+}
+
+impl Ambiguity {
+	/// Get the question to the player.
+	pub fn question(&self, ingame: &Ingame) -> String {
+		let names: Vec<String> = self.candidates.iter()
+			.map(|id| ingame.item_name(id))
+			.collect();
+		let or = format!(" {} ", ingame.msg("parser.or", &[]));
+		ingame.msg("parser.which",
+				&[("phrase", &self.phrase.join(" ")), ("names", &names.join(&or))])
+	}
+
+	/// Get the tokens of the input with the answer added to the name.
+	pub fn answer(&self, answer: &[String]) -> Vec<String> {
+		let mut tokens = self.tokens[..self.position].to_vec();
+		tokens.extend(answer.iter().cloned());
+		tokens.extend(self.tokens[self.position..].iter().cloned());
+		tokens
+	}
+}
+
+/// What the parser remembers about the conversation with a player.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Conversation {
+	/// Ids of the objects of the last command.
+	pub objects: Vec<String>,
+	/// The last input which was run.
+	pub last_input: Option<String>,
+	/// Question of the parser which waits for an answer.
+	pub pending: Option<Ambiguity>
+}
+
+impl Conversation {
+	/// Get the ids the pronoun refers to.
+	///
+	/// "them" refers to all objects of the last command, the other
+	/// pronouns to the last one.
+	pub fn referents(&self, pronoun: &str) -> Vec<String> {
+		if pronoun == "them" {
+			self.objects.clone()
+		} else {
+			self.objects.last().cloned().into_iter().collect()
+		}
+	}
+
+	/// Remember the objects and the input of a parsed command.
+	///
+	/// Commands without objects keep the previous objects.
+	pub fn remember(&mut self, parsed: &ParsedCommand, tokens: &[String]) {
+		if !parsed.direct.is_empty() {
+			self.objects = parsed.direct.clone();
+		} else if let Some(ref indirect) = parsed.indirect {
+			self.objects = vec![indirect.clone()];
+		}
+		self.last_input = Some(tokens.join(" "));
+	}
+}
+
+/// Result of the parser.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Parsed {
+	/// All objects were resolved.
+	Command(ParsedCommand),
+	/// The player has to tell which item is meant.
+	Ambiguous(Ambiguity)
+}
+
+/// Split the input into lower case words without articles.
+///
+/// Commas become "and".
+pub fn tokenize_input(input: &str) -> Vec<String> {
+	input.replace(",", " and ")
+		.split_whitespace()
+		.map(|word| word.trim_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+			.to_lowercase())
+		.filter(|word| !word.is_empty() && !ARTICLES.contains(&word.as_str()))
+		.collect()
+}
+
+enum Resolution {
+	Found(String),
+	Ambiguous(Vec<String>)
+}
+
+impl Ingame {
+	/// Get the ids of the items and actors which the actor can refer to.
+	pub fn scope_of(&self, actor: &Actor) -> GameResult<Vec<String>> {
+		let room = self.room_of_actor(actor)?;
+		let mut scope = self.items_around(actor)?;
+		scope.extend(room.actors.iter().filter(|id| **id != actor.id).cloned());
+		Ok(scope)
+	}
+
+	/// Get the nouns and adjectives which refer to an item.
+	///
+	/// The nouns are the id, the name, the words of the name and the
+	/// `nouns` meta, the adjectives come from the `adjectives` meta.
+	pub fn vocabulary(&self, item_id: &str) -> (Vec<String>, Vec<String>) {
+		let name = self.item_name(item_id).to_lowercase();
+		let mut nouns = vec![item_id.to_lowercase(), name.clone()];
+		nouns.extend(name.split_whitespace().map(|x| x.to_string()));
+		let mut adjectives = Vec::new();
+		if let Some(item) = self.get_item::<Item>(item_id) {
+			nouns.extend(item.meta_textvec_or_default("nouns", &[]).iter()
+				.map(|x| x.to_lowercase()));
+			adjectives.extend(item.meta_textvec_or_default("adjectives", &[]).iter()
+				.map(|x| x.to_lowercase()));
+		}
+		(nouns, adjectives)
+	}
+
+	/// Check if the words refer to the item.
+	///
+	/// All words must be nouns or adjectives of the item and one of them
+	/// must be a noun.
+	pub fn words_match(&self, item_id: &str, words: &[String]) -> bool {
+		let (nouns, adjectives) = self.vocabulary(item_id);
+		!words.is_empty()
+			&& words.iter().all(|word| nouns.contains(word) || adjectives.contains(word))
+			&& words.iter().any(|word| nouns.contains(word))
+	}
+
+	/// Resolve a pronoun against the conversation.
+	///
+	/// Returns None if the words are not a pronoun.
+	fn resolve_pronoun(&self, scope: &[String], words: &[String],
+					   conversation: &Conversation) -> GameResult<Option<Vec<String>>> {
+		if words.len() != 1 || !PRONOUNS.contains(&words[0].as_str()) {
+			return Ok(None)
+		}
+		let pronoun = &words[0];
+		let referents = conversation.referents(pronoun);
+		if referents.is_empty() {
+			return Err(berr(self.msg("parser.unknown_pronoun", &[("pronoun", pronoun)])))
+		}
+		for id in &referents {
+			if !scope.contains(id) {
+				return Err(berr(self.msg("parser.gone", &[("item", &self.item_name(id))])))
+			}
+		}
+		Ok(Some(referents))
+	}
+
+	fn resolve_words(&self, scope: &[String], words: &[String]) -> GameResult<Resolution> {
+		let mut candidates: Vec<String> = Vec::new();
+		for id in scope {
+			if !candidates.contains(id) && self.words_match(id, words) {
+				candidates.push(id.clone());
+			}
+		}
+		let phrase = words.join(" ");
+		let exact: Vec<String> = candidates.iter()
+			.filter(|id| self.item_name(id).to_lowercase() == phrase)
+			.cloned()
+			.collect();
+		if exact.len() == 1 {
+			return Ok(Resolution::Found(exact[0].clone()))
+		}
+		match candidates.len() {
+			0 => Err(berr(self.msg("item.not_here", &[("item", &phrase)]))),
+			1 => Ok(Resolution::Found(candidates[0].clone())),
+			_ => Ok(Resolution::Ambiguous(candidates))
+		}
+	}
+
+	/// Parse the tokens of an input for the actor.
+	///
+	/// Objects are resolved against the scope of the actor.  Direct objects
+	/// of a command with `from` are searched in the indirect object.
+	/// Pronouns refer to the objects remembered by the conversation.
+	///
+	/// # Failure
+	/// Error if the input is empty or if an object is not in scope.
+	pub fn parse_tokens(&self, actor: &Actor, tokens: &[String],
+						conversation: &Conversation) -> GameResult<Parsed> {
+		let verb = tokens.first().ok_or(berr(self.msg("cmd.empty", &[])))?.clone();
+		let prep_pos = tokens.iter().skip(1)
+			.position(|word| PREPOSITIONS.contains(&word.as_str()))
+			.map(|pos| pos + 1);
+		let direct_end = prep_pos.unwrap_or(tokens.len());
+		let scope = self.scope_of(actor)?;
+		let ambiguous = |position: usize, phrase: &[String], candidates: Vec<String>| {
+			Ok(Parsed::Ambiguous(Ambiguity {
+				tokens: tokens.to_vec(),
+				position: position,
+				phrase: phrase.to_vec(),
+				candidates: candidates
+			}))
+		};
+
+		let mut preposition = None;
+		let mut indirect = None;
+		if let Some(prep_pos) = prep_pos {
+			preposition = Some(tokens[prep_pos].clone());
+			let words = &tokens[prep_pos + 1..];
+			if let Some(referents) = self.resolve_pronoun(&scope, words, conversation)? {
+				indirect = referents.last().cloned();
+			} else if !words.is_empty() {
+				match self.resolve_words(&scope, words)? {
+					Resolution::Found(id) => indirect = Some(id),
+					Resolution::Ambiguous(candidates) =>
+						return ambiguous(prep_pos + 1, words, candidates)
+				}
+			}
+		}
+
+		let direct_scope = match (preposition.as_ref(), indirect.as_ref()) {
+			(Some(preposition), Some(indirect)) if preposition == "from" =>
+				match self.get_item::<Container>(indirect) {
+					Some(ref container) if container.open =>
+						self.reachable_items(&container.items),
+					_ => scope.clone()
+				},
+			_ => scope.clone()
+		};
+		let mut direct = Vec::new();
+		let mut start = 1;
+		while start < direct_end {
+			let end = tokens[start..direct_end].iter()
+				.position(|word| word == "and")
+				.map(|pos| start + pos)
+				.unwrap_or(direct_end);
+			let words = &tokens[start..end];
+			if let Some(referents) = self.resolve_pronoun(&direct_scope, words, conversation)? {
+				for id in referents {
+					if !direct.contains(&id) {
+						direct.push(id);
+					}
+				}
+			} else if !words.is_empty() {
+				match self.resolve_words(&direct_scope, words)? {
+					Resolution::Found(id) => if !direct.contains(&id) { direct.push(id) },
+					Resolution::Ambiguous(candidates) =>
+						return ambiguous(start, words, candidates)
+				}
+			}
+			start = end + 1;
+		}
+
+		Ok(Parsed::Command(ParsedCommand {
+			verb: verb,
+			direct: direct,
+			preposition: preposition,
+			indirect: indirect
+		}))
+	}
+}
+
+#[test]
+fn parser_test() {
+	use core::{Storage, MutIngame};
+	use room::Room;
+	use base::BaseGame;
+	use thing::Thing;
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	room.items.push("chest".to_string());
+	room.items.push("golden-key".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room)
+		.with_item(Thing::new("chest", "Chest"))
+		.with_item(Container::new("chest").with_item("rusty-key"))
+		.with_item(Thing::new("rusty-key", "Rusty key"))
+		.with_item(Thing::new("golden-key", "Golden key").with_adjective("shiny"));
+	let mut ingame = Ingame::with_storage(storage);
+	let player = ingame.get_player().unwrap();
+	let mut conversation = Conversation::default();
+	let parse = |ingame: &Ingame, input: &str, conversation: &Conversation| {
+		ingame.parse_tokens(&player, &tokenize_input(input), conversation)
+	};
+	assert_eq!(vec!["take", "rusty", "key", "and", "chest"],
+			tokenize_input("Take the rusty key, chest!"));
+	assert_eq!("I don't know what \"it\" refers to",
+			parse(&ingame, "take it", &conversation).unwrap_err().to_string());
+	let parsed = ParsedCommand {
+		verb: "take".to_string(),
+		direct: vec!["rusty-key".to_string()],
+		preposition: Some("from".to_string()),
+		indirect: Some("chest".to_string())
+	};
+	assert_eq!(Parsed::Command(parsed.clone()),
+			parse(&ingame, "take the key from the chest", &conversation).unwrap());
+	conversation.remember(&parsed, &tokenize_input("take the key from the chest"));
+	assert_eq!(Some("take key from chest".to_string()), conversation.last_input);
+	let ambiguity = match parse(&ingame, "take key and chest", &conversation).unwrap() {
+		Parsed::Ambiguous(ambiguity) => ambiguity,
+		parsed => panic!("Not ambiguous: {:?}", parsed)
+	};
+	assert_eq!("Which key do you mean: Golden key or Rusty key?", ambiguity.question(&ingame));
+	let tokens = ambiguity.answer(&tokenize_input("shiny"));
+	match ingame.parse_tokens(&player, &tokens, &conversation).unwrap() {
+		Parsed::Command(parsed) =>
+			assert_eq!(vec!["golden-key".to_string(), "chest".to_string()], parsed.direct),
+		parsed => panic!("Not parsed: {:?}", parsed)
+	}
+	match parse(&ingame, "examine it and golden key", &conversation).unwrap() {
+		Parsed::Command(parsed) =>
+			assert_eq!(vec!["rusty-key".to_string(), "golden-key".to_string()], parsed.direct),
+		parsed => panic!("Not parsed: {:?}", parsed)
+	}
+	assert_eq!("There is no rusty chest here",
+			parse(&ingame, "open rusty chest", &conversation).unwrap_err().to_string());
+	let chest = ingame.get_item::<Container>("chest").unwrap().closed();
+	MutIngame { ingame: &mut ingame }.insert_item(Box::new(chest));
+	assert!(parse(&ingame, "take rusty key", &conversation).is_err());
+	assert_eq!("You can't see Rusty key any more",
+			parse(&ingame, "examine it", &conversation).unwrap_err().to_string());
+}
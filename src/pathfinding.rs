@@ -0,0 +1,177 @@
+#![warn(missing_docs)]
+
+//! Find routes through the exits of the rooms.
+
+use core::{Ingame, Action, GameError, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use room::Room;
+use terminal::Command;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+impl Ingame {
+	/// Find a room by its id or by its name.
+	///
+	/// The name is compared case insensitive.
+	pub fn find_room(&self, name: &str) -> Option<Box<Room>> {
+		if let Some(room) = self.get_item::<Room>(name) {
+			return Some(room)
+		}
+		let name = name.trim().to_lowercase();
+		let mut rooms: Vec<Box<Room>> = self.all_of_type::<Room>()
+			.filter(|room| room.name.to_lowercase() == name)
+			.collect();
+		rooms.sort_by(|a, b| a.id.cmp(&b.id));
+		rooms.into_iter().next()
+	}
+
+	/// Find the shortest sequence of exits from one room to another.
+	///
+	/// Only exits which the actor is allowed to pass are used, see
+	/// `check_exit`.  Exits are visited in alphabetical order so the same
+	/// world always produces the same route.
+	///
+	/// # Failure
+	/// Returns an error if a room was not found or if there is no route.
+	pub fn find_path(&self, actor: &Actor, from_room: &str,
+					 to_room: &str) -> GameResult<Vec<String>> {
+		if self.get_item::<Room>(from_room).is_none() {
+			return Err(berr(self.msg("room.not_found", &[("room", from_room)])))
+		}
+		if self.get_item::<Room>(to_room).is_none() {
+			return Err(berr(self.msg("room.not_found", &[("room", to_room)])))
+		}
+		let mut visited: HashSet<String> = HashSet::new();
+		let mut previous: HashMap<String, (String, String)> = HashMap::new();
+		let mut queue: VecDeque<String> = VecDeque::new();
+		visited.insert(from_room.to_string());
+		queue.push_back(from_room.to_string());
+		while let Some(room_id) = queue.pop_front() {
+			if room_id == to_room {
+				let mut path = Vec::new();
+				let mut current = room_id;
+				while let Some(&(ref prev_room, ref exit)) = previous.get(&current) {
+					path.push(exit.clone());
+					current = prev_room.clone();
+				}
+				path.reverse();
+				return Ok(path)
+			}
+			let room = match self.get_item::<Room>(&room_id) {
+				Some(room) => room,
+				None => continue
+			};
+			let mut exits: Vec<&String> = room.exits.keys().collect();
+			exits.sort();
+			for exit in exits {
+				let dest = match self.check_exit(actor, &room, exit) {
+					Ok(dest) => dest,
+					Err(_) => continue
+				};
+				if !visited.contains(&dest) {
+					visited.insert(dest.clone());
+					previous.insert(dest.clone(), (room_id.clone(), exit.clone()));
+					queue.push_back(dest);
+				}
+			}
+		}
+		Err(berr(self.msg("path.no_way", &[("room", to_room)])))
+	}
+
+	/// Find the shortest sequence of exits from the player to the given room.
+	///
+	/// The room can be given by id or by name.
+	pub fn find_player_path(&self, room_name: &str) -> GameResult<Vec<String>> {
+		let player = self.get_player()?;
+		let player_room = self.room_of_actor(&player)?;
+		let room = self.find_room(room_name)
+				.ok_or(berr(self.msg("room.not_found", &[("room", room_name)])))?;
+		self.find_path(&player, &player_room.id, &room.id)
+	}
+}
+
+/// Creates an Action which walks the player to the given room.
+///
+/// The player is moved exit by exit and stops at the first exit which
+/// cannot be passed.
+pub fn gen_goto_action(room_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let path = ingame.ingame.find_player_path(&room_name)?;
+		if path.is_empty() {
+			let msg = ingame.ingame.msg("path.already_there", &[]);
+			ingame.add_text(MessageKind::Output, &msg);
+		}
+		for exit in path {
+			let player = ingame.ingame.get_player()?;
+			ingame.move_actor(&player, &exit)?;
+		}
+		Ok(())
+	})
+}
+
+/// Creates an Action which prints the exits to the given room.
+pub fn gen_path_action(room_name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let path = ingame.ingame.find_player_path(&room_name)?;
+		let msg = if path.is_empty() {
+			ingame.ingame.msg("path.already_there", &[])
+		} else {
+			ingame.ingame.msg("path.path", &[("path", &path.join(" "))])
+		};
+		ingame.add_text(MessageKind::Output, &msg);
+		Ok(())
+	})
+}
+
+/// Command which walks the player to a room, `goto <room>`.
+pub fn gen_goto_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			Ok(gen_goto_action(keywords[1..].join(" ").trim().to_string()))
+		})
+	}
+}
+
+/// Command which shows the way to a room, `path <room>`.
+pub fn gen_path_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, keywords | {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			Ok(gen_path_action(keywords[1..].join(" ").trim().to_string()))
+		})
+	}
+}
+
+#[test]
+fn find_path_test() {
+	use core::Storage;
+	use room::ExitState;
+	let actor = Actor::new("actor").with_name("Actor");
+	let mut room1 = Room::new("room1");
+	room1.exits.insert("north".to_string(), "room2".to_string());
+	room1.exits.insert("east".to_string(), "room3".to_string());
+	let mut room2 = Room::new("room2");
+	room2.exits.insert("east".to_string(), "room4".to_string());
+	let mut room3 = Room::new("room3");
+	room3.exits.insert("north".to_string(), "room4".to_string());
+	room3.exit_states.insert("north".to_string(), ExitState::Closed);
+	let storage = Storage::new("storage")
+		.with_item(room1)
+		.with_item(room2)
+		.with_item(room3)
+		.with_item(Room::new("room4").with_name("Tower"));
+	let ingame = Ingame::with_storage(storage);
+	assert_eq!(vec!["north".to_string(), "east".to_string()],
+			ingame.find_path(&actor, "room1", "room4").unwrap());
+	assert!(ingame.find_path(&actor, "room1", "room1").unwrap().is_empty());
+	assert!(ingame.find_path(&actor, "room3", "room4").is_err());
+	assert_eq!("room4", ingame.find_room("tower").unwrap().id);
+}
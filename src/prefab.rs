@@ -0,0 +1,396 @@
+#![warn(missing_docs)]
+
+//! Copy a region of a world into another world.
+//!
+//! A prefab is a storage with the rooms of a region, the actors and items
+//! in them and the rules of the rooms.  Its id is `prefab:` followed by the
+//! id of the entry room, so no item id is reserved.  It is saved in the
+//! format of the worldfile module.
+//!
+//! The region starts at a room and follows the exits up to a depth, rooms
+//! in the boundary are not entered.  Exits which leave the region are
+//! dropped and the player is left out.  Inventories and the contents of
+//! containers are copied with their owners.  Quests are not copied, they
+//! belong to the whole world even if their objectives name rooms of the
+//! region.
+//!
+//! On import all items get fresh ids and the exits, items, actors, exit
+//! keys, container contents, behaviours and the rooms and objects of rules
+//! follow.  Patrols and schedules lose the rooms outside of the prefab and
+//! actors stop following actors outside of it, a behaviour without targets
+//! is dropped.  Other references to items outside of the prefab, like
+//! dialogues or ids in scripts, stay as they are.
+
+use core::{Storage, Item, Itemizeable, Ingame, MutIngame, Action, GameError, GameResult, berr};
+use room::Room;
+use actor::Actor;
+use behaviour::BehaviourKind;
+use container::Container;
+use rules::Rule;
+use base::BaseGame;
+use genesis::{opposite_direction, world_path};
+use terminal::{Command, line_input};
+use worldfile::{load_world_file, save_world_file};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Start of the storage id of a prefab, the id of the entry room follows.
+pub const ID_PREFIX: &'static str = "prefab:";
+
+/// Get the id of the room which is connected on import.
+///
+/// Returns None if the storage is no prefab.
+pub fn prefab_entry(prefab: &Storage) -> Option<&str> {
+	if prefab.id().starts_with(ID_PREFIX) {
+		Some(&prefab.id()[ID_PREFIX.len()..])
+	} else {
+		None
+	}
+}
+
+impl Ingame {
+	/// Collect the region around the entry room into a prefab.
+	///
+	/// Exits are followed for at most depth steps and not into the rooms of
+	/// the boundary.
+	///
+	/// # Failure
+	/// Error if a room or item of the region is missing.
+	pub fn export_region(&self, entry: &str, depth: usize,
+						 boundary: &[String]) -> GameResult<Storage> {
+		let player = self.get_item::<BaseGame>("base_game")
+			.map(|base_game| base_game.player)
+			.unwrap_or_default();
+		let mut rooms: BTreeMap<String, Box<Room>> = BTreeMap::new();
+		let mut queue = VecDeque::new();
+		queue.push_back((entry.to_string(), 0));
+		while let Some((room_id, distance)) = queue.pop_front() {
+			if rooms.contains_key(&room_id) || (distance > 0 && boundary.contains(&room_id)) {
+				continue
+			}
+			let room = self.get_item::<Room>(&room_id)
+					.ok_or(berr(self.msg("room.not_found", &[("room", &room_id)])))?;
+			if distance < depth {
+				for target in room.exits.values() {
+					queue.push_back((target.clone(), distance + 1));
+				}
+			}
+			rooms.insert(room_id, room);
+		}
+		let mut prefab = Storage::new(format!("{}{}", ID_PREFIX, entry));
+		let mut pending = Vec::new();
+		for room in rooms.values() {
+			let mut room = room.clone();
+			let outside: Vec<String> = room.exits.iter()
+				.filter(|&(_, target)| !rooms.contains_key(target))
+				.map(|(exit, _)| exit.clone())
+				.collect();
+			for exit in outside {
+				room.exits.remove(&exit);
+				room.exit_states.remove(&exit);
+				room.exit_keys.remove(&exit);
+			}
+			room.actors.retain(|actor| *actor != player);
+			pending.extend(room.actors.iter().cloned());
+			pending.extend(room.items.iter().cloned());
+			let mut item = self.get_item::<Item>(&room.id)
+					.ok_or(berr(self.msg("room.not_found", &[("room", &room.id)])))?;
+			room.merge_into_item(&mut item);
+			prefab.insert(item);
+		}
+		for rule in self.all_of_type::<Rule>() {
+			if rule.room.as_ref().map(|room| rooms.contains_key(room)).unwrap_or(false) {
+				pending.push(rule.id);
+			}
+		}
+		while let Some(item_id) = pending.pop() {
+			if prefab.get_item::<Item>(&item_id).is_some() {
+				continue
+			}
+			let item = self.get_item::<Item>(&item_id)
+					.ok_or(berr(self.msg("item.not_found", &[("item", &item_id)])))?;
+			if let Some(actor) = Actor::from_item(&item) {
+				pending.extend(actor.items);
+			}
+			if let Some(container) = Container::from_item(&item) {
+				pending.extend(container.items);
+			}
+			prefab.insert(item);
+		}
+		Ok(prefab)
+	}
+}
+
+/// Copy the item with the ids replaced by the map.
+///
+/// Ids which are not in the map stay as they are, except for the targets
+/// of behaviours which are dropped.
+fn remap_item(item: &Item, ids: &BTreeMap<String, String>) -> Item {
+	let remap = |id: &String| ids.get(id).cloned().unwrap_or(id.clone());
+	let remap_all = |list: &mut Vec<String>| for id in list.iter_mut() {
+		*id = remap(id);
+	};
+	let mut res = item.clone();
+	res.item_id = remap(&item.item_id);
+	if let Some(mut room) = Room::from_item(item) {
+		for target in room.exits.values_mut() {
+			*target = remap(target);
+		}
+		for key in room.exit_keys.values_mut() {
+			*key = remap(key);
+		}
+		remap_all(&mut room.items);
+		remap_all(&mut room.actors);
+		room.merge_into_item(&mut res);
+	}
+	if let Some(mut actor) = Actor::from_item(item) {
+		remap_all(&mut actor.items);
+		actor.behaviour = actor.behaviour.take().and_then(|mut behaviour| {
+			let (before, after) = match behaviour.kind {
+				BehaviourKind::Wander => (0, 0),
+				BehaviourKind::Follow(ref mut actor_id) => {
+					let inside = ids.contains_key(actor_id) as usize;
+					*actor_id = remap(actor_id);
+					(1, inside)
+				},
+				BehaviourKind::Patrol(ref mut rooms) => {
+					let before = rooms.len();
+					rooms.retain(|room| ids.contains_key(room));
+					remap_all(rooms);
+					(before, rooms.len())
+				},
+				BehaviourKind::Schedule(_, ref mut times) => {
+					let before = times.len();
+					times.retain(|time| ids.contains_key(&time.1));
+					for time in times.iter_mut() {
+						time.1 = remap(&time.1);
+					}
+					(before, times.len())
+				}
+			};
+			if before > after {
+				behaviour.state = 0;
+			}
+			if before > 0 && after == 0 {
+				None
+			} else {
+				Some(behaviour)
+			}
+		});
+		actor.merge_into_item(&mut res);
+	}
+	if let Some(mut container) = Container::from_item(item) {
+		remap_all(&mut container.items);
+		container.merge_into_item(&mut res);
+	}
+	if let Some(mut rule) = Rule::from_item(item) {
+		rule.id = remap(&rule.id);
+		rule.room = rule.room.as_ref().map(|room| remap(room));
+		rule.object = rule.object.as_ref().map(|object| remap(object));
+		rule.merge_into_item(&mut res);
+	}
+	res
+}
+
+impl<'a> MutIngame<'a> {
+	/// Copy a prefab into the world and connect its entry to an exit.
+	///
+	/// The entry room gets the opposite exit back if it's free.  Returns
+	/// the new id of the entry room.
+	///
+	/// # Failure
+	/// Error if the storage is no prefab, if the room is missing or if the
+	/// exit is already used.
+	pub fn import_prefab(&mut self, prefab: &Storage, room_id: &str,
+						 exit: &str) -> GameResult<String> {
+		let entry = prefab_entry(prefab)
+				.ok_or(berr(self.ingame.msg("prefab.invalid", &[("prefab", prefab.id())])))?
+				.to_string();
+		let mut room = self.get_item::<Room>(room_id)
+				.ok_or(berr(self.ingame.msg("room.not_found", &[("room", room_id)])))?;
+		if room.exits.contains_key(exit) {
+			return Err(berr(self.ingame.msg("genesis.exit_exists", &[("exit", exit)])))
+		}
+		let mut ids: BTreeMap<String, String> = BTreeMap::new();
+		for item_id in prefab.ids() {
+			let mut new_id = item_id.to_string();
+			let mut i = 2;
+			while self.get_item::<Item>(&new_id).is_some()
+					|| ids.values().any(|id| *id == new_id) {
+				new_id = format!("{}-{}", item_id, i);
+				i += 1;
+			}
+			ids.insert(item_id.to_string(), new_id);
+		}
+		let new_entry = ids.get(&entry).cloned()
+				.ok_or(berr(self.ingame.msg("room.not_found", &[("room", &entry)])))?;
+		for item in prefab.all_of_type::<Item>() {
+			self.insert_item(Box::new(remap_item(&item, &ids)));
+		}
+		room.exits.insert(exit.to_string(), new_entry.clone());
+		self.insert_item(room);
+		if let Some(back) = opposite_direction(exit) {
+			let mut entry_room = self.get_item::<Room>(&new_entry)
+					.ok_or(berr(self.ingame.msg("room.not_found", &[("room", &new_entry)])))?;
+			if !entry_room.exits.contains_key(back) {
+				entry_room.exits.insert(back.to_string(), room_id.to_string());
+				self.insert_item(entry_room);
+			}
+		}
+		Ok(new_entry)
+	}
+}
+
+/// Load a prefab which was saved with `save_prefab`.
+pub fn load_prefab<P: AsRef<Path>>(path: P) -> GameResult<Storage> {
+	let prefab = load_world_file(path)?;
+	if prefab_entry(&prefab).is_none() {
+		return Err(berr(format!("Not a prefab: {}", prefab.id())))
+	}
+	Ok(prefab)
+}
+
+/// Save a prefab in the world file format.
+pub fn save_prefab<P: AsRef<Path>>(prefab: &Storage, path: P) -> GameResult<()> {
+	save_world_file(prefab, path)
+}
+
+/// Action which connects a prefab to an exit of the player's room.
+pub fn gen_import_prefab_action<S: Into<String>>(prefab: Storage, exit: S) -> Action {
+	let exit: String = exit.into();
+	Box::new(move |ingame, _| {
+		let room = ingame.ingame.room_of_player()?;
+		ingame.import_prefab(&prefab, &room.id, &exit)?;
+		Ok(())
+	})
+}
+
+/// Command which saves the region around the player's room as prefab,
+/// `<keyword> <depth> [boundary rooms]`.
+///
+/// The name is asked for and resolved inside the root directory, see
+/// `world_path`.
+pub fn gen_export_prefab_cmd<S: Into<String>>(keyword: S, root: PathBuf) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(move |ingame, keywords| {
+			if keywords.len() < 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			let depth = keywords[1].trim().parse::<usize>()
+				.map_err(|_| GameError::new(ingame.msg("cmd.expected_number", &[])))?;
+			let boundary: Vec<String> = keywords[2..].iter()
+				.map(|x| x.trim().to_string()).collect();
+			let room = ingame.room_of_player()?;
+			let prefab = ingame.export_region(&room.id, depth, &boundary)?;
+			let name = line_input(format!("{}: ", ingame.msg("genesis.world_name", &[])))?;
+			fs::create_dir_all(&root)?;
+			save_prefab(&prefab, world_path(ingame, &root, &name)?)?;
+			Err(GameError::new("".to_string()))?
+		})
+	}
+}
+
+/// Command which connects a saved prefab to an exit of the player's room,
+/// `<keyword> <exit>`.
+///
+/// The name is asked for and resolved inside the root directory, see
+/// `world_path`.
+pub fn gen_import_prefab_cmd<S: Into<String>>(keyword: S, root: PathBuf) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(move |ingame, keywords| {
+			if keywords.len() != 2 {
+				Err(GameError::new(ingame.msg("cmd.expected_one", &[])))?;
+			}
+			let name = line_input(format!("{}: ", ingame.msg("genesis.world_name", &[])))?;
+			let prefab = load_prefab(world_path(ingame, &root, &name)?)?;
+			Ok(gen_import_prefab_action(prefab, keywords[1].trim()))
+		})
+	}
+}
+
+#[test]
+fn prefab_test() {
+	use response::MessageKind;
+	use behaviour::Behaviour;
+	use rules::RulePhase;
+	use std::env;
+	let player = Actor::new("player").with_name("Player");
+	let mut guard = Actor::new("guard").with_name("Guard");
+	guard.items.push("sword".to_string());
+	guard.behaviour = Some(Behaviour::new(BehaviourKind::Patrol(
+			vec!["hall".to_string(), "street".to_string(), "attic".to_string()])));
+	let mut cat = Actor::new("cat").with_name("Cat");
+	cat.behaviour = Some(Behaviour::new(BehaviourKind::Follow("player".to_string())));
+	let mut street = Room::new("street").with_name("Street");
+	street.actors.push("player".to_string());
+	street.exits.insert("north".to_string(), "hall".to_string());
+	let mut hall = Room::new("hall").with_name("Hall");
+	hall.exits.insert("south".to_string(), "street".to_string());
+	hall.exits.insert("up".to_string(), "attic".to_string());
+	hall.exit_keys.insert("up".to_string(), "key".to_string());
+	hall.actors.push("guard".to_string());
+	hall.actors.push("cat".to_string());
+	hall.items.push("chest".to_string());
+	let mut attic = Room::new("attic").with_name("Attic");
+	attic.exits.insert("down".to_string(), "hall".to_string());
+	attic.exits.insert("up".to_string(), "roof".to_string());
+	let mut roof = Room::new("roof");
+	roof.exits.insert("down".to_string(), "attic".to_string());
+	let storage = Storage::new("town")
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(player)
+		.with_item(guard)
+		.with_item(cat)
+		.with_item(Rule::new("rule-hall-take", RulePhase::Instead, "take", "cancel()")
+			.with_room("hall")
+			.with_object("chest"))
+		.with_item(Rule::new("rule-street-take", RulePhase::Instead, "take", "cancel()")
+			.with_room("street"))
+		.with_item(street)
+		.with_item(hall)
+		.with_item(attic)
+		.with_item(roof)
+		.with_item(Container::new("chest").with_item("key"))
+		.with_item(Item::new("thing".to_string(), "key".to_string()))
+		.with_item(Item::new("thing".to_string(), "sword".to_string()));
+
+	let mut ingame = Ingame::with_storage(storage);
+	let prefab = ingame.export_region("hall", 1, &["street".to_string()]).unwrap();
+	assert_eq!(vec!["attic", "cat", "chest", "guard", "hall", "key", "rule-hall-take", "sword"],
+			prefab.ids().collect::<Vec<&str>>());
+	assert_eq!(Some("hall"), prefab_entry(&prefab));
+	assert!(!prefab.get_item::<Room>("hall").unwrap().exits.contains_key("south"));
+	assert!(!prefab.get_item::<Room>("attic").unwrap().exits.contains_key("up"));
+	assert_eq!(vec!["rule-street-take", "street"], ingame.export_region("street", 0, &[])
+			.unwrap().ids().collect::<Vec<&str>>());
+
+	let path = env::temp_dir().join("rustesge-prefab-test.world");
+	save_prefab(&prefab, &path).unwrap();
+	let prefab = load_prefab(&path).unwrap();
+	assert!(load_prefab(&env::temp_dir().join("rustesge-no-prefab.world")).is_err());
+
+	ingame.add_one_time_action(gen_import_prefab_action(prefab, "west"));
+	ingame.step();
+	assert_eq!("", ingame.response().text(&MessageKind::Error));
+	let street = ingame.get_item::<Room>("street").unwrap();
+	assert_eq!(Some(&"hall-2".to_string()), street.exits.get("west"));
+	let hall = ingame.get_item::<Room>("hall-2").unwrap();
+	assert_eq!(Some(&"street".to_string()), hall.exits.get("east"));
+	assert_eq!(Some(&"attic-2".to_string()), hall.exits.get("up"));
+	assert_eq!(Some(&"key-2".to_string()), hall.exit_keys.get("up"));
+	assert_eq!(vec!["guard-2".to_string(), "cat-2".to_string()], hall.actors);
+	assert_eq!(vec!["sword-2".to_string()], ingame.get_item::<Actor>("guard-2").unwrap().items);
+	assert_eq!(vec!["key-2".to_string()], ingame.get_item::<Container>("chest-2").unwrap().items);
+	assert_eq!(vec!["guard".to_string(), "cat".to_string()],
+			ingame.get_item::<Room>("hall").unwrap().actors);
+	let guard = ingame.get_item::<Actor>("guard-2").unwrap();
+	assert_eq!(BehaviourKind::Patrol(vec!["hall-2".to_string(), "attic-2".to_string()]),
+			guard.behaviour.unwrap().kind);
+	assert!(ingame.get_item::<Actor>("cat-2").unwrap().behaviour.is_none());
+	let rule = ingame.get_item::<Rule>("rule-hall-take-2").unwrap();
+	assert_eq!(Some("hall-2".to_string()), rule.room);
+	assert_eq!(Some("chest-2".to_string()), rule.object);
+}
@@ -0,0 +1,490 @@
+#![warn(missing_docs)]
+
+//! Quests with stages, objectives and rewards.
+//!
+//! A quest goes through its stages one after another.  A stage is done
+//! when all of its objectives were reached once, the quest is completed
+//! after the last stage.  The objectives are checked by the final action of
+//! the quest package at the end of every step.
+//!
+//! Quests start inactive unless they are created active.  Anything which
+//! can set a meta, for example a dialogue effect or a script, can start a
+//! quest by setting its `status` meta to `active`.  Only conversations
+//! which start after the quest became active count for `talk` objectives.
+//! On completion the reward items are taken from where they are to the
+//! player and the `on_complete` script of the quest runs.
+
+use core::{Ingame, MutIngame, Item, Meta, Action, Itemizeable, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use base::EsgePackage;
+use terminal::Command;
+
+/// Something the player has to do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Objective {
+	/// Enter the room with the given id.
+	Visit(String),
+	/// Carry the item with the given id.
+	Obtain(String),
+	/// Talk to the actor with the given id.
+	Talk(String),
+	/// Description and script expression which must become true.
+	Condition(String, String)
+}
+
+impl Objective {
+	/// Parse an objective like `visit:cellar` or `condition:Light:expr`.
+	pub fn parse(text: &str) -> Option<Self> {
+		let mut parts = text.splitn(2, ':');
+		let kind = parts.next().unwrap_or("");
+		let arg = match parts.next() {
+			Some(arg) => arg.to_string(),
+			None => return None
+		};
+		match kind {
+			"visit" => Some(Objective::Visit(arg)),
+			"obtain" => Some(Objective::Obtain(arg)),
+			"talk" => Some(Objective::Talk(arg)),
+			"condition" => {
+				let mut parts = arg.splitn(2, ':');
+				let description = parts.next().unwrap_or("").to_string();
+				parts.next().map(|expr| Objective::Condition(description, expr.to_string()))
+			},
+			_ => None
+		}
+	}
+
+	/// Get the text which can be parsed again.
+	pub fn to_text(&self) -> String {
+		match *self {
+			Objective::Visit(ref room) => format!("visit:{}", room),
+			Objective::Obtain(ref item) => format!("obtain:{}", item),
+			Objective::Talk(ref actor) => format!("talk:{}", actor),
+			Objective::Condition(ref description, ref expr) =>
+				format!("condition:{}:{}", description, expr)
+		}
+	}
+
+	/// Describe the objective for the player.
+	pub fn describe(&self, ingame: &Ingame) -> String {
+		match *self {
+			Objective::Visit(ref room) =>
+				ingame.msg("quest.visit", &[("room", &ingame.item_name(room))]),
+			Objective::Obtain(ref item) =>
+				ingame.msg("quest.obtain", &[("item", &ingame.item_name(item))]),
+			Objective::Talk(ref actor) =>
+				ingame.msg("quest.talk", &[("actor", &ingame.item_name(actor))]),
+			Objective::Condition(ref description, _) => description.clone()
+		}
+	}
+}
+
+/// State of a quest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuestStatus {
+	/// Not started yet.
+	Inactive,
+	/// Started but not completed.
+	Active,
+	/// All stages are done.
+	Completed
+}
+
+impl QuestStatus {
+	/// Get the name of the status.
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			QuestStatus::Inactive => "inactive",
+			QuestStatus::Active => "active",
+			QuestStatus::Completed => "completed"
+		}
+	}
+
+	/// Parse a status from its name.
+	pub fn parse(name: &str) -> Option<Self> {
+		match name.trim() {
+			"inactive" => Some(QuestStatus::Inactive),
+			"active" => Some(QuestStatus::Active),
+			"completed" => Some(QuestStatus::Completed),
+			_ => None
+		}
+	}
+}
+
+/// A stage of a quest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuestStage {
+	/// What the player has to do, displayed while the stage is running.
+	pub description: String,
+	/// All objectives must be reached to finish the stage.
+	pub objectives: Vec<Objective>
+}
+
+/// A quest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quest {
+	/// Identifier of the quest.
+	pub id: String,
+	/// Displayed name.
+	pub name: String,
+	/// State of the quest.
+	pub status: QuestStatus,
+	/// The stages in their order.
+	pub stages: Vec<QuestStage>,
+	/// Index of the current stage.
+	pub stage: usize,
+	/// Indices of the reached objectives of the current stage.
+	pub progress: Vec<usize>,
+	/// Ids of the items the player gets on completion.
+	pub rewards: Vec<String>,
+	/// If the player was told about the start of the quest.
+	pub announced: bool,
+	/// Number of conversations before the quest became active.
+	pub activated_at: u32
+}
+
+impl Quest {
+	/// Create an inactive quest without stages.
+	pub fn new<S: Into<String>>(id: S, name: S) -> Self {
+		Quest {
+			id: id.into(),
+			name: name.into(),
+			status: QuestStatus::Inactive,
+			stages: Vec::new(),
+			stage: 0,
+			progress: Vec::new(),
+			rewards: Vec::new(),
+			announced: false,
+			activated_at: 0
+		}
+	}
+
+	/// Consumes the quest and returns new one with an added stage.
+	///
+	/// Use for initialization.
+	pub fn with_stage<S: Into<String>>(mut self, description: S,
+									   objectives: Vec<Objective>) -> Self {
+		self.stages.push(QuestStage {
+			description: description.into(),
+			objectives: objectives
+		});
+		self
+	}
+
+	/// Consumes the quest and returns new one with an added reward item.
+	///
+	/// Use for initialization.
+	pub fn with_reward<S: Into<String>>(mut self, item_id: S) -> Self {
+		self.rewards.push(item_id.into());
+		self
+	}
+
+	/// Consumes the quest and returns new one which is already active.
+	///
+	/// Use for initialization.
+	pub fn active(mut self) -> Self {
+		self.status = QuestStatus::Active;
+		self
+	}
+
+	/// Get the current stage, None if the quest is completed.
+	pub fn current_stage(&self) -> Option<&QuestStage> {
+		match self.status {
+			QuestStatus::Completed => None,
+			_ => self.stages.get(self.stage)
+		}
+	}
+}
+
+impl Itemizeable for Quest {
+	fn item_type() -> Option<&'static str> {
+		Some("quest")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "quest" {
+			return None
+		}
+		let descriptions = item.meta_textvec_or_default("stages", &[]);
+		let mut stages: Vec<QuestStage> = descriptions.iter()
+			.map(|description| QuestStage {
+				description: description.clone(),
+				objectives: Vec::new()
+			})
+			.collect();
+		for entry in item.meta_textvec_or_default("objectives", &[]) {
+			let mut parts = entry.splitn(2, ':');
+			let index = parts.next().and_then(|x| x.parse::<usize>().ok());
+			let objective = parts.next().and_then(Objective::parse);
+			match (index, objective) {
+				(Some(index), Some(objective)) if index < stages.len() =>
+					stages[index].objectives.push(objective),
+				_ => return None
+			}
+		}
+		let int = |key: &str| item.item_meta.get(key).and_then(|x| x.int()).unwrap_or(0);
+		Some(Box::new(Quest {
+			id: item.item_id.clone(),
+			name: item.meta_text_or_default("name", "").to_string(),
+			status: QuestStatus::parse(item.meta_text_or_default("status", ""))
+				.unwrap_or(QuestStatus::Inactive),
+			stages: stages,
+			stage: int("stage") as usize,
+			progress: item.meta_textvec_or_default("progress", &[]).iter()
+				.filter_map(|x| x.parse::<usize>().ok())
+				.collect(),
+			rewards: item.meta_textvec_or_default("rewards", &[]).to_vec(),
+			announced: int("announced") != 0,
+			activated_at: int("activated_at").max(0) as u32
+		}))
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("quest".to_string(), self.id.clone());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		let objectives = self.stages.iter().enumerate()
+			.flat_map(|(i, stage)| stage.objectives.iter()
+				.map(move |objective| format!("{}:{}", i, objective.to_text())))
+			.collect();
+		let metas = &mut item.item_meta;
+		metas.insert("name".to_string(), Meta::Text(self.name.clone()));
+		metas.insert("status".to_string(), Meta::Text(self.status.as_str().to_string()));
+		metas.insert("stages".to_string(), Meta::TextVec(self.stages.iter()
+				.map(|stage| stage.description.clone()).collect()));
+		metas.insert("objectives".to_string(), Meta::TextVec(objectives));
+		metas.insert("stage".to_string(), Meta::Int(self.stage as i32));
+		metas.insert("progress".to_string(), Meta::TextVec(self.progress.iter()
+				.map(|x| x.to_string()).collect()));
+		metas.insert("rewards".to_string(), Meta::TextVec(self.rewards.clone()));
+		metas.insert("announced".to_string(), Meta::Int(self.announced as i32));
+		metas.insert("activated_at".to_string(), Meta::Int(self.activated_at as i32));
+	}
+	fn get_id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Check if the player has reached the objective.
+	///
+	/// # Failure
+	/// Error if the condition of the objective fails.
+	pub fn objective_reached(&mut self, quest: &Quest, player: &Actor,
+							 objective: &Objective) -> GameResult<bool> {
+		match *objective {
+			Objective::Visit(ref room) => Ok(self.ingame.room_of_actor(player)
+				.map(|x| &x.id == room).unwrap_or(false)),
+			Objective::Obtain(ref item) => Ok(player.carries(item)),
+			Objective::Talk(ref actor) => Ok(self.ingame.last_conversation_with(actor)
+				.map(|x| x > quest.activated_at).unwrap_or(false)),
+			Objective::Condition(_, ref expr) => {
+				let room = self.ingame.room_of_actor(player)
+					.map(|x| x.id).unwrap_or(String::new());
+				self.eval_condition(expr, &[("quest", quest.id.as_str()),
+						("actor", player.id.as_str()), ("room", room.as_str())])
+			}
+		}
+	}
+
+	/// Complete the quest and hand out the rewards.
+	///
+	/// The rewards are removed from their rooms, containers and owners.
+	fn complete_quest(&mut self, quest: &mut Quest, player: &Actor) -> GameResult<()> {
+		quest.status = QuestStatus::Completed;
+		quest.progress.clear();
+		let msg = self.ingame.msg("quest.completed", &[("quest", &quest.name)]);
+		self.add_message(MessageKind::Output, &quest.id, &msg);
+		for reward in &quest.rewards {
+			self.detach_item(reward);
+		}
+		let mut player = self.get_item::<Actor>(&player.id)
+				.ok_or(berr(format!("Actor not found: {}", player.id)))?;
+		for reward in &quest.rewards {
+			player.items.push(reward.clone());
+			let msg = self.ingame.msg("quest.reward", &[("item", &self.ingame.item_name(reward))]);
+			self.add_message(MessageKind::Output, reward, &msg);
+		}
+		self.insert_item(player);
+		self.insert_item(Box::new(quest.clone()));
+		self.run_trigger(&quest.id, "on_complete", &[("quest", quest.id.as_str())])?;
+		Ok(())
+	}
+
+	/// Check the objectives of the active quests and advance them.
+	///
+	/// Announces started quests, finished stages and completed quests on out.
+	pub fn update_quests(&mut self) -> GameResult<()> {
+		let player = match self.ingame.get_player() {
+			Ok(player) => player,
+			Err(_) => return Ok(())
+		};
+		let mut quests: Vec<Box<Quest>> = self.ingame.all_of_type::<Quest>()
+			.filter(|quest| quest.status == QuestStatus::Active)
+			.collect();
+		quests.sort_by(|a, b| a.id.cmp(&b.id));
+		for mut quest in quests {
+			let before = quest.clone();
+			if !quest.announced {
+				quest.announced = true;
+				quest.activated_at = self.ingame.conversation_count();
+				let msg = self.ingame.msg("quest.new", &[("quest", &quest.name)]);
+				let msg = match quest.current_stage() {
+					Some(stage) => format!("{}\n{}", msg, stage.description),
+					None => msg
+				};
+				self.add_message(MessageKind::Output, &quest.id, &msg);
+			}
+			while let Some(stage) = quest.current_stage().cloned() {
+				for (i, objective) in stage.objectives.iter().enumerate() {
+					if !quest.progress.contains(&i)
+							&& self.objective_reached(&quest, &player, objective)? {
+						quest.progress.push(i);
+					}
+				}
+				if quest.progress.len() < stage.objectives.len() {
+					break
+				}
+				quest.stage += 1;
+				quest.progress.clear();
+				if let Some(next) = quest.current_stage() {
+					let msg = self.ingame.msg("quest.updated", &[("quest", &quest.name)]);
+					self.add_message(MessageKind::Output, &quest.id,
+							&format!("{}\n{}", msg, next.description));
+				}
+			}
+			if quest.stage >= quest.stages.len() {
+				self.complete_quest(&mut quest, &player)?;
+			} else if *quest != *before {
+				self.insert_item(quest);
+			}
+		}
+		Ok(())
+	}
+
+	/// Print the active and completed quests to out.
+	pub fn display_quests(&mut self) -> GameResult<()> {
+		let mut quests: Vec<Box<Quest>> = self.ingame.all_of_type::<Quest>()
+			.filter(|quest| quest.status != QuestStatus::Inactive)
+			.collect();
+		quests.sort_by(|a, b| a.id.cmp(&b.id));
+		if quests.is_empty() {
+			let msg = self.ingame.msg("quest.none", &[]);
+			self.add_text(MessageKind::Output, &msg);
+			return Ok(())
+		}
+		for quest in quests {
+			let stage = match quest.current_stage() {
+				Some(stage) => stage,
+				None => {
+					let msg = self.ingame.msg("quest.done", &[("quest", &quest.name)]);
+					self.add_message(MessageKind::Output, &quest.id, &msg);
+					continue
+				}
+			};
+			self.add_message(MessageKind::Output, &quest.id,
+					&format!("{}: {}", quest.name, stage.description));
+			for (i, objective) in stage.objectives.iter().enumerate() {
+				let mark = if quest.progress.contains(&i) { "x" } else { " " };
+				let line = format!("  [{}] {}", mark, objective.describe(self.ingame));
+				self.add_message(MessageKind::Output, &quest.id, &line);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Create the quest package.
+///
+/// Its final action checks the quests at the end of every step.
+pub fn gen_quest_package() -> EsgePackage {
+	EsgePackage::new(Box::new(|_, _| Ok(())), Box::new(|_| None))
+		.with_final_action(Box::new(|ingame, _| ingame.update_quests()))
+}
+
+/// Creates an Action which lists the quests of the player.
+pub fn gen_quests_action() -> Action {
+	Box::new(|ingame, _| ingame.display_quests())
+}
+
+/// Command which lists the quests.
+pub fn gen_quests_cmd<S: Into<String>>(keyword: S) -> Command {
+	Command {
+		keyword: keyword.into(),
+		action_fn: Box::new(|_, _ | Ok(gen_quests_action()))
+	}
+}
+
+#[test]
+fn quest_test() {
+	use core::Storage;
+	use room::Room;
+	use base::{BaseGame, gen_move_player_action};
+	use inventory::gen_take_action;
+	use dialogue::DialogueNode;
+	let mut hall = Room::new("hall");
+	hall.exits.insert("down".to_string(), "cellar".to_string());
+	hall.actors.push("player".to_string());
+	hall.items.push("coin".to_string());
+	let mut cellar = Room::new("cellar");
+	cellar.items.push("wine".to_string());
+	let mut quest = Quest::new("thirst", "Thirst")
+		.with_stage("Find the cellar.", vec![Objective::Visit("cellar".to_string())])
+		.with_stage("Get some wine.", vec![
+			Objective::Obtain("wine".to_string()),
+			Objective::Condition("Be in the cellar".to_string(),
+					"room == \"cellar\"".to_string())])
+		.with_reward("coin");
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(hall)
+		.with_item(cellar)
+		.with_item(Item::new("thing".to_string(), "wine".to_string()))
+		.with_item(quest.clone());
+	let mut ingame = Ingame::init_packages(storage, vec![gen_quest_package()]).unwrap();
+	ingame.step();
+	assert_eq!("", ingame.response().text(&MessageKind::Output));
+	let mut mut_ingame = MutIngame { ingame: &mut ingame };
+	let mut started = mut_ingame.get_item::<Item>("thirst").unwrap();
+	started.item_meta.insert("status".to_string(), Meta::Text("active".to_string()));
+	mut_ingame.insert_item(started);
+	ingame.step();
+	assert_eq!("New quest: Thirst\nFind the cellar.",
+			ingame.response().text(&MessageKind::Output));
+	ingame.add_one_time_action(gen_move_player_action("down".to_string()));
+	ingame.step();
+	assert!(ingame.response().text(&MessageKind::Output)
+			.ends_with("Quest updated: Thirst\nGet some wine."));
+	ingame.add_one_time_action(gen_quests_action());
+	ingame.step();
+	assert_eq!("Thirst: Get some wine.\n  [ ] Obtain wine\n  [x] Be in the cellar",
+			ingame.response().text(&MessageKind::Output));
+	ingame.add_one_time_action(gen_take_action("wine".to_string()));
+	ingame.step();
+	assert_eq!("Taken: wine\nQuest completed: Thirst\nYou receive: coin",
+			ingame.response().text(&MessageKind::Output));
+	assert!(ingame.get_player().unwrap().carries("coin"));
+	assert!(ingame.get_item::<Room>("hall").unwrap().items.is_empty());
+	quest.status = QuestStatus::Completed;
+	quest.stage = 2;
+	quest.announced = true;
+	assert_eq!(quest, *ingame.get_item::<Quest>("thirst").unwrap());
+
+	let mut guard = Actor::new("guard").with_name("Guard");
+	guard.dialogue = Some("hello".to_string());
+	let gossip = Quest::new("gossip", "Gossip")
+		.with_stage("Talk to the guard.", vec![Objective::Talk("guard".to_string())]);
+	let mut mut_ingame = MutIngame { ingame: &mut ingame };
+	mut_ingame.insert_item(Box::new(DialogueNode::new("hello", "Hello.")));
+	mut_ingame.insert_item(Box::new(guard.clone()));
+	mut_ingame.start_dialogue(&guard).unwrap();
+	mut_ingame.insert_item(Box::new(gossip.active()));
+	ingame.step();
+	let gossip = ingame.get_item::<Quest>("gossip").unwrap();
+	assert_eq!((QuestStatus::Active, 1), (gossip.status, gossip.activated_at));
+	MutIngame { ingame: &mut ingame }.start_dialogue(&guard).unwrap();
+	ingame.step();
+	assert_eq!(QuestStatus::Completed, ingame.get_item::<Quest>("gossip").unwrap().status);
+}
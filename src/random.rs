@@ -0,0 +1,72 @@
+#![warn(missing_docs)]
+
+//! A small seedable pseudo random number generator.
+//!
+//! The same seed always produces the same numbers on every platform which
+//! keeps games replayable and tests deterministic.  It's not suitable for
+//! cryptography.
+
+/// Pseudo random number generator based on splitmix64.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+	state: u64
+}
+
+impl Rng {
+	/// Create a generator from a seed.
+	pub fn new(seed: u64) -> Self {
+		Rng { state: seed }
+	}
+
+	/// Get the internal state to store it.
+	///
+	/// A generator created by `new` with this state continues the sequence.
+	pub fn state(&self) -> u64 {
+		self.state
+	}
+
+	/// Get the next random number.
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	/// Get a random number from low to high, high is not included.
+	///
+	/// Returns low if high is not greater than low.
+	pub fn range(&mut self, low: i32, high: i32) -> i32 {
+		if high <= low {
+			return low
+		}
+		let width = (high as i64 - low as i64) as u64;
+		(low as i64 + (self.next_u64() % width) as i64) as i32
+	}
+
+	/// Get a random index for a slice of the given length.
+	///
+	/// Returns 0 if the length is 0.
+	pub fn index(&mut self, len: usize) -> usize {
+		if len == 0 {
+			0
+		} else {
+			(self.next_u64() % len as u64) as usize
+		}
+	}
+}
+
+#[test]
+fn rng_test() {
+	let mut a = Rng::new(42);
+	let mut b = Rng::new(42);
+	for _ in 0..100 {
+		let x = a.range(1, 7);
+		assert!(x >= 1 && x < 7);
+		assert_eq!(x, b.range(1, 7));
+	}
+	let mut c = Rng::new(a.state());
+	assert_eq!(a.next_u64(), c.next_u64());
+	assert_eq!(5, a.range(5, 5));
+}
@@ -0,0 +1,223 @@
+#![warn(missing_docs)]
+
+//! Typed messages which the actions send to the frontend.
+//!
+//! Every message has a kind, the action which sent it, optionally the item
+//! it is about and its position in the step.  Frontends can render, filter
+//! or serialize them.  A message has no trailing line break, frontends put
+//! every message on lines of its own.  The channel names of the older API
+//! map to the kinds: "out" is output, "err" is an error and "done" ends the
+//! game.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use rustc_serialize::json::Json;
+
+/// Kind of a message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessageKind {
+	/// Text for the player, channel "out".
+	Output,
+	/// An error, channel "err".
+	Error,
+	/// The game is over, channel "done".  The text is not shown.
+	Done,
+	/// Any other channel.
+	Channel(String)
+}
+
+impl MessageKind {
+	/// Get the kind of a channel name.
+	pub fn from_channel(channel: &str) -> MessageKind {
+		match channel {
+			"out" => MessageKind::Output,
+			"err" => MessageKind::Error,
+			"done" => MessageKind::Done,
+			_ => MessageKind::Channel(channel.to_string())
+		}
+	}
+
+	/// Get the channel name of the kind.
+	pub fn channel(&self) -> &str {
+		match *self {
+			MessageKind::Output => "out",
+			MessageKind::Error => "err",
+			MessageKind::Done => "done",
+			MessageKind::Channel(ref channel) => channel
+		}
+	}
+}
+
+/// The action which sent a message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActionSource {
+	/// A regular action with its id.
+	Regular(u32),
+	/// A one time action by its position.
+	OneTime(usize),
+	/// A final action by its position.
+	Final(usize),
+	/// Sent outside of a step, for example by a frontend.
+	External
+}
+
+impl fmt::Display for ActionSource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ActionSource::Regular(i) => write!(f, "regular:{}", i),
+			ActionSource::OneTime(i) => write!(f, "one_time:{}", i),
+			ActionSource::Final(i) => write!(f, "final:{}", i),
+			ActionSource::External => write!(f, "external")
+		}
+	}
+}
+
+/// A message of a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+	/// Kind of the message.
+	pub kind: MessageKind,
+	/// The action which sent the message.
+	pub source: ActionSource,
+	/// Id of the item the message is about.
+	pub subject: Option<String>,
+	/// Position of the message in the step, starting with zero.
+	pub order: usize,
+	/// The text, it may contain markup.
+	pub text: String
+}
+
+impl Message {
+	/// Get the message as JSON object.
+	pub fn to_json(&self) -> Json {
+		let mut object = BTreeMap::new();
+		object.insert("kind".to_string(), Json::String(self.kind.channel().to_string()));
+		object.insert("source".to_string(), Json::String(self.source.to_string()));
+		object.insert("subject".to_string(), match self.subject {
+			Some(ref subject) => Json::String(subject.clone()),
+			None => Json::Null
+		});
+		object.insert("order".to_string(), Json::U64(self.order as u64));
+		object.insert("text".to_string(), Json::String(self.text.clone()));
+		Json::Object(object)
+	}
+}
+
+/// Holds the messages of one step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+	messages: Vec<Message>,
+	source: ActionSource
+}
+
+impl Response {
+	/// Create an empty response.
+	pub fn new() -> Self {
+		Response {
+			messages: Vec::new(),
+			source: ActionSource::External
+		}
+	}
+
+	/// Set the action which sends the next messages.
+	///
+	/// Used by `Ingame::step` before an action runs.
+	pub fn set_source(&mut self, source: ActionSource) {
+		self.source = source;
+	}
+
+	/// Add a message from the current action.
+	pub fn push(&mut self, kind: MessageKind, subject: Option<&str>, text: &str) {
+		let order = self.messages.len();
+		self.messages.push(Message {
+			kind: kind,
+			source: self.source,
+			subject: subject.map(|x| x.to_string()),
+			order: order,
+			text: text.to_string()
+		});
+	}
+
+	/// Append the text to the last message of the kind.
+	///
+	/// Adds a new message if there is none of the kind yet.
+	pub fn append_text(&mut self, kind: MessageKind, text: &str) {
+		if let Some(message) = self.messages.iter_mut().rev()
+				.find(|message| message.kind == kind) {
+			message.text.push_str(text);
+			return
+		}
+		self.push(kind, None, text);
+	}
+
+	/// Get all messages in their order.
+	pub fn messages(&self) -> &[Message] {
+		&self.messages
+	}
+
+	/// Get the messages of one kind.
+	pub fn of_kind<'a>(&'a self, kind: &'a MessageKind)
+					   -> Box<Iterator<Item=&'a Message> + 'a> {
+		Box::new(self.messages.iter().filter(move |message| message.kind == *kind))
+	}
+
+	/// Check if there is a message of the kind.
+	pub fn contains(&self, kind: &MessageKind) -> bool {
+		self.of_kind(kind).next().is_some()
+	}
+
+	/// Get the texts of one kind, one message per line.
+	pub fn text(&self, kind: &MessageKind) -> String {
+		self.of_kind(kind).map(|message| message.text.as_str())
+			.collect::<Vec<&str>>()
+			.join("\n")
+	}
+
+	/// Replace the messages of a kind by one message.
+	pub fn set_text(&mut self, kind: MessageKind, text: &str) {
+		self.messages.retain(|message| message.kind != kind);
+		for (i, message) in self.messages.iter_mut().enumerate() {
+			message.order = i;
+		}
+		self.push(kind, None, text);
+	}
+
+	/// Remove all messages.
+	pub fn clear(&mut self) {
+		self.messages.clear();
+		self.source = ActionSource::External;
+	}
+
+	/// Get the messages as JSON array.
+	pub fn to_json(&self) -> String {
+		Json::Array(self.messages.iter().map(|message| message.to_json()).collect())
+			.to_string()
+	}
+}
+
+#[test]
+fn response_test() {
+	let mut response = Response::new();
+	response.set_source(ActionSource::Regular(3));
+	response.push(MessageKind::Output, Some("lamp"), "Taken: Lamp");
+	response.set_source(ActionSource::OneTime(0));
+	response.push(MessageKind::from_channel("err"), None, "Oops");
+	response.push(MessageKind::Output, None, "Done.");
+	assert_eq!("Taken: Lamp\nDone.", response.text(&MessageKind::Output));
+	assert!(response.contains(&MessageKind::Error));
+	assert!(!response.contains(&MessageKind::Done));
+	assert_eq!(vec![1], response.of_kind(&MessageKind::Error)
+			.map(|message| message.order).collect::<Vec<usize>>());
+	assert_eq!(ActionSource::Regular(3), response.messages()[0].source);
+	let mut single = Response::new();
+	single.push(MessageKind::Output, Some("lamp"), "Lamp");
+	assert_eq!("[{\"kind\":\"out\",\"order\":0,\"source\":\"external\",\
+			\"subject\":\"lamp\",\"text\":\"Lamp\"}]", single.to_json());
+	response.set_text(MessageKind::Output, "Replaced");
+	assert_eq!("Replaced", response.text(&MessageKind::Output));
+	assert_eq!(1, response.messages()[1].order);
+	response.append_text(MessageKind::Output, " again");
+	response.append_text(MessageKind::Done, "");
+	assert_eq!("Replaced again", response.text(&MessageKind::Output));
+	assert_eq!(3, response.messages().len());
+}
@@ -7,7 +7,45 @@ use core::{Item, Meta};
 use core::serialize_hashmap;
 use core::deserialize_hashmap;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+/// State of an exit which decides if an actor can pass it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitState {
+	/// The exit can be passed.
+	Open,
+	/// The exit is closed and cannot be passed.
+	Closed,
+	/// The exit can only be passed with the key of the exit.
+	Locked,
+	/// The exit is not displayed and behaves as if it does not exist.
+	Hidden
+}
+
+impl ExitState {
+	/// Get the name of the state which is used in the storage.
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			ExitState::Open => "open",
+			ExitState::Closed => "closed",
+			ExitState::Locked => "locked",
+			ExitState::Hidden => "hidden"
+		}
+	}
+
+	/// Get the state by its name.
+	///
+	/// Returns None if the name is unknown.
+	pub fn parse(name: &str) -> Option<ExitState> {
+		match name {
+			"open" => Some(ExitState::Open),
+			"closed" => Some(ExitState::Closed),
+			"locked" => Some(ExitState::Locked),
+			"hidden" => Some(ExitState::Hidden),
+			_ => None
+		}
+	}
+}
 
 /// Introduces rooms to storageable objects.
 #[derive(Clone, Debug)]
@@ -23,7 +61,14 @@ pub struct Room {
 	/// Actor identifier in the room.
 	pub actors: Vec<String>,
 	/// Exits and the identifier to a room id.
-	pub exits: HashMap<String, String>
+	pub exits: HashMap<String, String>,
+	/// State of the exits, exits without state are open.
+	pub exit_states: HashMap<String, ExitState>,
+	/// Item identifiers required to pass the exits.
+	///
+	/// The key is only checked while the exit is locked, an open exit can be
+	/// passed without it.
+	pub exit_keys: HashMap<String, String>
 }
 
 impl Room {
@@ -36,7 +81,9 @@ impl Room {
 			description: "".to_string(),
 			items: Vec::new(),
 			actors: Vec::new(),
-			exits: HashMap::new()
+			exits: HashMap::new(),
+			exit_states: HashMap::new(),
+			exit_keys: HashMap::new()
 		}
 	}
 
@@ -55,9 +102,28 @@ impl Room {
 		self.description = description.into();
 		self
 	}
+
+	/// Get the state of the given exit.
+	pub fn exit_state(&self, exit_name: &str) -> ExitState {
+		self.exit_states.get(exit_name).cloned().unwrap_or(ExitState::Open)
+	}
+
+	/// Get the item identifier required to pass the given exit.
+	pub fn exit_key(&self, exit_name: &str) -> Option<&String> {
+		self.exit_keys.get(exit_name)
+	}
+
+	/// Return the names of all exits which are not hidden.
+	pub fn visible_exits<'a>(&'a self) -> Box<Iterator<Item=&'a String> + 'a> {
+		Box::new(self.exits.keys()
+			.filter(move |x| self.exit_state(x) != ExitState::Hidden))
+	}
 }
 
 impl core::Itemizeable for Room {
+	fn item_type() -> Option<&'static str> {
+		Some("room")
+	}
 	fn from_item(item: &Item) -> Option<Box<Room>> {
 		if item.item_type != "room" {
 			None
@@ -72,13 +138,23 @@ impl core::Itemizeable for Room {
 						.iter().map(|x| x.to_string()).collect();
 			let exits = deserialize_hashmap(
 					&item.meta_text_or_default("exits", "").to_string());
+			let exit_states = deserialize_hashmap(
+					&item.meta_text_or_default("exit_states", "{}").to_string())
+				.into_iter()
+				.filter_map(|(exit, state)| ExitState::parse(&state)
+						.map(|state| (exit, state)))
+				.collect();
+			let exit_keys = deserialize_hashmap(
+					&item.meta_text_or_default("exit_keys", "{}").to_string());
 			Some(Box::new(Room {
 				id: item.item_id.clone(),
 				name: name,
 				description: desc,
 				items: items,
 				actors: actors,
-				exits: exits
+				exits: exits,
+				exit_states: exit_states,
+				exit_keys: exit_keys
 			}))
 		}
 	}
@@ -86,7 +162,7 @@ impl core::Itemizeable for Room {
 		let mut item = Item {
 			item_id: self.id.clone(),
 			item_type: "room".to_string(),
-			item_meta: HashMap::new()
+			item_meta: BTreeMap::new()
 		};
 		self.merge_into_item(&mut item);
 		item
@@ -98,6 +174,13 @@ impl core::Itemizeable for Room {
 		metas.insert("items".to_string(), Meta::TextVec(self.items.clone()));
 		metas.insert("actors".to_string(), Meta::TextVec(self.actors.clone()));
 		metas.insert("exits".to_string(), Meta::Text(serialize_hashmap(&self.exits)));
+		let exit_states: HashMap<String, String> = self.exit_states.iter()
+			.map(|(exit, state)| (exit.clone(), state.as_str().to_string()))
+			.collect();
+		metas.insert("exit_states".to_string(),
+				Meta::Text(serialize_hashmap(&exit_states)));
+		metas.insert("exit_keys".to_string(),
+				Meta::Text(serialize_hashmap(&self.exit_keys)));
 	}
 	fn get_id(&self) -> &str {
 		&self.id
@@ -0,0 +1,315 @@
+#![warn(missing_docs)]
+
+//! Rules which change what a command does.
+//!
+//! Rules are stored as items and checked by the terminal after parsing a
+//! command and before its action is created, so a blocked command doesn't
+//! ask the player for more input.  A rule matches on the verb of the parsed
+//! command and optionally on the object, one of its direct objects, and on
+//! the room of the player.  The direct objects are item ids for the verbs
+//! of the parser and the rest of the input for keyword commands.  Its
+//! condition is a script expression, see the `script` module, which gets
+//! the variables `actor`, `room`, `verb`, `object` and `indirect`.  The
+//! room is the one where the command was entered.  A guard which blocks the way could look like this:
+//!
+//! ```text
+//! phase:     instead
+//! verb:      go
+//! object:    north
+//! condition: room_of("guard") == room
+//! script:    print("The guard doesn't let you pass.")
+//! ```
+//!
+//! There are three phases:
+//!
+//! * `before` rules run first.  If one calls `cancel()` the command is
+//!   blocked.
+//! * The first `instead` rule replaces the action of the command.  If it
+//!   calls `cancel()` the next `instead` rule or the action runs.
+//! * `after` rules run if the action succeeded.  `cancel()` skips the
+//!   remaining ones.
+//!
+//! Within a phase more specific rules run first: a rule with object, room
+//! and condition beats one with only an object.  Rules with the same
+//! specificity are ordered by id.
+
+use core::{Ingame, MutIngame, Item, Meta, Action, Itemizeable, GameResult};
+use parser::ParsedCommand;
+
+/// Creates the action of a command if no rule blocks or replaces it.
+pub type ActionBuilder = Box<Fn(&mut Ingame) -> GameResult<Action>>;
+
+/// When a rule runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RulePhase {
+	/// Before the action, can block it.
+	Before,
+	/// Instead of the action.
+	Instead,
+	/// After the action succeeded.
+	After
+}
+
+impl RulePhase {
+	/// Get the name of the phase.
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			RulePhase::Before => "before",
+			RulePhase::Instead => "instead",
+			RulePhase::After => "after"
+		}
+	}
+
+	/// Parse a phase from its name.
+	pub fn parse(name: &str) -> Option<Self> {
+		match name.trim() {
+			"before" => Some(RulePhase::Before),
+			"instead" => Some(RulePhase::Instead),
+			"after" => Some(RulePhase::After),
+			_ => None
+		}
+	}
+}
+
+/// A rule which changes the behaviour of a command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+	/// Identifier of the rule.
+	pub id: String,
+	/// When the rule runs.
+	pub phase: RulePhase,
+	/// The keyword of the command.
+	pub verb: String,
+	/// The rest of the input, any object if None.
+	pub object: Option<String>,
+	/// The room of the player, any room if None.
+	pub room: Option<String>,
+	/// Script expression which must be true, empty for always.
+	pub condition: String,
+	/// Script which runs if the rule matches.
+	pub script: String
+}
+
+impl Rule {
+	/// Create a rule for a verb which matches any object in any room.
+	pub fn new<S: Into<String>>(id: S, phase: RulePhase, verb: S, script: S) -> Self {
+		Rule {
+			id: id.into(),
+			phase: phase,
+			verb: verb.into(),
+			object: None,
+			room: None,
+			condition: String::new(),
+			script: script.into()
+		}
+	}
+
+	/// Consumes the rule and returns new one which only matches the object.
+	///
+	/// Use for initialization.
+	pub fn with_object<S: Into<String>>(mut self, object: S) -> Self {
+		self.object = Some(object.into());
+		self
+	}
+
+	/// Consumes the rule and returns new one which only matches in the room.
+	///
+	/// Use for initialization.
+	pub fn with_room<S: Into<String>>(mut self, room: S) -> Self {
+		self.room = Some(room.into());
+		self
+	}
+
+	/// Consumes the rule and returns new one with the given condition.
+	///
+	/// Use for initialization.
+	pub fn with_condition<S: Into<String>>(mut self, condition: S) -> Self {
+		self.condition = condition.into();
+		self
+	}
+
+	/// Number of restrictions of the rule, more specific rules run first.
+	pub fn specificity(&self) -> u32 {
+		self.object.is_some() as u32 + self.room.is_some() as u32
+			+ !self.condition.trim().is_empty() as u32
+	}
+
+	/// Check if the command and the room match the rule.
+	///
+	/// The object matches any of the direct objects, compared case
+	/// insensitive.  The condition is not checked.
+	pub fn matches(&self, parsed: &ParsedCommand, room: &str) -> bool {
+		self.verb == parsed.verb
+			&& self.object.as_ref()
+				.map(|object| parsed.direct.iter()
+					.any(|x| x.trim().to_lowercase() == object.trim().to_lowercase()))
+				.unwrap_or(true)
+			&& self.room.as_ref().map(|x| x == room).unwrap_or(true)
+	}
+}
+
+impl Itemizeable for Rule {
+	fn item_type() -> Option<&'static str> {
+		Some("rule")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "rule" {
+			return None
+		}
+		let phase = match RulePhase::parse(item.meta_text_or_default("phase", "")) {
+			Some(phase) => phase,
+			None => return None
+		};
+		let optional = |key: &str| match item.meta_text_or_default(key, "") {
+			"" => None,
+			value => Some(value.to_string())
+		};
+		Some(Box::new(Rule {
+			id: item.item_id.clone(),
+			phase: phase,
+			verb: item.meta_text_or_default("verb", "").to_string(),
+			object: optional("object"),
+			room: optional("room"),
+			condition: item.meta_text_or_default("condition", "").to_string(),
+			script: item.meta_text_or_default("script", "").to_string()
+		}))
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("rule".to_string(), self.id.clone());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		let metas = &mut item.item_meta;
+		metas.insert("phase".to_string(), Meta::Text(self.phase.as_str().to_string()));
+		metas.insert("verb".to_string(), Meta::Text(self.verb.clone()));
+		metas.insert("object".to_string(),
+				Meta::Text(self.object.clone().unwrap_or(String::new())));
+		metas.insert("room".to_string(),
+				Meta::Text(self.room.clone().unwrap_or(String::new())));
+		metas.insert("condition".to_string(), Meta::Text(self.condition.clone()));
+		metas.insert("script".to_string(), Meta::Text(self.script.clone()));
+	}
+	fn get_id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl Ingame {
+	/// Get the rules of a phase which match the command and the room.
+	///
+	/// The most specific rules come first.
+	pub fn matching_rules(&self, phase: RulePhase, parsed: &ParsedCommand,
+						  room: &str) -> Vec<Box<Rule>> {
+		let mut rules: Vec<Box<Rule>> = self.all_of_type::<Rule>()
+			.filter(|rule| rule.phase == phase && rule.matches(parsed, room))
+			.collect();
+		rules.sort_by(|a, b| b.specificity().cmp(&a.specificity())
+				.then(a.id.cmp(&b.id)));
+		rules
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Run the matching rules of a phase whose condition is true.
+	///
+	/// Stops at the first rule which completes if `stop_if_completed` is
+	/// set, otherwise at the first rule which calls `cancel()`.  Returns if a
+	/// rule stopped the phase.
+	fn run_rules(&mut self, phase: RulePhase, parsed: &ParsedCommand,
+				 vars: &[(&str, &str)], stop_if_completed: bool) -> GameResult<bool> {
+		let room = vars.iter().find(|&&(name, _)| name == "room")
+			.map(|&(_, room)| room).unwrap_or("");
+		let rules = self.ingame.matching_rules(phase, parsed, room);
+		for rule in rules {
+			if !self.eval_condition(&rule.condition, vars)? {
+				continue
+			}
+			if self.run_script(&rule.script, vars)? == stop_if_completed {
+				return Ok(true)
+			}
+		}
+		Ok(false)
+	}
+}
+
+/// Wrap a command with the rules for its parsed input.
+///
+/// The action is only built and run if no `before` rule blocks the command
+/// and no `instead` rule replaces it.
+pub fn gen_ruled_action(parsed: ParsedCommand, build: ActionBuilder) -> Action {
+	let object = parsed.direct.join(" ");
+	let indirect = parsed.indirect.clone().unwrap_or(String::new());
+	Box::new(move |ingame, i| {
+		let actor = ingame.ingame.get_player().map(|x| x.id).unwrap_or(String::new());
+		let room = ingame.ingame.room_of_player().map(|x| x.id).unwrap_or(String::new());
+		let vars = [("actor", actor.as_str()), ("room", room.as_str()),
+				("verb", parsed.verb.as_str()), ("object", object.as_str()),
+				("indirect", indirect.as_str())];
+		if ingame.run_rules(RulePhase::Before, &parsed, &vars, false)? {
+			return Ok(())
+		}
+		if !ingame.run_rules(RulePhase::Instead, &parsed, &vars, true)? {
+			let action = build(ingame.ingame)?;
+			action(ingame, i)?;
+			ingame.run_rules(RulePhase::After, &parsed, &vars, false)?;
+		}
+		Ok(())
+	})
+}
+
+#[test]
+fn rules_test() {
+	use core::Storage;
+	use response::MessageKind;
+	use actor::Actor;
+	use room::Room;
+	use base::{BaseGame, gen_move_player_action};
+	let mut hall = Room::new("hall");
+	hall.exits.insert("north".to_string(), "vault".to_string());
+	hall.actors.push("player".to_string());
+	hall.actors.push("guard".to_string());
+	let mut vault = Room::new("vault");
+	vault.exits.insert("south".to_string(), "hall".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(Actor::new("guard").with_name("Guard"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(hall)
+		.with_item(vault)
+		.with_item(Rule::new("block", RulePhase::Instead, "go",
+				"print(\"The guard blocks the way.\")")
+			.with_object("North")
+			.with_condition("room_of(\"guard\") == room"))
+		.with_item(Rule::new("steps", RulePhase::Before, "go", "print(\"Steps.\")"))
+		.with_item(Rule::new("cold", RulePhase::After, "go", "print(\"It's cold.\")")
+			.with_room("hall"))
+		.with_item(Rule::new("no-south", RulePhase::Before, "go", "cancel()")
+			.with_object("south"));
+	let mut ingame = Ingame::with_storage(storage);
+	let go = |ingame: &mut Ingame, direction: &str| {
+		let exit = direction.to_string();
+		ingame.add_one_time_action(gen_ruled_action(
+				ParsedCommand::from_keywords(&["go", direction]),
+				Box::new(move |_| Ok(gen_move_player_action(exit.clone())))));
+		ingame.step();
+		ingame.response().text(&MessageKind::Output)
+	};
+	assert_eq!("Steps.\nThe guard blocks the way.", go(&mut ingame, "north"));
+	assert_eq!("hall", ingame.room_of_player().unwrap().id);
+	assert_eq!("", go(&mut ingame, "south"));
+	ingame.add_one_time_action(gen_ruled_action(
+			ParsedCommand::from_keywords(&["go", "south"]),
+			Box::new(|_| panic!("The action of a blocked command was built"))));
+	ingame.step();
+	assert_eq!("", ingame.response().text(&MessageKind::Error));
+	let guard = ingame.get_item::<Actor>("guard").unwrap();
+	let hall = ingame.room_of_actor(&guard).unwrap();
+	MutIngame { ingame: &mut ingame }.remove_actor_from_room(&guard, hall);
+	assert!(go(&mut ingame, "north").starts_with("Steps.\n"));
+	assert_eq!("vault", ingame.room_of_player().unwrap().id);
+	assert!(ingame.response().text(&MessageKind::Output).ends_with("It's cold."));
+	let rule = ingame.get_item::<Rule>("block").unwrap();
+	assert_eq!(Some("North".to_string()), rule.object);
+	assert_eq!(2, rule.specificity());
+}
@@ -0,0 +1,756 @@
+#![warn(missing_docs)]
+
+//! A small scripting language for triggers on rooms, exits and items.
+//!
+//! Scripts are stored as text metas on the items and are run when the
+//! trigger happens:
+//!
+//! * `on_enter` on a room when an actor enters it.
+//! * `on_exit:<exit>` on a room when an actor passes the exit.
+//! * `on_take` on an item when an actor takes it.
+//! * `on_use` on an item when an actor uses it.
+//!
+//! The variables `actor`, `room`, `item` and `exit` contain the ids of the
+//! involved items where it makes sense.  The language has strings,
+//! integers, booleans, variables, `if`/`else`, `while` and the usual
+//! operators:
+//!
+//! ```text
+//! let count = get(room, "visits", 0) + 1;
+//! set(room, "visits", count);
+//! if count > 3 {
+//!     print("You know this place by heart.");
+//! }
+//! ```
+//!
+//! Available functions are `print`, `error`, `get`, `set`, `move`,
+//! `player`, `room_of`, `carries`, `random` and `cancel`.  `get(item, key,
+//! default)` returns the default if the meta is missing and `room_of` an
+//! empty string if the actor is in no room.  `cancel()` stops the trigger
+//! from happening, for example an actor won't pass the exit.
+//!
+//! Scripts run in a sandbox: they can only access the storage through
+//! these functions and are aborted after `STEP_LIMIT` steps, if they build
+//! strings longer than `STRING_LIMIT` or nest deeper than `DEPTH_LIMIT`.
+
+use core::{MutIngame, Item, Meta, GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use room::Room;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Maximal number of statements and expressions a script may evaluate.
+pub const STEP_LIMIT: u32 = 10000;
+/// Maximal length of a string a script may build, in bytes.
+pub const STRING_LIMIT: usize = 10000;
+/// Maximal nesting of blocks and expressions in a script.
+pub const DEPTH_LIMIT: usize = 64;
+
+/// Trigger on a room when an actor enters it.
+pub const ON_ENTER: &'static str = "on_enter";
+/// Trigger on an item when an actor takes it.
+pub const ON_TAKE: &'static str = "on_take";
+/// Trigger on an item when an actor uses it.
+pub const ON_USE: &'static str = "on_use";
+
+/// Name of the trigger on a room when an actor passes the exit.
+pub fn on_exit_trigger(exit_name: &str) -> String {
+	format!("on_exit:{}", exit_name)
+}
+
+/// A value in a script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	/// Text value.
+	Str(String),
+	/// Number value.
+	Int(i32),
+	/// Boolean value.
+	Bool(bool)
+}
+
+impl Value {
+	/// Check if the value counts as true in conditions.
+	pub fn is_true(&self) -> bool {
+		match *self {
+			Value::Str(ref s) => !s.is_empty(),
+			Value::Int(i) => i != 0,
+			Value::Bool(b) => b
+		}
+	}
+}
+
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Value::Str(ref s) => write!(f, "{}", s),
+			Value::Int(i) => write!(f, "{}", i),
+			Value::Bool(b) => write!(f, "{}", b)
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+	Ident(String),
+	Str(String),
+	Int(i32),
+	Sym(&'static str)
+}
+
+const SYMBOLS: &'static [&'static str] = &[
+	"==", "!=", "<=", ">=", "&&", "||",
+	"(", ")", "{", "}", ",", ";", "=", "<", ">", "+", "-", "*", "/", "%", "!"
+];
+
+fn tokenize(source: &str) -> GameResult<Vec<Token>> {
+	let chars: Vec<char> = source.chars().collect();
+	let mut tokens = Vec::new();
+	let mut i = 0;
+	'outer: while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+		} else if c == '#' {
+			while i < chars.len() && chars[i] != '\n' {
+				i += 1;
+			}
+		} else if c == '"' {
+			let mut text = String::new();
+			i += 1;
+			loop {
+				match chars.get(i) {
+					None => return Err(berr("Unterminated string in script")),
+					Some(&'"') => break,
+					Some(&'\\') => {
+						match chars.get(i + 1) {
+							Some(&'n') => text.push('\n'),
+							Some(&c) => text.push(c),
+							None => return Err(berr("Unterminated string in script"))
+						}
+						i += 1;
+					},
+					Some(&c) => text.push(c)
+				}
+				i += 1;
+			}
+			i += 1;
+			tokens.push(Token::Str(text));
+		} else if c.is_digit(10) {
+			let start = i;
+			while i < chars.len() && chars[i].is_digit(10) {
+				i += 1;
+			}
+			let number: String = chars[start..i].iter().cloned().collect();
+			let number = number.parse::<i32>()
+				.map_err(|_| berr(format!("Number too large in script: {}", number)))?;
+			tokens.push(Token::Int(number));
+		} else if c.is_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			tokens.push(Token::Ident(chars[start..i].iter().cloned().collect()));
+		} else {
+			for symbol in SYMBOLS {
+				let len = symbol.len();
+				if i + len <= chars.len() {
+					let candidate: String = chars[i..i + len].iter().cloned().collect();
+					if &candidate == symbol {
+						tokens.push(Token::Sym(symbol));
+						i += len;
+						continue 'outer
+					}
+				}
+			}
+			return Err(berr(format!("Unexpected character in script: {}", c)))
+		}
+	}
+	Ok(tokens)
+}
+
+/// An expression of the script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+	/// A constant value.
+	Value(Value),
+	/// Read a variable.
+	Var(String),
+	/// Unary operator and operand.
+	Unary(&'static str, Box<Expr>),
+	/// Binary operator and operands.
+	Binary(&'static str, Box<Expr>, Box<Expr>),
+	/// Call of a function with arguments.
+	Call(String, Vec<Expr>)
+}
+
+/// A statement of the script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+	/// Assign a value to a variable.
+	Let(String, Expr),
+	/// Evaluate an expression and ignore the result.
+	Expr(Expr),
+	/// Conditional with then and else branch.
+	If(Expr, Vec<Stmt>, Vec<Stmt>),
+	/// Loop while the condition is true.
+	While(Expr, Vec<Stmt>)
+}
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize,
+	depth: usize
+}
+
+const BINARY_LEVELS: &'static [&'static [&'static str]] = &[
+	&["||"],
+	&["&&"],
+	&["==", "!="],
+	&["<", ">", "<=", ">="],
+	&["+", "-"],
+	&["*", "/", "%"]
+];
+
+impl Parser {
+	fn enter(&mut self) -> GameResult<()> {
+		self.depth += 1;
+		if self.depth > DEPTH_LIMIT {
+			Err(berr("Script exceeded the nesting limit"))
+		} else {
+			Ok(())
+		}
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn is_sym(&self, symbol: &str) -> bool {
+		match self.peek() {
+			Some(&Token::Sym(s)) => s == symbol,
+			_ => false
+		}
+	}
+
+	fn is_ident(&self, name: &str) -> bool {
+		match self.peek() {
+			Some(&Token::Ident(ref s)) => s == name,
+			_ => false
+		}
+	}
+
+	fn expect_sym(&mut self, symbol: &str) -> GameResult<()> {
+		if self.is_sym(symbol) {
+			self.pos += 1;
+			Ok(())
+		} else {
+			Err(berr(format!("Expected '{}' in script", symbol)))
+		}
+	}
+
+	fn ident(&mut self) -> GameResult<String> {
+		match self.peek().cloned() {
+			Some(Token::Ident(name)) => {
+				self.pos += 1;
+				Ok(name)
+			},
+			_ => Err(berr("Expected a name in script"))
+		}
+	}
+
+	fn block(&mut self) -> GameResult<Vec<Stmt>> {
+		self.expect_sym("{")?;
+		self.enter()?;
+		let mut stmts = Vec::new();
+		while !self.is_sym("}") {
+			if self.peek().is_none() {
+				return Err(berr("Expected '}' in script"))
+			}
+			stmts.push(self.statement()?);
+		}
+		self.pos += 1;
+		self.depth -= 1;
+		Ok(stmts)
+	}
+
+	fn statement(&mut self) -> GameResult<Stmt> {
+		let stmt = if self.is_ident("let") {
+			self.pos += 1;
+			let name = self.ident()?;
+			self.expect_sym("=")?;
+			Stmt::Let(name, self.expression(0)?)
+		} else if self.is_ident("if") {
+			self.pos += 1;
+			let condition = self.expression(0)?;
+			let then_block = self.block()?;
+			let else_block = if self.is_ident("else") {
+				self.pos += 1;
+				if self.is_ident("if") {
+					vec![self.statement()?]
+				} else {
+					self.block()?
+				}
+			} else {
+				Vec::new()
+			};
+			return Ok(Stmt::If(condition, then_block, else_block))
+		} else if self.is_ident("while") {
+			self.pos += 1;
+			let condition = self.expression(0)?;
+			return Ok(Stmt::While(condition, self.block()?))
+		} else {
+			Stmt::Expr(self.expression(0)?)
+		};
+		if self.is_sym(";") {
+			self.pos += 1;
+		}
+		Ok(stmt)
+	}
+
+	fn expression(&mut self, level: usize) -> GameResult<Expr> {
+		if level >= BINARY_LEVELS.len() {
+			return self.unary()
+		}
+		if level == 0 {
+			self.enter()?;
+		}
+		let mut left = self.expression(level + 1)?;
+		loop {
+			let op = match self.peek() {
+				Some(&Token::Sym(s)) if BINARY_LEVELS[level].contains(&s) => s,
+				_ => break
+			};
+			self.pos += 1;
+			let right = self.expression(level + 1)?;
+			left = Expr::Binary(op, Box::new(left), Box::new(right));
+		}
+		if level == 0 {
+			self.depth -= 1;
+		}
+		Ok(left)
+	}
+
+	fn unary(&mut self) -> GameResult<Expr> {
+		if self.is_sym("!") || self.is_sym("-") {
+			let op = if self.is_sym("!") { "!" } else { "-" };
+			self.pos += 1;
+			self.enter()?;
+			let operand = self.unary()?;
+			self.depth -= 1;
+			return Ok(Expr::Unary(op, Box::new(operand)))
+		}
+		self.primary()
+	}
+
+	fn primary(&mut self) -> GameResult<Expr> {
+		match self.peek().cloned() {
+			Some(Token::Str(text)) => {
+				self.pos += 1;
+				Ok(Expr::Value(Value::Str(text)))
+			},
+			Some(Token::Int(i)) => {
+				self.pos += 1;
+				Ok(Expr::Value(Value::Int(i)))
+			},
+			Some(Token::Sym("(")) => {
+				self.pos += 1;
+				let expr = self.expression(0)?;
+				self.expect_sym(")")?;
+				Ok(expr)
+			},
+			Some(Token::Ident(name)) => {
+				self.pos += 1;
+				if name == "true" || name == "false" {
+					return Ok(Expr::Value(Value::Bool(name == "true")))
+				}
+				if !self.is_sym("(") {
+					return Ok(Expr::Var(name))
+				}
+				self.pos += 1;
+				let mut args = Vec::new();
+				while !self.is_sym(")") {
+					args.push(self.expression(0)?);
+					if !self.is_sym(")") {
+						self.expect_sym(",")?;
+					}
+				}
+				self.pos += 1;
+				Ok(Expr::Call(name, args))
+			},
+			_ => Err(berr("Unexpected end of expression in script"))
+		}
+	}
+}
+
+/// Parse a script.
+///
+/// # Failure
+/// Error if the script contains a syntax error.
+pub fn parse(source: &str) -> GameResult<Vec<Stmt>> {
+	let mut parser = Parser {
+		tokens: tokenize(source)?,
+		pos: 0,
+		depth: 0
+	};
+	let mut stmts = Vec::new();
+	while parser.peek().is_some() {
+		stmts.push(parser.statement()?);
+	}
+	Ok(stmts)
+}
+
+/// Parse a single expression, for example a condition.
+///
+/// # Failure
+/// Error if the expression contains a syntax error.
+pub fn parse_expression(source: &str) -> GameResult<Expr> {
+	let mut parser = Parser {
+		tokens: tokenize(source)?,
+		pos: 0,
+		depth: 0
+	};
+	let expr = parser.expression(0)?;
+	if parser.peek().is_some() {
+		return Err(berr("Unexpected input after expression in script"))
+	}
+	Ok(expr)
+}
+
+/// Runs parsed scripts with access to the game.
+pub struct Interpreter<'a, 'b: 'a> {
+	ingame: &'a mut MutIngame<'b>,
+	vars: HashMap<String, Value>,
+	steps: u32,
+	depth: usize,
+	cancelled: bool
+}
+
+impl<'a, 'b> Interpreter<'a, 'b> {
+	/// Create an interpreter with the given variables.
+	pub fn new(ingame: &'a mut MutIngame<'b>, vars: &[(&str, &str)]) -> Self {
+		Interpreter {
+			ingame: ingame,
+			vars: vars.iter()
+				.map(|&(name, value)| (name.to_string(), Value::Str(value.to_string())))
+				.collect(),
+			steps: 0,
+			depth: 0,
+			cancelled: false
+		}
+	}
+
+	/// Check if the script called `cancel()`.
+	pub fn cancelled(&self) -> bool {
+		self.cancelled
+	}
+
+	fn step(&mut self) -> GameResult<()> {
+		self.steps += 1;
+		if self.steps > STEP_LIMIT {
+			Err(berr("Script exceeded the step limit"))
+		} else {
+			Ok(())
+		}
+	}
+
+	fn enter(&mut self) -> GameResult<()> {
+		self.depth += 1;
+		if self.depth > DEPTH_LIMIT {
+			Err(berr("Script exceeded the nesting limit"))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Run the statements.
+	///
+	/// # Failure
+	/// Error if the script fails or exceeds one of the limits.
+	pub fn run(&mut self, stmts: &[Stmt]) -> GameResult<()> {
+		self.enter()?;
+		let result = self.run_stmts(stmts);
+		self.depth -= 1;
+		result
+	}
+
+	fn run_stmts(&mut self, stmts: &[Stmt]) -> GameResult<()> {
+		for stmt in stmts {
+			self.step()?;
+			match *stmt {
+				Stmt::Let(ref name, ref expr) => {
+					let value = self.eval(expr)?;
+					self.vars.insert(name.clone(), value);
+				},
+				Stmt::Expr(ref expr) => {
+					self.eval(expr)?;
+				},
+				Stmt::If(ref condition, ref then_block, ref else_block) => {
+					if self.eval(condition)?.is_true() {
+						self.run(then_block)?;
+					} else {
+						self.run(else_block)?;
+					}
+				},
+				Stmt::While(ref condition, ref block) => {
+					while self.eval(condition)?.is_true() {
+						self.run(block)?;
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Evaluate an expression.
+	///
+	/// # Failure
+	/// Error if the expression fails or exceeds one of the limits.
+	pub fn eval(&mut self, expr: &Expr) -> GameResult<Value> {
+		self.enter()?;
+		let value = self.eval_expr(expr);
+		self.depth -= 1;
+		value
+	}
+
+	fn eval_expr(&mut self, expr: &Expr) -> GameResult<Value> {
+		self.step()?;
+		match *expr {
+			Expr::Value(ref value) => Ok(value.clone()),
+			Expr::Var(ref name) => self.vars.get(name).cloned()
+				.ok_or(berr(format!("Unknown variable in script: {}", name))),
+			Expr::Unary(op, ref operand) => {
+				let value = self.eval(operand)?;
+				match (op, value) {
+					("!", value) => Ok(Value::Bool(!value.is_true())),
+					("-", Value::Int(i)) => Ok(Value::Int(i.wrapping_neg())),
+					_ => Err(berr(format!("Invalid operand for {} in script", op)))
+				}
+			},
+			Expr::Binary("&&", ref left, ref right) => {
+				let left = self.eval(left)?.is_true();
+				Ok(Value::Bool(left && self.eval(right)?.is_true()))
+			},
+			Expr::Binary("||", ref left, ref right) => {
+				let left = self.eval(left)?.is_true();
+				Ok(Value::Bool(left || self.eval(right)?.is_true()))
+			},
+			Expr::Binary(op, ref left, ref right) => {
+				let left = self.eval(left)?;
+				let right = self.eval(right)?;
+				binary(op, left, right)
+			},
+			Expr::Call(ref name, ref args) => {
+				let mut values = Vec::new();
+				for arg in args {
+					values.push(self.eval(arg)?);
+				}
+				self.call(name, values)
+			}
+		}
+	}
+
+	fn call(&mut self, name: &str, args: Vec<Value>) -> GameResult<Value> {
+		let text = |i: usize| args.get(i).map(|x| x.to_string()).unwrap_or(String::new());
+		match name {
+			"print" | "error" => {
+				let kind = if name == "print" {
+					MessageKind::Output
+				} else {
+					MessageKind::Error
+				};
+				let msg: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+				self.ingame.add_text(kind, &msg.join(" "));
+				Ok(Value::Bool(true))
+			},
+			"get" => {
+				let item = self.ingame.get_item::<Item>(&text(0));
+				let value = item.as_ref().and_then(|item| item.item_meta.get(&text(1)));
+				Ok(match value {
+					Some(&Meta::Int(i)) => Value::Int(i),
+					Some(&Meta::Text(ref s)) => Value::Str(s.clone()),
+					_ => args.get(2).cloned().unwrap_or(Value::Str(String::new()))
+				})
+			},
+			"set" => {
+				let mut item = self.ingame.get_item::<Item>(&text(0))
+						.ok_or(berr(format!("Item not found: {}", text(0))))?;
+				let meta = match args.get(2) {
+					Some(&Value::Int(i)) => Meta::Int(i),
+					_ => Meta::Text(text(2))
+				};
+				item.item_meta.insert(text(1), meta);
+				self.ingame.insert_item(item);
+				Ok(Value::Bool(true))
+			},
+			"move" => {
+				let actor = self.ingame.get_item::<Actor>(&text(0))
+						.ok_or(berr(format!("Actor not found: {}", text(0))))?;
+				let room = self.ingame.get_item::<Room>(&text(1))
+						.ok_or(berr(format!("Room not found: {}", text(1))))?;
+				self.ingame.warp_actor(&actor, room);
+				Ok(Value::Bool(true))
+			},
+			"player" => Ok(Value::Str(self.ingame.ingame.get_player()?.id)),
+			"room_of" => {
+				let actor = self.ingame.get_item::<Actor>(&text(0))
+						.ok_or(berr(format!("Actor not found: {}", text(0))))?;
+				let room = self.ingame.ingame.room_of_actor(&actor)
+					.map(|room| room.id)
+					.unwrap_or(String::new());
+				Ok(Value::Str(room))
+			},
+			"carries" => {
+				let carries = self.ingame.get_item::<Actor>(&text(0))
+					.map(|actor| actor.carries(&text(1)))
+					.unwrap_or(false);
+				Ok(Value::Bool(carries))
+			},
+			"random" => {
+				let (low, high) = match (args.get(0), args.get(1)) {
+					(Some(&Value::Int(low)), Some(&Value::Int(high))) => (low, high),
+					_ => return Err(berr("random expects two numbers"))
+				};
+				Ok(Value::Int(self.ingame.rng().range(low, high)))
+			},
+			"cancel" => {
+				self.cancelled = true;
+				Ok(Value::Bool(true))
+			},
+			_ => Err(berr(format!("Unknown function in script: {}", name)))
+		}
+	}
+}
+
+fn binary(op: &str, left: Value, right: Value) -> GameResult<Value> {
+	match (op, left, right) {
+		("==", left, right) => Ok(Value::Bool(left == right)),
+		("!=", left, right) => Ok(Value::Bool(left != right)),
+		("+", Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_add(b))),
+		("+", a, b) => {
+			let text = format!("{}{}", a, b);
+			if text.len() > STRING_LIMIT {
+				return Err(berr("Script exceeded the string limit"))
+			}
+			Ok(Value::Str(text))
+		},
+		("-", Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_sub(b))),
+		("*", Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_mul(b))),
+		("/", Value::Int(_), Value::Int(0)) | ("%", Value::Int(_), Value::Int(0)) =>
+			Err(berr("Division by zero in script")),
+		("/", Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_div(b))),
+		("%", Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_rem(b))),
+		("<", Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a < b)),
+		(">", Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a > b)),
+		("<=", Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a <= b)),
+		(">=", Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a >= b)),
+		("<", Value::Str(a), Value::Str(b)) => Ok(Value::Bool(a < b)),
+		(">", Value::Str(a), Value::Str(b)) => Ok(Value::Bool(a > b)),
+		("<=", Value::Str(a), Value::Str(b)) => Ok(Value::Bool(a <= b)),
+		(">=", Value::Str(a), Value::Str(b)) => Ok(Value::Bool(a >= b)),
+		(op, _, _) => Err(berr(format!("Invalid operands for {} in script", op)))
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Run a script with the given variables.
+	///
+	/// Returns false if the script called `cancel()`.
+	///
+	/// # Failure
+	/// Error if the script has a syntax error, fails or exceeds the step limit.
+	pub fn run_script(&mut self, source: &str,
+					  vars: &[(&str, &str)]) -> GameResult<bool> {
+		let stmts = parse(source)?;
+		let mut interpreter = Interpreter::new(self, vars);
+		interpreter.run(&stmts)?;
+		Ok(!interpreter.cancelled())
+	}
+
+	/// Evaluate a condition with the given variables.
+	///
+	/// An empty condition is always true.
+	///
+	/// # Failure
+	/// Error if the condition has a syntax error or fails.
+	pub fn eval_condition(&mut self, source: &str,
+						  vars: &[(&str, &str)]) -> GameResult<bool> {
+		if source.trim().is_empty() {
+			return Ok(true)
+		}
+		let expr = parse_expression(source)?;
+		let mut interpreter = Interpreter::new(self, vars);
+		Ok(interpreter.eval(&expr)?.is_true())
+	}
+
+	/// Run the script stored in the trigger meta of an item.
+	///
+	/// Returns None if there is no script, otherwise if the trigger was not
+	/// cancelled.
+	pub fn run_trigger(&mut self, item_id: &str, trigger: &str,
+					   vars: &[(&str, &str)]) -> GameResult<Option<bool>> {
+		let source = match self.get_item::<Item>(item_id) {
+			Some(item) => item.meta_text_or_default(trigger, "").to_string(),
+			None => return Ok(None)
+		};
+		if source.trim().is_empty() {
+			return Ok(None)
+		}
+		self.run_script(&source, vars).map(Some)
+	}
+}
+
+#[test]
+fn script_test() {
+	use core::{Ingame, Storage};
+	use base::BaseGame;
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room)
+		.with_item(Room::new("cellar"));
+	let mut ingame = Ingame::with_storage(storage);
+	let mut mut_ingame = MutIngame { ingame: &mut ingame };
+	assert!(mut_ingame.run_script("let i = ", &[]).is_err());
+	let script = "
+		# count the visits
+		let i = 0;
+		while i < 3 { let i = get(room, \"visits\", 0) + 1; set(room, \"visits\", i) }
+		if i == 3 && !carries(player(), \"lamp\") {
+			print(\"It's dark,\", \"you fall down.\");
+			move(player(), \"cellar\");
+		} else {
+			cancel();
+		}";
+	assert_eq!(true, mut_ingame.run_script(script, &[("room", "room")]).unwrap());
+	assert_eq!("It's dark, you fall down.",
+			mut_ingame.ingame.response().text(&MessageKind::Output));
+	assert_eq!("cellar", mut_ingame.ingame.room_of_player().unwrap().id);
+	assert_eq!(false, mut_ingame.run_script("cancel()", &[]).unwrap());
+	let err = mut_ingame.run_script("while true { }", &[]).unwrap_err();
+	assert_eq!("Script exceeded the step limit", err.to_string());
+	let err = mut_ingame.run_script("let s = \"ab\"; while true { let s = s + s }", &[])
+		.unwrap_err();
+	assert_eq!("Script exceeded the string limit", err.to_string());
+
+	let nested = |open: &str, inner: &str, close: &str, n: usize| {
+		format!("{}{}{}", open.repeat(n), inner, close.repeat(n))
+	};
+	assert!(parse_expression(&nested("(", "1", ")", DEPTH_LIMIT - 1)).is_ok());
+	for source in &[nested("(", "1", ")", 1000), nested("!", "true", "", 1000),
+					nested("-", "1", "", 1000),
+					nested("if true { ", "", "}", 1000)] {
+		let err = mut_ingame.run_script(source, &[]).unwrap_err();
+		assert_eq!("Script exceeded the nesting limit", err.to_string());
+	}
+	let mut expr = Expr::Value(Value::Bool(true));
+	for _ in 0..DEPTH_LIMIT {
+		expr = Expr::Unary("!", Box::new(expr));
+	}
+	let err = Interpreter::new(&mut mut_ingame, &[]).eval(&expr).unwrap_err();
+	assert_eq!("Script exceeded the nesting limit", err.to_string());
+}
@@ -8,17 +8,47 @@ use std::collections::HashMap;
 use std::io::Write;
 use std::error::Error;
 use std::fmt::Display;
+use std::rc::Rc;
 use core::berr;
 use core::GameResult;
+use rules::{ActionBuilder, gen_ruled_action};
+use parser::{Verb, Parsed, ParsedCommand, Conversation, AGAIN, tokenize_input};
+use markup::{Renderer, PlainRenderer, Style, styled};
+use response::{Message, MessageKind, ActionSource};
 
 /// Main Terminal UI type.
 pub struct Terminal {
 	/// The main game engine
 	pub ingame: Ingame,
 	/// The registered commands
-	pub commands: HashMap<String, Command>,
+	pub commands: HashMap<String, Rc<Command>>,
+	/// The registered verbs which use the parser
+	pub verbs: HashMap<String, Rc<Verb>>,
+	/// The conversations of the parser with the players by their ids
+	pub conversations: HashMap<String, Conversation>,
 	/// The prompt string
-	pub prompt: String
+	pub prompt: String,
+	/// Turns the markup of the responses into the output
+	pub renderer: Box<Renderer>,
+	/// Width to wrap the output to, no wrapping if None
+	pub width: Option<usize>
+}
+
+/// The reply of the terminal to one input.
+pub struct Reply {
+	/// The rendered output, empty if no action ran
+	pub output: String,
+	/// The rendered errors
+	pub errors: String,
+	/// The messages of the step including the errors of the input
+	pub messages: Vec<Message>
+}
+
+impl Reply {
+	/// Check if the input caused no errors.
+	pub fn is_ok(&self) -> bool {
+		self.errors.is_empty()
+	}
 }
 
 /// A command which is executed 
@@ -38,70 +68,212 @@ impl Terminal {
 		Terminal {
 			ingame: ingame,
 			commands: HashMap::new(),
-			prompt: "> ".to_string()
+			verbs: HashMap::new(),
+			conversations: HashMap::new(),
+			prompt: "> ".to_string(),
+			renderer: Box::new(PlainRenderer),
+			width: None
 		}
 	}
 
+	/// Consumes the terminal and returns new one with the given renderer.
+	///
+	/// Use for initialization.
+	pub fn with_renderer(mut self, renderer: Box<Renderer>) -> Self {
+		self.renderer = renderer;
+		self
+	}
+
+	/// Consumes the terminal and returns new one which wraps the output
+	/// to the width.
+	///
+	/// Use for initialization.
+	pub fn with_width(mut self, width: usize) -> Self {
+		self.width = Some(width);
+		self
+	}
+
+	/// Render the markup of a text.
+	fn render(&self, text: &str) -> String {
+		self.renderer.render(text, self.width)
+	}
+
+	/// Render an error message.
+	fn render_error(&self, text: &str) -> String {
+		self.render(&styled(Style::Error, text))
+	}
+
 	/// Perform one stop by executing a command
 	///
-	/// # Error
-	/// Returns an error if the input produces an error.
-	pub fn step(&mut self, input: &str) -> GameResult<String> {
+	/// The output and the errors are rendered by the renderer of the terminal.
+	/// Verbs are checked before the commands.  If the parser asked which
+	/// item is meant, an input which is neither a verb nor a command is
+	/// taken as answer.  "again" or "g" repeat the last input.
+	///
+	/// Errors of the input and of the step are returned together with the
+	/// output of the step.
+	pub fn step(&mut self, input: &str) -> Reply {
+		match self.step_input(input) {
+			Ok(reply) => reply,
+			Err(err) => self.error_reply(&err.to_string())
+		}
+	}
+
+	/// Run the input, errors which prevent the step are returned.
+	fn step_input(&mut self, input: &str) -> GameResult<Reply> {
 		// Divide the keywords into the their tokens.
 		let keywords: Vec<&str> = input.split(" ").collect();
 
 		// Abort on no input
 		if keywords.len() == 0 {
-			return Err(berr("Keywords are empty"))
+			return Err(berr(self.ingame.msg("cmd.empty", &[])))
+		}
+
+		let tokens = tokenize_input(input);
+		if tokens.len() == 1 && AGAIN.contains(&tokens[0].as_str()) {
+			let last_input = match self.conversation().last_input.clone() {
+				Some(last_input) => last_input,
+				None => return Err(berr(self.ingame.msg("cmd.nothing_to_repeat", &[])))
+			};
+			return self.step_input(&last_input)
+		}
+		let pending = self.conversation().pending.take();
+		if tokens.first().map(|verb| self.verbs.contains_key(verb)).unwrap_or(false) {
+			return self.step_verb(tokens)
 		}
 
 		// Get the command entry according to the first token.
 		//
-		if let Some(command) = self.commands.get(keywords[0].trim()) {
-			// Extruct and run action.
-			// If the command replies an Ok, it will contain an action which
-			// will be added as one time action.
-			// If the command replies an Err, no ingame.step will be called
-			// And the error string will be printed.
-			let command_fn = &command.action_fn;
-			match command_fn(&mut self.ingame, &keywords) {
-				Ok(action) =>  {
-					self.ingame.add_one_time_action(action);
-					self.ingame.step();
-					let ingame_error = self.ingame.get_response("err");
-					if ingame_error.is_empty() {
-						Ok(format!("{}\n", self.ingame.get_response("out")))
-					} else {
-						Err(berr(format!("Error: {}\n", ingame_error)))
-					}
-				},
-				Err(err) => Err(berr(format!("{}", err)))
-			}
+		if let Some(command) = self.commands.get(keywords[0].trim()).cloned() {
+			// The rules run first, the action of the command is only
+			// created if they don't block or replace it.  Errors of the
+			// command are errors of the step.
+			self.conversation().last_input = Some(input.to_string());
+			let parsed = ParsedCommand::from_keywords(&keywords);
+			let keywords: Vec<String> = keywords.iter().map(|x| x.to_string()).collect();
+			self.run_action(parsed, Box::new(move |ingame| {
+				let keywords: Vec<&str> = keywords.iter().map(|x| x.as_str()).collect();
+				(command.action_fn)(ingame, &keywords)
+			}))
+		} else if let Some(ambiguity) = pending {
+			// Add the answer to the name and try again.
+			self.step_verb(ambiguity.answer(&tokens))
 		} else {
 			// Tell the user, the command was not found.
-			Err(berr(
-				format!("Could not find command '{}'\n", keywords[0].trim())))
+			Err(berr(self.ingame.msg("cmd.not_found", &[("command", keywords[0].trim())])))
+		}
+	}
+
+	/// Parse the tokens and run the action of the verb.
+	///
+	/// Asks the player if an object is ambiguous.
+	fn step_verb(&mut self, tokens: Vec<String>) -> GameResult<Reply> {
+		let player = self.ingame.get_player()?;
+		let conversation = self.conversation().clone();
+		let parsed = match self.ingame.parse_tokens(&player, &tokens, &conversation) {
+			Ok(Parsed::Command(parsed)) => parsed,
+			Ok(Parsed::Ambiguous(ambiguity)) => {
+				let question = ambiguity.question(&self.ingame);
+				self.conversation().pending = Some(ambiguity);
+				return Ok(Reply {
+					output: format!("{}\n", self.render(&question)),
+					errors: String::new(),
+					messages: vec![Message {
+						kind: MessageKind::Output,
+						source: ActionSource::External,
+						subject: None,
+						order: 0,
+						text: question
+					}]
+				})
+			},
+			Err(err) => return Err(err)
+		};
+		let verb = match self.verbs.get(&parsed.verb) {
+			Some(verb) => verb.clone(),
+			None => return Err(berr(self.ingame.msg("cmd.not_found",
+					&[("command", &parsed.verb)])))
+		};
+		self.conversation().remember(&parsed, &tokens);
+		let build_parsed = parsed.clone();
+		self.run_action(parsed, Box::new(move |ingame| {
+			(verb.action_fn)(ingame, &build_parsed)
+		}))
+	}
+
+	/// Get the conversation of the parser with the current player.
+	pub fn conversation(&mut self) -> &mut Conversation {
+		let player = self.ingame.get_player().map(|x| x.id).unwrap_or(String::new());
+		self.conversations.entry(player).or_insert(Conversation::default())
+	}
+
+	/// Run the rules and the action of a command in one step.
+	fn run_action(&mut self, parsed: ParsedCommand,
+				  build: ActionBuilder) -> GameResult<Reply> {
+		self.ingame.add_one_time_action(gen_ruled_action(parsed, build));
+		self.ingame.step();
+		let out = self.ingame.response().text(&MessageKind::Output);
+		let ingame_error = self.ingame.response().text(&MessageKind::Error);
+		let output = if out.is_empty() && !ingame_error.is_empty() {
+			String::new()
+		} else {
+			format!("{}\n", self.render(&out))
+		};
+		let errors = if ingame_error.is_empty() {
+			String::new()
+		} else {
+			let msg = self.ingame.msg_markup("cmd.error", &[("error", &ingame_error)]);
+			format!("{}\n", self.render_error(&msg))
+		};
+		Ok(Reply {
+			output: output,
+			errors: errors,
+			messages: self.ingame.response().messages().to_vec()
+		})
+	}
+
+	/// Create the reply to an input which could not run.
+	fn error_reply(&self, msg: &str) -> Reply {
+		Reply {
+			output: String::new(),
+			errors: format!("{}\n", self.render_error(msg)),
+			messages: vec![Message {
+				kind: MessageKind::Error,
+				source: ActionSource::External,
+				subject: None,
+				order: 0,
+				text: msg.to_string()
+			}]
 		}
 	}
 
 	/// Runs the repl.
 	pub fn run(&mut self) {
-		print!("Commands: {}\n", self.commands.len());
+		let count = self.commands.len().to_string();
+		print!("{}\n", self.ingame.msg("cmd.commands", &[("count", &count)]));
 		loop {
-			if self.ingame.get_response("done") != "" {
+			if self.ingame.response().contains(&MessageKind::Done) {
 				break;
 			}
 			let input = line_input(&self.prompt).expect("IO Error");
-			match self.step(&input) {
-				Ok(msg) => println!("{}", msg),
-				Err(err) => println!("{}", err)
+			let reply = self.step(&input);
+			if !reply.output.is_empty() {
+				println!("{}", reply.output);
+			}
+			if !reply.errors.is_empty() {
+				println!("{}", reply.errors);
 			}
 		}
 	}
 
 	/// Add a new command to the terminal.
 	pub fn add_command(&mut self, command: Command) {
-		self.commands.insert(command.keyword.clone(), command);
+		self.commands.insert(command.keyword.clone(), Rc::new(command));
+	}
+
+	/// Add a new verb to the terminal.
+	pub fn add_verb(&mut self, verb: Verb) {
+		self.verbs.insert(verb.keyword.clone(), Rc::new(verb));
 	}
 }
 
@@ -133,4 +305,56 @@ pub fn multiline_input<S: Into<String>>(term: S) -> Result<String, io::Error> {
 		io::stdin().read_line(&mut input)?;
 	}
 	Ok(res.trim().to_string())
-}
\ No newline at end of file
+}
+
+#[test]
+fn terminal_parser_test() {
+	use core::Storage;
+	use actor::Actor;
+	use room::Room;
+	use base::BaseGame;
+	use thing::Thing;
+	use inventory::{gen_take_verb, gen_drop_verb};
+	use thing::gen_examine_verb;
+	use rules::{Rule, RulePhase};
+	let mut room = Room::new("room");
+	room.actors.push("player".to_string());
+	room.items.push("red-ball".to_string());
+	room.items.push("blue-ball".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room)
+		.with_item(Thing::new("red-ball", "Red ball").with_descriptions("", "It's red."))
+		.with_item(Thing::new("blue-ball", "Blue ball"))
+		.with_item(Rule::new("blue", RulePhase::Instead, "examine",
+				"print(\"Too blue.\")").with_object("blue-ball"))
+		.with_item(Rule::new("mute", RulePhase::Before, "ask", "cancel()"));
+	let mut terminal = Terminal::new(Ingame::with_storage(storage));
+	terminal.add_verb(gen_take_verb("take"));
+	terminal.add_verb(gen_drop_verb("drop"));
+	terminal.add_verb(gen_examine_verb("examine"));
+	terminal.add_command(Command {
+		keyword: "ask".to_string(),
+		action_fn: Box::new(|_, _| panic!("The blocked command asked"))
+	});
+	assert!(!terminal.step("g").is_ok());
+	assert!(terminal.step("ask").is_ok());
+	assert_eq!("Too blue.\n", terminal.step("examine blue ball").output);
+	assert_eq!("Which ball do you mean: Red ball or Blue ball?\n",
+			terminal.step("take ball").output);
+	assert_eq!("Taken: Red ball\n", terminal.step("red").output);
+	assert_eq!("It's red.\n", terminal.step("examine it").output);
+	assert_eq!("It's red.\n", terminal.step("again").output);
+	assert!(terminal.step("take blue ball").is_ok());
+	assert_eq!("Dropped: Red ball\nDropped: Blue ball\n",
+			terminal.step("drop red ball and it").output);
+	assert_eq!("Taken: Red ball\nTaken: Blue ball\n", terminal.step("take them").output);
+	terminal.step("drop blue ball");
+	let reply = terminal.step("take blue ball and red ball");
+	assert_eq!("Taken: Blue ball\n", reply.output);
+	assert_eq!("Error: There is no Red ball here\n", reply.errors);
+	assert_eq!(Some("blue-ball".to_string()), reply.messages[0].subject);
+	assert_eq!(MessageKind::Error, reply.messages[1].kind);
+	assert_eq!("Could not find command 'dance'\n", terminal.step("dance").errors);
+}
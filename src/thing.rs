@@ -0,0 +1,227 @@
+#![warn(missing_docs)]
+
+//! Things which the player can look at.
+//!
+//! A thing has a display name, a short description which is used as
+//! fallback, a long description shown by `examine` and nouns and adjectives
+//! under which the player can refer to it.  The player can examine things in the room
+//! or in the inventory and the actors in the room.
+
+use core::{Ingame, MutIngame, Item, Meta, Action, Itemizeable,
+				GameResult, berr};
+use response::MessageKind;
+use actor::Actor;
+use container::Container;
+use parser::Verb;
+
+/// An item with descriptions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thing {
+	/// Id of the item.
+	pub id: String,
+	/// Displayed name.
+	pub name: String,
+	/// Description in a few words.
+	pub short_description: String,
+	/// Description shown when the thing is examined.
+	pub long_description: String,
+	/// Words which refer to the thing besides its name.
+	pub nouns: Vec<String>,
+	/// Words which describe the thing closer, like "rusty".
+	pub adjectives: Vec<String>
+}
+
+impl Thing {
+	/// Create a thing without descriptions.
+	pub fn new<S: Into<String>>(id: S, name: S) -> Self {
+		Thing {
+			id: id.into(),
+			name: name.into(),
+			short_description: String::new(),
+			long_description: String::new(),
+			nouns: Vec::new(),
+			adjectives: Vec::new()
+		}
+	}
+
+	/// Consumes the thing and returns new one with the given descriptions.
+	///
+	/// Use for initialization.
+	pub fn with_descriptions<S: Into<String>>(mut self, short: S, long: S) -> Self {
+		self.short_description = short.into();
+		self.long_description = long.into();
+		self
+	}
+
+	/// Consumes the thing and returns new one with an added noun.
+	///
+	/// Use for initialization.
+	pub fn with_noun<S: Into<String>>(mut self, noun: S) -> Self {
+		self.nouns.push(noun.into());
+		self
+	}
+
+	/// Consumes the thing and returns new one with an added adjective.
+	///
+	/// Use for initialization.
+	pub fn with_adjective<S: Into<String>>(mut self, adjective: S) -> Self {
+		self.adjectives.push(adjective.into());
+		self
+	}
+
+	/// Get the long description, the short one if it's empty.
+	pub fn description(&self) -> &str {
+		if self.long_description.is_empty() {
+			&self.short_description
+		} else {
+			&self.long_description
+		}
+	}
+}
+
+impl Itemizeable for Thing {
+	fn item_type() -> Option<&'static str> {
+		Some("thing")
+	}
+	fn from_item(item: &Item) -> Option<Box<Self>> {
+		if item.item_type != "thing" {
+			None
+		} else {
+			Some(Box::new(Thing {
+				id: item.item_id.clone(),
+				name: item.meta_text_or_default("name", &item.item_id).to_string(),
+				short_description: item.meta_text_or_default("short", "").to_string(),
+				long_description: item.meta_text_or_default("long", "").to_string(),
+				nouns: item.meta_textvec_or_default("nouns", &[]).to_vec(),
+				adjectives: item.meta_textvec_or_default("adjectives", &[]).to_vec()
+			}))
+		}
+	}
+	fn to_item(&self) -> Item {
+		let mut item = Item::new("thing".to_string(), self.id.clone());
+		self.merge_into_item(&mut item);
+		item
+	}
+	fn merge_into_item(&self, item: &mut Item) {
+		let metas = &mut item.item_meta;
+		metas.insert("name".to_string(), Meta::Text(self.name.clone()));
+		metas.insert("short".to_string(), Meta::Text(self.short_description.clone()));
+		metas.insert("long".to_string(), Meta::Text(self.long_description.clone()));
+		metas.insert("nouns".to_string(), Meta::TextVec(self.nouns.clone()));
+		metas.insert("adjectives".to_string(), Meta::TextVec(self.adjectives.clone()));
+	}
+	fn get_id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl Ingame {
+	/// Find the id of something the actor can examine by name.
+	///
+	/// Searches the inventory, the items in the room including open
+	/// containers and the actors in the room in this order.
+	///
+	/// # Failure
+	/// Error if nothing has the name.
+	pub fn resolve_thing(&self, actor: &Actor, name: &str) -> GameResult<String> {
+		let room = self.room_of_actor(actor)?;
+		let inventory = self.reachable_items(&actor.items);
+		let items = self.reachable_items(&room.items);
+		self.find_item_in(&inventory, name)
+			.or(self.find_item_in(&items, name))
+			.or(self.find_item_in(&room.actors, name))
+			.ok_or(berr(self.msg("item.not_here", &[("item", name)])))
+	}
+}
+
+impl<'a> MutIngame<'a> {
+	/// Print the description of a thing, an actor or any other item to out.
+	///
+	/// The contents of open containers are listed too.
+	pub fn display_description(&mut self, item_id: &str) -> GameResult<()> {
+		let description = if let Some(thing) = self.get_item::<Thing>(item_id) {
+			thing.description().to_string()
+		} else if let Some(actor) = self.get_item::<Actor>(item_id) {
+			actor.description.clone()
+		} else {
+			String::new()
+		};
+		let name = self.ingame.item_name(item_id);
+		if description.trim().is_empty() {
+			let msg = self.ingame.msg("item.nothing_special", &[("item", &name)]);
+			self.add_message(MessageKind::Output, item_id, &msg);
+		} else {
+			self.add_message(MessageKind::Output, item_id, description.trim());
+		}
+		match self.get_item::<Container>(item_id) {
+			Some(ref container) if container.open => self.display_container(container),
+			_ => Ok(())
+		}
+	}
+}
+
+/// Creates an Action which shows the description of something.
+pub fn gen_examine_action(name: String) -> Action {
+	Box::new(move |ingame, _| {
+		let player = ingame.ingame.get_player()?;
+		let item_id = ingame.ingame.resolve_thing(&player, &name)?;
+		ingame.display_description(&item_id)
+	})
+}
+
+/// Verb which shows the descriptions of things, `examine <things>`.
+pub fn gen_examine_verb<S: Into<String>>(keyword: S) -> Verb {
+	Verb {
+		keyword: keyword.into(),
+		action_fn: Box::new(|ingame, parsed| {
+			let items = parsed.expect_direct(ingame)?;
+			Ok(Box::new(move |ingame, _| {
+				for item_id in &items {
+					ingame.display_description(item_id)?;
+				}
+				Ok(())
+			}))
+		})
+	}
+}
+
+#[test]
+fn thing_test() {
+	use core::Storage;
+	use room::Room;
+	use base::{BaseGame, gen_display_current_room_action};
+	let mut room = Room::new("room");
+	room.name = "Hall".to_string();
+	room.actors.push("player".to_string());
+	room.actors.push("guard".to_string());
+	room.items.push("lamp".to_string());
+	room.items.push("chest".to_string());
+	let storage = Storage::new("storage")
+		.with_item(Actor::new("player").with_name("Player"))
+		.with_item(Actor::new("guard").with_name("Guard").with_description("He is tired."))
+		.with_item(BaseGame { player: "player".to_string() })
+		.with_item(room)
+		.with_item(Thing::new("lamp", "Lamp")
+			.with_descriptions("a brass lamp", "The lamp is old but works.")
+			.with_noun("lantern"))
+		.with_item(Thing::new("chest", "Chest").with_descriptions("a chest", ""))
+		.with_item(Container::new("chest").with_item("coin"));
+	let mut ingame = Ingame::with_storage(storage);
+	let run = |ingame: &mut Ingame, action: Action| {
+		ingame.add_one_time_action(action);
+		ingame.step();
+		let response = ingame.response();
+		format!("{}{}", response.text(&MessageKind::Output), response.text(&MessageKind::Error))
+	};
+	assert_eq!("[h]Room: [room]Hall[/room][/h]\n\nItems: Lamp, Chest\nActors: Guard",
+			run(&mut ingame, gen_display_current_room_action()));
+	assert_eq!("The lamp is old but works.",
+			run(&mut ingame, gen_examine_action("Lantern".to_string())));
+	assert_eq!("He is tired.", run(&mut ingame, gen_examine_action("guard".to_string())));
+	assert_eq!("a chest\nThe Chest contains: coin",
+			run(&mut ingame, gen_examine_action("chest".to_string())));
+	assert_eq!("You see nothing special about coin.",
+			run(&mut ingame, gen_examine_action("coin".to_string())));
+	assert_eq!("There is no dragon here",
+			run(&mut ingame, gen_examine_action("dragon".to_string())));
+}
@@ -0,0 +1,39 @@
+#![warn(missing_docs)]
+
+//! File helpers shared by the modules which save worlds.
+
+use core::GameResult;
+use std::io::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the saves of this process to give each a temporary file.
+static SAVE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Write the text to a file at the given path.
+///
+/// The text is written to a temporary file next to the target first and
+/// renamed afterwards, so an interrupted save never leaves a broken file.
+/// The name of the temporary file contains the process id and a counter,
+/// so saves running at the same time don't share it.
+pub fn write_atomic<P: AsRef<Path>>(path: P, text: &str) -> GameResult<()> {
+	let path = path.as_ref();
+	let file_name = path.file_name()
+			.ok_or(io::Error::new(io::ErrorKind::InvalidInput, "path without file name"))?
+			.to_string_lossy()
+			.into_owned();
+	let tmp_path = path.with_file_name(format!(".{}.{}-{}.tmp", file_name, process::id(),
+			SAVE_COUNTER.fetch_add(1, Ordering::SeqCst)));
+	{
+		let mut out_file = fs::File::create(&tmp_path)?;
+		write!(out_file, "{}", text)?;
+		out_file.sync_all()?;
+	}
+	if let Err(err) = fs::rename(&tmp_path, path) {
+		let _ = fs::remove_file(&tmp_path);
+		Err(err)?;
+	}
+	Ok(())
+}
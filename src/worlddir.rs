@@ -0,0 +1,537 @@
+#![warn(missing_docs)]
+
+//! A world format with one file per item for version control.
+//!
+//! The directory holds `storage.json` with the id of the storage and one
+//! directory per item type with a file per item:
+//!
+//! ```text
+//! castle/
+//!     storage.json
+//!     actor/hero.json
+//!     room/hall.json
+//!     room/kitchen.json
+//! ```
+//!
+//! The files are pretty printed JSON with sorted keys, so saving the same
+//! world twice gives the same files and a diff shows which items changed.
+//! Texts are JSON strings, integers are numbers, text vectors are arrays
+//! and binary metas are objects like `{"binary": [1, 2]}`.  Tables like the
+//! exits of rooms are objects of strings.
+//!
+//! File names are the ids with characters other than lowercase letters,
+//! digits, `-` and `_` percent encoded.  Uppercase letters are encoded too,
+//! so ids which only differ in case don't share a file on file systems
+//! which ignore case.  The id inside the file is the one which counts.
+//!
+//! Files and directories starting with a dot like `.git` are skipped and so
+//! are files whose names are not encoded like `file_name` does.  The
+//! storage file lists the files of the last save and saving only removes
+//! those and the directories which it emptied, other files in the
+//! directory are left alone.
+//!
+//! `DirBackend` reads and writes the directory one item at a time.
+
+use core::{Storage, Item, Meta, GameResult, berr, serialize_hashmap};
+use backend::Backend;
+use worldfile::{is_table, decode_table};
+use util::write_atomic;
+use rustc_serialize::json::{self, Json};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// File in the world directory which holds the id of the storage.
+pub const STORAGE_FILE: &'static str = "storage.json";
+
+/// Extension of the item files.
+pub const ITEM_FILE_EXTENSION: &'static str = "json";
+
+/// Get the file name for an id, without extension.
+pub fn file_name(id: &str) -> String {
+	if id.is_empty() {
+		return "%".to_string()
+	}
+	let mut res = String::new();
+	for c in id.chars() {
+		if c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_' {
+			res.push(c);
+		} else {
+			let mut buf = [0; 4];
+			for byte in c.encode_utf8(&mut buf).bytes() {
+				res.push_str(&format!("%{:02X}", byte));
+			}
+		}
+	}
+	res
+}
+
+fn meta_to_json(meta: &Meta, table: bool) -> Json {
+	match *meta {
+		Meta::Text(ref text) => match decode_table(text).filter(|_| table) {
+			Some(map) => Json::Object(map.into_iter()
+				.map(|(key, value)| (key, Json::String(value))).collect()),
+			None => Json::String(text.clone())
+		},
+		Meta::Int(int) => Json::I64(int as i64),
+		Meta::TextVec(ref texts) => Json::Array(texts.iter()
+			.map(|text| Json::String(text.clone())).collect()),
+		Meta::Binary(ref bytes) => {
+			let mut object = BTreeMap::new();
+			object.insert("binary".to_string(), Json::Array(bytes.iter()
+				.map(|byte| Json::U64(*byte as u64)).collect()));
+			Json::Object(object)
+		}
+	}
+}
+
+fn meta_from_json(json: &Json, table: bool) -> Option<Meta> {
+	match *json {
+		Json::String(ref text) => Some(Meta::Text(text.clone())),
+		Json::Object(ref object) if table => object.iter()
+			.map(|(key, value)| value.as_string().map(|x| (key.clone(), x.to_string())))
+			.collect::<Option<HashMap<String, String>>>()
+			.map(|map| Meta::Text(serialize_hashmap(&map))),
+		Json::I64(int) if int >= i32::min_value() as i64 && int <= i32::max_value() as i64 =>
+			Some(Meta::Int(int as i32)),
+		Json::U64(int) if int <= i32::max_value() as u64 => Some(Meta::Int(int as i32)),
+		Json::Array(ref texts) => texts.iter()
+			.map(|text| text.as_string().map(|x| x.to_string()))
+			.collect::<Option<Vec<String>>>()
+			.map(Meta::TextVec),
+		Json::Object(ref object) if object.len() == 1 => object.get("binary")
+			.and_then(|bytes| bytes.as_array())
+			.and_then(|bytes| bytes.iter()
+				.map(|byte| byte.as_u64().filter(|x| *x <= 255).map(|x| x as u8))
+				.collect::<Option<Vec<u8>>>())
+			.map(Meta::Binary),
+		_ => None
+	}
+}
+
+fn pretty(json: &Json) -> String {
+	format!("{}\n", json::as_pretty_json(json).indent(2))
+}
+
+/// Get the content of the file of an item.
+pub fn item_to_json(item: &Item) -> String {
+	let metas: BTreeMap<String, Json> = item.item_meta.iter()
+		.map(|(key, meta)| (key.clone(), meta_to_json(meta, is_table(&item.item_type, key))))
+		.collect();
+	let mut object = BTreeMap::new();
+	object.insert("id".to_string(), Json::String(item.item_id.clone()));
+	object.insert("type".to_string(), Json::String(item.item_type.clone()));
+	object.insert("meta".to_string(), Json::Object(metas));
+	pretty(&Json::Object(object))
+}
+
+/// Read an item from the content of its file.
+pub fn item_from_json(source: &str) -> GameResult<Item> {
+	let json = Json::from_str(source)?;
+	let text = |key: &str| json.find(key).and_then(|x| x.as_string())
+		.map(|x| x.to_string())
+		.ok_or(berr(format!("Expected {} as string", key)));
+	let mut item = Item::new(text("type")?, text("id")?);
+	let metas = json.find("meta").and_then(|x| x.as_object())
+		.ok_or(berr("Expected meta as object"))?;
+	for (key, value) in metas.iter() {
+		let meta = meta_from_json(value, is_table(&item.item_type, key))
+			.ok_or(berr(format!("Invalid meta: {}", key)))?;
+		item.item_meta.insert(key.clone(), meta);
+	}
+	Ok(item)
+}
+
+fn read_file(path: &Path) -> GameResult<String> {
+	let mut source = String::new();
+	fs::File::open(path)?.read_to_string(&mut source)?;
+	Ok(source)
+}
+
+/// Write the file unless it already has the content, see `write_atomic`.
+fn write_file(path: &Path, content: &str) -> GameResult<()> {
+	if path.is_file() && read_file(path)? == content {
+		return Ok(())
+	}
+	write_atomic(path, content)
+}
+
+/// Get the id from a file name made by `file_name`.
+pub fn id_from_file_name(name: &str) -> Option<String> {
+	if name == "%" {
+		return Some(String::new())
+	}
+	let mut bytes = Vec::new();
+	let mut rest = name;
+	while let Some(c) = rest.chars().next() {
+		if c == '%' {
+			let byte = rest.get(1..3).and_then(|x| u8::from_str_radix(x, 16).ok())?;
+			bytes.push(byte);
+			rest = &rest[3..];
+		} else {
+			bytes.extend(c.to_string().bytes());
+			rest = &rest[c.len_utf8()..];
+		}
+	}
+	String::from_utf8(bytes).ok()
+}
+
+/// Check if the name is made by `file_name`.
+fn is_encoded(name: &str) -> bool {
+	id_from_file_name(name).map(|id| file_name(&id) == name).unwrap_or(false)
+}
+
+fn is_hidden(path: &Path) -> bool {
+	path.file_name().map(|x| x.to_string_lossy().starts_with('.')).unwrap_or(false)
+}
+
+fn is_item_file(path: &Path) -> bool {
+	path.is_file() && !is_hidden(path)
+		&& path.extension().map(|x| x == ITEM_FILE_EXTENSION).unwrap_or(false)
+		&& path.file_stem().and_then(|x| x.to_str()).map(is_encoded).unwrap_or(false)
+}
+
+fn item_path(dir: &Path, item: &Item) -> PathBuf {
+	dir.join(relative_item_path(item))
+}
+
+/// Get the path of the item file inside the world directory as it is
+/// listed in the storage file.
+fn relative_item_path(item: &Item) -> String {
+	format!("{}/{}.{}", file_name(&item.item_type), file_name(&item.item_id),
+			ITEM_FILE_EXTENSION)
+}
+
+/// Get the path of a file listed in the storage file.
+///
+/// Returns None if it's no item file made by `relative_item_path`, so a
+/// changed storage file can't point outside of the directory.
+fn listed_path(dir: &Path, relative: &str) -> Option<PathBuf> {
+	let parts: Vec<&str> = relative.split('/').collect();
+	if parts.len() != 2 || !is_encoded(parts[0]) {
+		return None
+	}
+	let suffix = format!(".{}", ITEM_FILE_EXTENSION);
+	if !parts[1].ends_with(&suffix) || !is_encoded(&parts[1][..parts[1].len() - suffix.len()]) {
+		return None
+	}
+	Some(dir.join(parts[0]).join(parts[1]))
+}
+
+fn write_storage_file(dir: &Path, id: &str, files: &BTreeSet<String>) -> GameResult<()> {
+	let mut object = BTreeMap::new();
+	object.insert("id".to_string(), Json::String(id.to_string()));
+	object.insert("files".to_string(), Json::Array(files.iter()
+		.map(|file| Json::String(file.clone())).collect()));
+	write_file(&dir.join(STORAGE_FILE), &pretty(&Json::Object(object)))
+}
+
+/// Read the id of the storage and the files of the last save.
+fn read_storage_file(dir: &Path) -> GameResult<(String, BTreeSet<String>)> {
+	let path = dir.join(STORAGE_FILE);
+	let json = Json::from_str(&read_file(&path)?)?;
+	let id = json.find("id").and_then(|x| x.as_string())
+		.ok_or(berr(format!("{}: Expected id as string", path.display())))?;
+	let files = match json.find("files") {
+		Some(files) => files.as_array()
+			.and_then(|files| files.iter()
+				.map(|file| file.as_string().map(|x| x.to_string()))
+				.collect::<Option<BTreeSet<String>>>())
+			.ok_or(berr(format!("{}: Expected files as array of strings",
+					path.display())))?,
+		None => BTreeSet::new()
+	};
+	Ok((id.to_string(), files))
+}
+
+/// Get the paths of all item files, sorted.
+///
+/// Hidden files and directories and files with names which are not
+/// encoded by `file_name` are skipped.
+fn item_files(dir: &Path) -> GameResult<Vec<PathBuf>> {
+	let mut paths = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		let type_dir = entry?.path();
+		if !type_dir.is_dir() || is_hidden(&type_dir) {
+			continue
+		}
+		for entry in fs::read_dir(&type_dir)? {
+			let path = entry?.path();
+			if is_item_file(&path) {
+				paths.push(path);
+			}
+		}
+	}
+	paths.sort();
+	Ok(paths)
+}
+
+/// Read an item file and check that it is in the directory of its type.
+fn read_item_file(path: &Path) -> GameResult<Item> {
+	let item = match item_from_json(&read_file(path)?) {
+		Ok(item) => item,
+		Err(err) => return Err(berr(format!("{}: {}", path.display(), err)))
+	};
+	let type_dir = path.parent().and_then(|x| x.file_name())
+		.map(|x| x.to_string_lossy().into_owned());
+	if type_dir != Some(file_name(&item.item_type)) {
+		return Err(berr(format!("{}: Item of type {} in the wrong directory",
+				path.display(), item.item_type)))
+	}
+	Ok(item)
+}
+
+/// Save the storage to a directory with one file per item.
+///
+/// Files which the last save wrote for items which are no longer in the
+/// storage are removed and so are the type directories which become
+/// empty.  Other files and directories are left alone.  Files whose
+/// content didn't change are not written.
+pub fn save_world_dir<P: AsRef<Path>>(storage: &Storage, dir: P) -> GameResult<()> {
+	let dir = dir.as_ref();
+	fs::create_dir_all(dir)?;
+	let saved = if dir.join(STORAGE_FILE).is_file() {
+		read_storage_file(dir)?.1
+	} else {
+		BTreeSet::new()
+	};
+	let mut written: BTreeSet<String> = BTreeSet::new();
+	for item in storage.all_of_type::<Item>() {
+		let path = item_path(dir, &item);
+		fs::create_dir_all(path.parent().unwrap())?;
+		write_file(&path, &item_to_json(&item))?;
+		written.insert(relative_item_path(&item));
+	}
+	let mut type_dirs: HashSet<PathBuf> = HashSet::new();
+	for relative in saved.difference(&written) {
+		if let Some(path) = listed_path(dir, relative).filter(|path| path.is_file()) {
+			fs::remove_file(&path)?;
+			type_dirs.insert(path.parent().unwrap().to_path_buf());
+		}
+	}
+	write_storage_file(dir, storage.id(), &written)?;
+	for type_dir in type_dirs {
+		if fs::read_dir(&type_dir)?.next().is_none() {
+			fs::remove_dir(&type_dir)?;
+		}
+	}
+	Ok(())
+}
+
+/// Load a storage from a directory written by `save_world_dir`.
+///
+/// # Failure
+/// Error with the file name if a file is malformed, if an item is in the
+/// directory of another type or if an id is used twice.
+pub fn load_world_dir<P: AsRef<Path>>(dir: P) -> GameResult<Storage> {
+	let dir = dir.as_ref();
+	let mut storage = Storage::new(read_storage_file(dir)?.0);
+	for path in item_files(dir)? {
+		let item = read_item_file(&path)?;
+		if storage.get_item::<Item>(&item.item_id).is_some() {
+			return Err(berr(format!("{}: Id used twice: {}", path.display(), item.item_id)))
+		}
+		storage.insert(Box::new(item));
+	}
+	Ok(storage)
+}
+
+/// Backend which keeps a world directory on disk.
+///
+/// Only the file names are read when it is opened, the items are read
+/// when they are loaded.  The types are known from the directories and the
+/// rooms are read once when an actor is looked up for the first time.
+pub struct DirBackend {
+	id: String,
+	dir: PathBuf,
+	paths: BTreeMap<String, PathBuf>,
+	files: BTreeSet<String>,
+	actor_rooms: RefCell<Option<BTreeMap<String, String>>>
+}
+
+impl DirBackend {
+	/// Open a world directory written by `save_world_dir`.
+	///
+	/// # Failure
+	/// Error if the directory has no storage file, if a file name is not
+	/// a valid id or if an id is used twice.
+	pub fn open<P: AsRef<Path>>(dir: P) -> GameResult<Self> {
+		let dir = dir.as_ref().to_path_buf();
+		let mut paths = BTreeMap::new();
+		for path in item_files(&dir)? {
+			let id = path.file_stem().and_then(|x| x.to_str()).and_then(id_from_file_name)
+				.ok_or(berr(format!("{}: Invalid file name", path.display())))?;
+			if let Some(other) = paths.insert(id.clone(), path.clone()) {
+				return Err(berr(format!("{}: Id used twice: {} in {}",
+						path.display(), id, other.display())))
+			}
+		}
+		let (id, files) = read_storage_file(&dir)?;
+		Ok(DirBackend {
+			id: id,
+			dir: dir,
+			paths: paths,
+			files: files,
+			actor_rooms: RefCell::new(None)
+		})
+	}
+
+	/// Create an empty world directory.
+	pub fn create<P: AsRef<Path>>(dir: P, id: &str) -> GameResult<Self> {
+		let dir = dir.as_ref().to_path_buf();
+		fs::create_dir_all(&dir)?;
+		write_storage_file(&dir, id, &BTreeSet::new())?;
+		DirBackend::open(dir)
+	}
+}
+
+impl Backend for DirBackend {
+	fn id(&self) -> &str {
+		&self.id
+	}
+	fn ids(&self) -> GameResult<Vec<String>> {
+		Ok(self.paths.keys().cloned().collect())
+	}
+	fn load(&self, item_id: &str) -> GameResult<Option<Item>> {
+		let path = match self.paths.get(item_id) {
+			Some(path) => path,
+			None => return Ok(None)
+		};
+		let item = read_item_file(path)?;
+		if item.item_id != item_id {
+			return Err(berr(format!("{}: Expected item {} but found {}",
+					path.display(), item_id, item.item_id)))
+		}
+		Ok(Some(item))
+	}
+	fn store(&mut self, item: &Item) -> GameResult<()> {
+		let path = item_path(&self.dir, item);
+		if self.paths.get(&item.item_id).map(|old| *old != path).unwrap_or(false) {
+			self.remove(&item.item_id)?;
+		}
+		fs::create_dir_all(path.parent().unwrap())?;
+		write_file(&path, &item_to_json(item))?;
+		self.paths.insert(item.item_id.clone(), path);
+		if let Some(ref mut actor_rooms) = *self.actor_rooms.borrow_mut() {
+			actor_rooms.retain(|_, room_id| *room_id != item.item_id);
+			if item.item_type == "room" {
+				for actor_id in item.meta_textvec_or_default("actors", &[]) {
+					actor_rooms.insert(actor_id.clone(), item.item_id.clone());
+				}
+			}
+		}
+		if self.files.insert(relative_item_path(item)) {
+			write_storage_file(&self.dir, &self.id, &self.files)?;
+		}
+		Ok(())
+	}
+	fn remove(&mut self, item_id: &str) -> GameResult<()> {
+		if let Some(ref mut actor_rooms) = *self.actor_rooms.borrow_mut() {
+			actor_rooms.retain(|_, room_id| room_id != item_id);
+		}
+		if let Some(path) = self.paths.remove(item_id) {
+			fs::remove_file(&path)?;
+			let relative = path.strip_prefix(&self.dir).ok()
+				.and_then(|x| x.to_str()).map(|x| x.replace('\\', "/"));
+			if relative.map(|x| self.files.remove(&x)).unwrap_or(false) {
+				write_storage_file(&self.dir, &self.id, &self.files)?;
+			}
+		}
+		Ok(())
+	}
+	fn ids_of_type(&self, item_type: &str) -> GameResult<Vec<String>> {
+		let type_dir = self.dir.join(file_name(item_type));
+		Ok(self.paths.iter()
+			.filter(|&(_, path)| path.parent() == Some(&type_dir))
+			.map(|(id, _)| id.clone())
+			.collect())
+	}
+	fn room_of_actor(&self, actor_id: &str) -> GameResult<Option<String>> {
+		if self.actor_rooms.borrow().is_none() {
+			let mut actor_rooms = BTreeMap::new();
+			for room_id in self.ids_of_type("room")? {
+				if let Some(room) = self.load(&room_id)? {
+					for actor_id in room.meta_textvec_or_default("actors", &[]) {
+						actor_rooms.insert(actor_id.clone(), room_id.clone());
+					}
+				}
+			}
+			*self.actor_rooms.borrow_mut() = Some(actor_rooms);
+		}
+		Ok(self.actor_rooms.borrow().as_ref().and_then(|x| x.get(actor_id).cloned()))
+	}
+}
+
+#[test]
+fn worlddir_test() {
+	use std::env;
+	use room::Room;
+	let dir = env::temp_dir().join("rustesge-worlddir-test");
+	let _ = fs::remove_dir_all(&dir);
+	let mut hall = Room::new("hall").with_name("Hall");
+	hall.exits.insert("north".to_string(), "kitchen".to_string());
+	hall.exits.insert("east".to_string(), "tower".to_string());
+	let mut odd = Item::new("thing".to_string(), "odd/id .x".to_string());
+	odd.item_meta.insert("data".to_string(), Meta::Binary(vec![0, 255]));
+	odd.item_meta.insert("count".to_string(), Meta::Int(-3));
+	odd.item_meta.insert("tags".to_string(), Meta::TextVec(vec![]));
+	let storage = Storage::new("castle")
+		.with_item(hall)
+		.with_item(Room::new("kitchen"))
+		.with_item(Room::new("Kitchen"))
+		.with_item(odd);
+	fs::create_dir_all(dir.join(".git")).unwrap();
+	fs::create_dir_all(dir.join("empty")).unwrap();
+	fs::File::create(dir.join(".git").join("config.json")).unwrap();
+	fs::File::create(dir.join("notes.json")).unwrap();
+	save_world_dir(&storage, &dir).unwrap();
+	assert!(dir.join("room").join("%4Bitchen.json").is_file());
+	assert!(dir.join("room").join("hall.json").is_file());
+	assert!(dir.join("thing").join("odd%2Fid%20%2Ex.json").is_file());
+	let hall_file = read_file(&dir.join("room").join("hall.json")).unwrap();
+	assert!(hall_file.starts_with("{\n  \"id\": \"hall\",\n  \"meta\": {\n    \"actors\": [],"));
+	assert!(hall_file.contains(
+		"\"exits\": {\n      \"east\": \"tower\",\n      \"north\": \"kitchen\"\n    },"));
+	fs::copy(dir.join("room").join("hall.json"), dir.join("room").join(".hall.json")).unwrap();
+	fs::copy(dir.join("room").join("hall.json"), dir.join("room").join("Hall.json")).unwrap();
+	let mut cellar = Item::new("room".to_string(), "cellar".to_string());
+	cellar.item_meta.insert("exits".to_string(), Meta::Text("{}".to_string()));
+	write_file(&dir.join("room").join("cellar.json"), &item_to_json(&cellar)).unwrap();
+
+	let loaded = load_world_dir(&dir).unwrap();
+	assert_eq!("castle", loaded.id());
+	for item in storage.all_of_type::<Item>() {
+		let loaded_item = loaded.get_item::<Item>(&item.item_id).unwrap();
+		assert_eq!(item_to_json(&item), item_to_json(&loaded_item));
+	}
+	assert_eq!(storage.all_of_type::<Item>().count() + 1, loaded.all_of_type::<Item>().count());
+	assert_eq!(Some("{}".to_string()), loaded.get_item::<Item>("cellar")
+		.and_then(|x| x.item_meta.get("exits").cloned())
+		.and_then(|x| if let Meta::Text(text) = x { Some(text) } else { None }));
+
+	let smaller = Storage::new("castle").with_item(Room::new("kitchen"));
+	save_world_dir(&smaller, &dir).unwrap();
+	assert!(!dir.join("room").join("hall.json").exists());
+	assert!(!dir.join("thing").exists());
+	assert!(dir.join(".git").join("config.json").is_file());
+	assert!(dir.join("notes.json").is_file());
+	assert!(dir.join("empty").is_dir());
+	assert!(dir.join("room").join(".hall.json").is_file());
+	assert!(dir.join("room").join("Hall.json").is_file());
+	assert!(dir.join("room").join("cellar.json").is_file());
+	assert_eq!(2, load_world_dir(&dir).unwrap().all_of_type::<Item>().count());
+	let mut backend = DirBackend::open(&dir).unwrap();
+	assert_eq!(vec!["cellar".to_string(), "kitchen".to_string()], backend.ids().unwrap());
+	backend.remove("kitchen").unwrap();
+	assert_eq!(Vec::<String>::new(), read_storage_file(&dir).unwrap().1.into_iter()
+		.collect::<Vec<String>>());
+	backend.store(&Item::new("room".to_string(), "attic".to_string())).unwrap();
+	assert_eq!(vec!["room/attic.json".to_string()], read_storage_file(&dir).unwrap().1
+		.into_iter().collect::<Vec<String>>());
+	assert!(fs::read_dir(dir.join("room")).unwrap()
+		.all(|x| !x.unwrap().path().to_string_lossy().ends_with(".tmp")));
+
+	assert!(item_from_json("{\"id\": \"a\", \"type\": \"room\", \"meta\": {\"x\": 1.5}}").is_err());
+}
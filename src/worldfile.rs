@@ -0,0 +1,652 @@
+#![warn(missing_docs)]
+
+//! A world format which is written by hand.
+//!
+//! The format is a small subset of TOML.  The file starts with the id of
+//! the storage and the player, followed by one table per item named
+//! `[<item type>.<item id>]` which holds the metas of the item:
+//!
+//! ```text
+//! id = "castle"
+//! player = "hero"
+//!
+//! [room.hall]
+//! name = "Hall"
+//! desc = """
+//! A big hall."""
+//! actors = ["hero"]
+//! exits = { north = "kitchen" }
+//!
+//! [actor.hero]
+//! name = "Hero"
+//! ```
+//!
+//! Strings become text metas, numbers become integers, arrays of strings
+//! become text vectors and byte arrays like `bytes[0, 255]` become binary
+//! metas.  Inline tables of strings are only allowed for the exits, exit
+//! states and exit keys of rooms and the attributes of actors.  Keys and
+//! ids which are not made of letters, digits, `_` and `-` are quoted.
+//! Comments start with `#`.
+//!
+//! Loading checks that exits lead to rooms, that the actors and items in
+//! rooms and inventories exist and that the player is an actor.  Errors
+//! name the line.
+
+use core::{Storage, Item, Meta, Itemizeable, GameResult, berr, serialize_hashmap};
+use room::Room;
+use actor::Actor;
+use base::BaseGame;
+use util::write_atomic;
+use rustc_serialize::json;
+use std::collections::{HashMap, BTreeMap};
+use std::char;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// Storage id used if the file declares none.
+pub const DEFAULT_ID: &'static str = "world";
+
+/// Item types and keys of the metas which are tables.
+const TABLES: &'static [(&'static str, &'static str)] = &[("room", "exits"),
+		("room", "exit_states"), ("room", "exit_keys"), ("actor", "attributes")];
+
+/// Check if the meta of the item type holds a table of strings as JSON text.
+pub fn is_table(item_type: &str, key: &str) -> bool {
+	TABLES.iter().any(|&(table_type, table_key)| table_type == item_type && table_key == key)
+}
+
+fn is_bare(c: char) -> bool {
+	c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Get the table of strings from the text of a table meta.
+///
+/// Returns None if the text is no JSON object of strings.
+pub fn decode_table(text: &str) -> Option<HashMap<String, String>> {
+	if text.starts_with('{') {
+		json::decode(text).ok()
+	} else {
+		None
+	}
+}
+
+struct Parser {
+	chars: Vec<char>,
+	pos: usize,
+	line: usize
+}
+
+impl Parser {
+	fn new(source: &str) -> Self {
+		Parser {
+			chars: source.chars().collect(),
+			pos: 0,
+			line: 1
+		}
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).cloned()
+	}
+
+	fn looking_at(&self, text: &str) -> bool {
+		text.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+	}
+
+	fn next(&mut self) -> Option<char> {
+		let c = self.peek();
+		if let Some(c) = c {
+			self.pos += 1;
+			if c == '\n' {
+				self.line += 1;
+			}
+		}
+		c
+	}
+
+	fn error<T, S: Into<String>>(&self, msg: S) -> GameResult<T> {
+		Err(berr(format!("Line {}: {}", self.line, msg.into())))
+	}
+
+	fn expect(&mut self, expected: char) -> GameResult<()> {
+		if self.peek() == Some(expected) {
+			self.next();
+			Ok(())
+		} else {
+			self.error(format!("expected '{}'", expected))
+		}
+	}
+
+	fn skip_spaces(&mut self) {
+		while let Some(' ') | Some('\t') | Some('\r') = self.peek() {
+			self.next();
+		}
+	}
+
+	fn skip_comment(&mut self) {
+		if self.peek() == Some('#') {
+			while self.peek().map(|c| c != '\n').unwrap_or(false) {
+				self.next();
+			}
+		}
+	}
+
+	/// Skip spaces, comments and empty lines.
+	fn skip_blank(&mut self) {
+		loop {
+			self.skip_spaces();
+			self.skip_comment();
+			if self.peek() != Some('\n') {
+				break
+			}
+			self.next();
+		}
+	}
+
+	fn end_of_line(&mut self) -> GameResult<()> {
+		self.skip_spaces();
+		self.skip_comment();
+		match self.next() {
+			None | Some('\n') => Ok(()),
+			Some(c) => self.error(format!("unexpected '{}'", c))
+		}
+	}
+
+	fn key(&mut self) -> GameResult<String> {
+		if self.peek() == Some('"') && !self.looking_at("\"\"\"") {
+			return self.string()
+		}
+		let mut key = String::new();
+		while let Some(c) = self.peek() {
+			if !is_bare(c) {
+				break
+			}
+			key.push(c);
+			self.next();
+		}
+		if key.is_empty() {
+			self.error("expected a key")
+		} else {
+			Ok(key)
+		}
+	}
+
+	fn escape(&mut self) -> GameResult<char> {
+		match self.next() {
+			Some('n') => Ok('\n'),
+			Some('t') => Ok('\t'),
+			Some('r') => Ok('\r'),
+			Some('"') => Ok('"'),
+			Some('\\') => Ok('\\'),
+			Some('u') => {
+				let hex: String = (0..4).filter_map(|_| self.next()).collect();
+				match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+					Some(c) => Ok(c),
+					None => self.error(format!("invalid unicode escape: {}", hex))
+				}
+			},
+			_ => self.error("invalid escape sequence")
+		}
+	}
+
+	/// Parse a string in quotes or a multi-line string in triple quotes.
+	///
+	/// A line break right after the opening triple quotes is ignored.
+	fn string(&mut self) -> GameResult<String> {
+		let start_line = self.line;
+		let multiline = self.looking_at("\"\"\"");
+		if multiline {
+			self.pos += 3;
+			if self.peek() == Some('\n') {
+				self.next();
+			}
+		} else {
+			self.next();
+		}
+		let mut res = String::new();
+		loop {
+			if multiline && self.looking_at("\"\"\"") {
+				self.pos += 3;
+				return Ok(res)
+			}
+			match self.next() {
+				Some('"') if !multiline => return Ok(res),
+				Some('\\') => res.push(self.escape()?),
+				Some('\n') if !multiline => break,
+				Some(c) => res.push(c),
+				None => break
+			}
+		}
+		Err(berr(format!("Line {}: unterminated string", start_line)))
+	}
+
+	fn int(&mut self) -> GameResult<i32> {
+		let mut text = String::new();
+		if self.peek() == Some('-') {
+			text.push('-');
+			self.next();
+		}
+		while let Some(c) = self.peek() {
+			if !c.is_digit(10) {
+				break
+			}
+			text.push(c);
+			self.next();
+		}
+		match text.parse::<i32>() {
+			Ok(int) => Ok(int),
+			Err(_) => self.error(format!("invalid number: {}", text))
+		}
+	}
+
+	/// Parse the separator after an element of an array or inline table.
+	fn separator(&mut self, close: char) -> GameResult<()> {
+		self.skip_blank();
+		match self.peek() {
+			Some(',') => {
+				self.next();
+				Ok(())
+			},
+			Some(c) if c == close => Ok(()),
+			_ => self.error(format!("expected ',' or '{}'", close))
+		}
+	}
+
+	fn array(&mut self) -> GameResult<Meta> {
+		self.next();
+		let mut texts = Vec::new();
+		loop {
+			self.skip_blank();
+			match self.peek() {
+				Some(']') => {
+					self.next();
+					break
+				},
+				Some('"') => texts.push(self.string()?),
+				_ => return self.error("expected a string")
+			}
+			self.separator(']')?;
+		}
+		Ok(Meta::TextVec(texts))
+	}
+
+	/// Parse a byte array like `bytes[0, 255]`.
+	fn bytes(&mut self) -> GameResult<Meta> {
+		self.pos += "bytes".len();
+		self.expect('[')?;
+		let mut bytes = Vec::new();
+		loop {
+			self.skip_blank();
+			match self.peek() {
+				Some(']') => {
+					self.next();
+					break
+				},
+				Some(c) if c == '-' || c.is_digit(10) => {
+					let int = self.int()?;
+					if int < 0 || int > 255 {
+						return self.error(format!("byte out of range: {}", int))
+					}
+					bytes.push(int as u8);
+				},
+				_ => return self.error("expected a byte")
+			}
+			self.separator(']')?;
+		}
+		Ok(Meta::Binary(bytes))
+	}
+
+	fn table(&mut self) -> GameResult<Meta> {
+		self.next();
+		let mut map = HashMap::new();
+		loop {
+			self.skip_blank();
+			if self.peek() == Some('}') {
+				self.next();
+				break
+			}
+			let key = self.key()?;
+			self.skip_spaces();
+			self.expect('=')?;
+			self.skip_spaces();
+			if self.peek() != Some('"') {
+				return self.error("expected a string")
+			}
+			let value = self.string()?;
+			if map.insert(key.clone(), value).is_some() {
+				return self.error(format!("duplicate key: {}", key))
+			}
+			self.separator('}')?;
+		}
+		Ok(Meta::Text(serialize_hashmap(&map)))
+	}
+
+	fn value(&mut self) -> GameResult<Meta> {
+		match self.peek() {
+			Some('"') => Ok(Meta::Text(self.string()?)),
+			Some('[') => self.array(),
+			Some('{') => self.table(),
+			Some('b') if self.looking_at("bytes[") => self.bytes(),
+			Some(c) if c == '-' || c.is_digit(10) => Ok(Meta::Int(self.int()?)),
+			_ => self.error("expected a value")
+		}
+	}
+
+	/// Parse `key = value` up to the end of the line.
+	///
+	/// Also tells whether the value is an inline table.
+	fn assignment(&mut self) -> GameResult<(String, Meta, bool)> {
+		let key = self.key()?;
+		self.skip_spaces();
+		self.expect('=')?;
+		self.skip_spaces();
+		let table = self.peek() == Some('{');
+		let value = self.value()?;
+		self.end_of_line()?;
+		Ok((key, value, table))
+	}
+
+	/// Parse `[type.id]` up to the end of the line.
+	fn header(&mut self) -> GameResult<(String, String)> {
+		self.next();
+		self.skip_spaces();
+		let item_type = self.key()?;
+		self.skip_spaces();
+		self.expect('.')?;
+		self.skip_spaces();
+		let item_id = self.key()?;
+		self.skip_spaces();
+		self.expect(']')?;
+		self.end_of_line()?;
+		Ok((item_type, item_id))
+	}
+}
+
+/// Lines of the declarations, the header of an item has an empty key.
+type Lines = HashMap<(String, String), usize>;
+
+fn line_of(lines: &Lines, item_id: &str, key: &str) -> usize {
+	lines.get(&(item_id.to_string(), key.to_string()))
+		.or(lines.get(&(item_id.to_string(), String::new())))
+		.cloned()
+		.unwrap_or(0)
+}
+
+/// Find references to missing items and return the first one.
+fn check_references(storage: &Storage, lines: &Lines,
+					player: Option<(&str, usize)>) -> GameResult<()> {
+	let mut problems = Vec::new();
+	for room in storage.all_of_type::<Room>() {
+		for (exit, target) in room.exits.iter() {
+			if storage.get_item::<Room>(target).is_none() {
+				problems.push((line_of(lines, &room.id, "exits"),
+						format!("exit {} of {} leads to unknown room: {}", exit, room.id, target)));
+			}
+		}
+		for actor in room.actors.iter() {
+			if storage.get_item::<Actor>(actor).is_none() {
+				problems.push((line_of(lines, &room.id, "actors"),
+						format!("unknown actor in {}: {}", room.id, actor)));
+			}
+		}
+		for item in room.items.iter() {
+			if storage.get_item::<Item>(item).is_none() {
+				problems.push((line_of(lines, &room.id, "items"),
+						format!("unknown item in {}: {}", room.id, item)));
+			}
+		}
+	}
+	for actor in storage.all_of_type::<Actor>() {
+		for item in actor.items.iter() {
+			if storage.get_item::<Item>(item).is_none() {
+				problems.push((line_of(lines, &actor.id, "items"),
+						format!("unknown item of {}: {}", actor.id, item)));
+			}
+		}
+	}
+	if let Some((player, line)) = player {
+		if storage.get_item::<Actor>(player).is_none() {
+			problems.push((line, format!("player is not an actor: {}", player)));
+		}
+	}
+	problems.sort();
+	match problems.into_iter().next() {
+		Some((line, problem)) => Err(berr(format!("Line {}: {}", line, problem))),
+		None => Ok(())
+	}
+}
+
+/// Create a storage from a world file.
+///
+/// # Failure
+/// Error with the line number if the file is malformed, if an id is
+/// declared twice or if a reference leads to a missing item.
+pub fn parse_world(source: &str) -> GameResult<Storage> {
+	let mut parser = Parser::new(source);
+	let mut id = DEFAULT_ID.to_string();
+	let mut player: Option<(String, usize)> = None;
+	let mut items: Vec<Item> = Vec::new();
+	let mut lines = Lines::new();
+	loop {
+		parser.skip_blank();
+		let line = parser.line;
+		if parser.peek().is_none() {
+			break
+		}
+		if parser.peek() == Some('[') {
+			let (item_type, item_id) = parser.header()?;
+			if lines.insert((item_id.clone(), String::new()), line).is_some() {
+				return Err(berr(format!("Line {}: id declared twice: {}", line, item_id)))
+			}
+			items.push(if item_type == "room" {
+				Room::new(item_id).to_item()
+			} else {
+				Item::new(item_type, item_id)
+			});
+			continue
+		}
+		let (key, value, table) = parser.assignment()?;
+		let item = match items.last_mut() {
+			Some(item) => item,
+			None => {
+				match (key.as_str(), value) {
+					("id", Meta::Text(text)) => id = text,
+					("player", Meta::Text(text)) => player = Some((text, line)),
+					(key, _) => return Err(berr(format!(
+							"Line {}: expected id or player as string: {}", line, key)))
+				}
+				continue
+			}
+		};
+		if lines.insert((item.item_id.clone(), key.clone()), line).is_some() {
+			return Err(berr(format!("Line {}: duplicate key: {}", line, key)))
+		}
+		match (is_table(&item.item_type, &key), table) {
+			(true, false) => return Err(berr(format!(
+					"Line {}: {} must be a table of strings", line, key))),
+			(false, true) => return Err(berr(format!(
+					"Line {}: {} cannot be a table", line, key))),
+			_ => ()
+		}
+		item.item_meta.insert(key, value);
+	}
+	let mut storage = Storage::new(id);
+	for item in items {
+		storage.insert(Box::new(item));
+	}
+	if let Some((ref player, _)) = player {
+		storage.insert(Box::new(BaseGame { player: player.clone() }));
+	}
+	check_references(&storage, &lines,
+			player.as_ref().map(|&(ref player, line)| (player.as_str(), line)))?;
+	Ok(storage)
+}
+
+/// Load a storage from a world file.
+pub fn load_world_file<P: AsRef<Path>>(path: P) -> GameResult<Storage> {
+	let path = path.as_ref();
+	let mut source = String::new();
+	fs::File::open(path)?.read_to_string(&mut source)?;
+	match parse_world(&source) {
+		Ok(storage) => Ok(storage),
+		Err(err) => Err(berr(format!("{}: {}", path.display(), err)))
+	}
+}
+
+fn escape(text: &str, multiline: bool) -> String {
+	let mut res = String::new();
+	for c in text.chars() {
+		match c {
+			'"' => res.push_str("\\\""),
+			'\\' => res.push_str("\\\\"),
+			'\n' if multiline => res.push('\n'),
+			'\n' => res.push_str("\\n"),
+			'\t' => res.push_str("\\t"),
+			'\r' => res.push_str("\\r"),
+			c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+			c => res.push(c)
+		}
+	}
+	res
+}
+
+fn format_string(text: &str) -> String {
+	if text.contains('\n') {
+		format!("\"\"\"\n{}\"\"\"", escape(text, true))
+	} else {
+		format!("\"{}\"", escape(text, false))
+	}
+}
+
+fn format_key(key: &str) -> String {
+	if !key.is_empty() && key.chars().all(is_bare) {
+		key.to_string()
+	} else {
+		format!("\"{}\"", escape(key, false))
+	}
+}
+
+/// Format the meta, text metas which are tables become inline tables.
+fn format_meta(meta: &Meta, table: bool) -> String {
+	match *meta {
+		Meta::Text(ref text) => match decode_table(text).filter(|_| table) {
+			Some(ref table) if table.is_empty() => "{}".to_string(),
+			Some(table) => {
+				let table: BTreeMap<String, String> = table.into_iter().collect();
+				let entries: Vec<String> = table.iter()
+					.map(|(key, value)| format!("{} = {}", format_key(key),
+							format!("\"{}\"", escape(value, false))))
+					.collect();
+				format!("{{ {} }}", entries.join(", "))
+			},
+			None => format_string(text)
+		},
+		Meta::TextVec(ref texts) => {
+			let texts: Vec<String> = texts.iter()
+				.map(|text| format!("\"{}\"", escape(text, false)))
+				.collect();
+			format!("[{}]", texts.join(", "))
+		},
+		Meta::Binary(ref bytes) => {
+			let bytes: Vec<String> = bytes.iter().map(|byte| byte.to_string()).collect();
+			format!("bytes[{}]", bytes.join(", "))
+		},
+		Meta::Int(int) => int.to_string()
+	}
+}
+
+/// Position of a meta key in the output, name and description come first.
+fn key_rank(key: &str) -> usize {
+	match key {
+		"name" => 0,
+		"desc" => 1,
+		_ => 2
+	}
+}
+
+/// Write the storage in the world file format.
+///
+/// Items are sorted by type and id.  The player of the base game is written
+/// at the top of the file.
+pub fn export_world(storage: &Storage) -> String {
+	let mut res = format!("id = {}\n", format_string(storage.id()));
+	let mut items: Vec<Box<Item>> = storage.all_of_type::<Item>().collect();
+	items.sort_by(|a, b| (&a.item_type, &a.item_id).cmp(&(&b.item_type, &b.item_id)));
+	for item in items {
+		if item.item_type == "base_game" && item.item_id == "base_game"
+				&& item.item_meta.len() == 1 {
+			if let Some(&Meta::Text(ref player)) = item.item_meta.get("player") {
+				res.push_str(&format!("player = {}\n", format_string(player)));
+				continue
+			}
+		}
+		res.push_str(&format!("\n[{}.{}]\n", format_key(&item.item_type),
+				format_key(&item.item_id)));
+		let mut keys: Vec<&String> = item.item_meta.keys().collect();
+		keys.sort_by(|a, b| (key_rank(a), a).cmp(&(key_rank(b), b)));
+		for key in keys {
+			res.push_str(&format!("{} = {}\n", format_key(key),
+					format_meta(&item.item_meta[key], is_table(&item.item_type, key))));
+		}
+	}
+	res
+}
+
+/// Write the storage to a world file, see `write_atomic`.
+pub fn save_world_file<P: AsRef<Path>>(storage: &Storage, path: P) -> GameResult<()> {
+	write_atomic(path, &export_world(storage))
+}
+
+#[test]
+fn worldfile_test() {
+	use std::env;
+	let storage = parse_world(include_str!("../examples/castle.world")).unwrap();
+	assert_eq!("castle", storage.id());
+	let hall = storage.get_item::<Room>("hall").unwrap();
+	assert_eq!("A big hall with a long table.\nDoors lead north and east.", hall.description);
+	assert_eq!(Some(&"kitchen".to_string()), hall.exits.get("north"));
+	assert_eq!(Some(&"key".to_string()), hall.exit_keys.get("east"));
+	assert_eq!(vec!["hero".to_string(), "guard".to_string()], hall.actors);
+	assert_eq!("hero", storage.get_item::<BaseGame>("base_game").unwrap().player);
+	assert_eq!(Some(20), storage.get_item::<Actor>("hero").unwrap().attribute("health"));
+	assert!(storage.get_item::<Room>("tower").unwrap().exits.contains_key("west"));
+
+	let exported = export_world(&storage);
+	let reloaded = parse_world(&exported).unwrap();
+	assert_eq!(exported, export_world(&reloaded));
+
+	let mut item = Item::new("thing".to_string(), "odd id".to_string());
+	item.item_meta.insert("on use".to_string(), Meta::Text("say \"hi\"\\".to_string()));
+	item.item_meta.insert("data".to_string(), Meta::Binary(vec![0, 255]));
+	item.item_meta.insert("empty".to_string(), Meta::Binary(vec![]));
+	item.item_meta.insert("json".to_string(), Meta::Text("{\"a\": \"b\"}".to_string()));
+	item.item_meta.insert("count".to_string(), Meta::Int(-3));
+	item.item_meta.insert("tags".to_string(), Meta::TextVec(vec![]));
+	let exported = export_world(&Storage::new("odd").with_item(item));
+	assert!(exported.contains("data = bytes[0, 255]\nempty = bytes[]\n"));
+	let odd = parse_world(&exported).unwrap();
+	let item = odd.get_item::<Item>("odd id").unwrap();
+	assert_eq!(Some(&"say \"hi\"\\".to_string()), item.item_meta["on use"].text_ref());
+	assert_eq!(Some(&vec![0, 255]), item.item_meta["data"].binary_ref());
+	assert_eq!(Some(&vec![]), item.item_meta["empty"].binary_ref());
+	assert_eq!(Some(&"{\"a\": \"b\"}".to_string()), item.item_meta["json"].text_ref());
+	assert_eq!(Some(-3), item.item_meta["count"].int());
+	assert_eq!(Some(&vec![]), item.item_meta["tags"].textvec_ref());
+
+	let error = |source: &str| parse_world(source).err().unwrap().to_string();
+	assert_eq!("Line 3: expected '='", error("id = \"a\"\n[room.a]\nname \"A\""));
+	assert_eq!("Line 3: unterminated string", error("\n[room.a]\ndesc = \"\"\"\nopen"));
+	assert_eq!("Line 3: id declared twice: a", error("[room.a]\n\n[thing.a]"));
+	assert_eq!("Line 2: exit north of a leads to unknown room: b",
+			error("[room.a]\nexits = { north = \"b\" }"));
+	assert_eq!("Line 1: player is not an actor: a", error("player = \"a\"\n[room.a]"));
+	assert_eq!("Line 2: exits must be a table of strings", error("[room.a]\nexits = \"b\""));
+	assert_eq!("Line 2: exits cannot be a table", error("[thing.a]\nexits = { a = \"b\" }"));
+	assert_eq!("Line 2: expected a string", error("[thing.a]\ndata = [1, 2]"));
+
+	let path = env::temp_dir().join("rustesge-worldfile-test.world");
+	save_world_file(&storage, &path).unwrap();
+	assert_eq!(export_world(&storage), export_world(&load_world_file(&path).unwrap()));
+	fs::remove_file(&path).unwrap();
+}